@@ -0,0 +1,95 @@
+// Benchmarks of the CPU image pipeline, at the resolution of the common 1600x1200 panels.
+// Run with `cargo bench --bench cpu_pipeline`; criterion compares with the previous run.
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use rabbitink::image::*;
+use rabbitink::imgproc::cpu::CpuMonoImgproc;
+use rabbitink::imgproc::{dithering, rotate, simd, DitheringMethod, MonoImgprocOptions, Rotation};
+
+const WIDTH: i32 = 1600;
+const HEIGHT: i32 = 1200;
+
+fn bgra_frame() -> ImageBuffer {
+    let mut img = ImageBuffer::new(ImageFormat::BGRA, WIDTH, HEIGHT, None);
+    let mut seed = 1u32;
+    for v in img.mut_data().iter_mut() {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        *v = (seed >> 16) as u8;
+    }
+    img
+}
+
+fn bench_kernels(c: &mut Criterion) {
+    let frame = bgra_frame();
+    let mut group = c.benchmark_group("kernels");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+
+    let mut gray = vec![0u8; (WIDTH * HEIGHT) as usize];
+    group.bench_function("bgra_to_gray", |b| b.iter(|| simd::bgra_to_gray(black_box(frame.data()), &mut gray)));
+
+    let mut packed = vec![0u8; gray.len() / 8];
+    let thresholds: Vec<u8> = [0, 128, 32, 160].repeat(gray.len() / 4);
+    group.bench_function("pack_above_thresholds", |b| {
+        b.iter(|| simd::pack_above_thresholds(black_box(&gray), &thresholds, &mut packed))
+    });
+    group.bench_function("unpack_bits", |b| b.iter(|| simd::unpack_bits(black_box(&packed), &mut gray)));
+
+    let mono8 = ImageBuffer::new(ImageFormat::Mono8Bpp, WIDTH, HEIGHT, None);
+    group.bench_function("row_hashes", |b| {
+        b.iter(|| (0..HEIGHT).map(|y| simd::row_hash(&mono8.data()[(y * WIDTH) as usize..][..WIDTH as usize])).sum::<u64>())
+    });
+    group.finish();
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let frame = bgra_frame();
+    let size = frame.size();
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    group.sample_size(20);
+
+    for rotation in [Rotation::NoRotation, Rotation::Rotate90] {
+        let output_size = rotation.rotated_size(size);
+        let mut imgproc = CpuMonoImgproc::new(MonoImgprocOptions { input_size: size, output_size, rotation });
+        let mut output = ImageBuffer::new(ImageFormat::Mono1Bpp, output_size.width, output_size.height, None);
+        group.bench_function(format!("mono_imgproc/{}", rotation), |b| {
+            b.iter(|| imgproc.process(black_box(&frame), &mut output, DitheringMethod::Bayers4))
+        });
+        group.bench_function(format!("rotate/{}", rotation), |b| {
+            b.iter(|| rotate::rotate(black_box(&frame), rotation, output_size))
+        });
+    }
+
+    // the edge pass of font recovery, on noise (edges everywhere) and on a flat frame (all rows skipped)
+    let options = MonoImgprocOptions { input_size: size, output_size: size, rotation: Rotation::NoRotation };
+    let mut imgproc = CpuMonoImgproc::new(options);
+    imgproc.set_font_recovery(true);
+    let mut output = ImageBuffer::new(ImageFormat::Mono1Bpp, WIDTH, HEIGHT, None);
+    let mut flat = ImageBuffer::new(ImageFormat::BGRA, WIDTH, HEIGHT, None);
+    flat.fill(0xe0);
+    for (name, input) in [("noise", &frame), ("flat", &flat)] {
+        group.bench_function(format!("mono_imgproc/font_recovery/{}", name), |b| {
+            b.iter(|| imgproc.process(black_box(input), &mut output, DitheringMethod::Bayers4))
+        });
+    }
+
+    group.bench_function("floyd_steinberg", |b| {
+        b.iter(|| dithering::floyd_steinberg(black_box(&frame), dithering::GREY16_TARGET_COLOR_SPACE))
+    });
+    let stucki = dithering::ErrorDiffusion { kernel: dithering::DiffusionKernel::Stucki, serpentine: true };
+    group.bench_function("error_diffusion/stucki_serpentine", |b| {
+        b.iter(|| dithering::error_diffusion(black_box(&frame), dithering::GREY16_TARGET_COLOR_SPACE, stucki))
+    });
+    let gray = dithering::floyd_steinberg(&frame, dithering::GREY16_TARGET_COLOR_SPACE);
+    let packed = convert::repack_mono(&gray, ImageFormat::Mono1Bpp, WIDTH / 8);
+    group.bench_function("repack_mono/8to1", |b| {
+        b.iter(|| convert::repack_mono(black_box(&gray), ImageFormat::Mono1Bpp, WIDTH / 8))
+    });
+    group.bench_function("repack_mono/1to8", |b| {
+        b.iter(|| convert::repack_mono(black_box(&packed), ImageFormat::Mono8Bpp, WIDTH))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_kernels, bench_pipeline);
+criterion_main!(benches);
@@ -1,28 +1,42 @@
-use log::{debug, info};
-use std::cell::RefCell;
-use std::hash::Hasher;
-use std::rc::Rc;
+use log::{debug, info, warn};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use crate::imgproc::dithering;
+use crate::config::{ConfigWatcher, Settings};
+use crate::control::{ClientId, Command, ControlServer};
 
 use super::driver::it8915::{DisplayMode, MemMode, IT8915};
 use super::image::*;
-use super::imgproc::{rotate::rotate as rotate_image, MonoImgproc, MonoImgprocOptions, Rotation};
+use super::imgproc::stroke::Embolden;
+use super::imgproc::{simd, ImgprocBackend, Rotation};
 use super::run_mode::RunMode;
-use super::source::Source;
+use super::source::SourceFactory;
 
-type RowSet = std::collections::BTreeSet<i32>;
+pub mod change;
+pub mod content;
+pub mod ghosting;
+pub mod policy;
+pub mod animation;
+pub mod capture;
+pub mod mask;
+pub mod input;
+pub mod latency;
+use animation::AnimationTracker;
+use capture::{CaptureSettings, CaptureThread, CapturedFrame};
+use input::InputTracker;
+use latency::LatencyModel;
+use mask::{apply_masks, quality_areas, Mask, MaskOptions};
+use change::{analyze_change, ChangeKind};
+use content::{classify_rows, ContentClass};
+use ghosting::GhostingTracker;
+use policy::{create_policy, Decision, DefaultPolicy, DisplayRequest, PolicyContext, PolicyOptions,
+             RefreshPolicy, UpdateHistory, UpdateRecord};
+
+pub type RowSet = std::collections::BTreeSet<i32>;
 
 fn compute_row_hashes(m: &impl ConstImage) -> Vec<u64> {
     (0..m.height())
-        .map(|y| {
-            let ptr = m.ptr(y);
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            hasher.write(unsafe {std::slice::from_raw_parts(ptr, m.pitch() as usize)});
-            hasher.finish()
-        })
+        .map(|y| simd::row_hash(unsafe { std::slice::from_raw_parts(m.ptr(y), m.pitch() as usize) }))
         .collect()
 }
 
@@ -37,66 +51,162 @@ fn compute_modified_rows(row_hashes_a: &[u64], row_hashes_b: &[u64]) -> RowSet {
 pub struct AppOptions {
     pub reload_flag: Arc<AtomicBool>,
     pub terminate_flag: Arc<AtomicBool>,
-    pub get_run_mode_callback: Box<dyn Fn() -> RunMode>,
+    pub run_mode: RunMode,
+    // return the new run mode, if requested (via the legacy run mode config file)
+    pub get_run_mode_callback: Box<dyn FnMut() -> Option<RunMode>>,
 
     pub driver_poll_ready_interval: std::time::Duration,
     pub source_poll_interval: std::time::Duration,
 
     pub rotation: Rotation,
+
+    pub control_server: Option<ControlServer>,
+    pub config_watcher: Option<ConfigWatcher>,
+
+    pub policy: PolicyOptions,
+    pub imgproc_backend: ImgprocBackend,
+    // where the measured latency is persisted, None to not persist
+    pub latency_model_path: Option<std::path::PathBuf>,
 }
 
+// the temperature changes slowly, no need to read it for each update
+const TEMPERATURE_READ_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// save the latency model after this many new measurements (and on exit)
+const LATENCY_MODEL_SAVE_SAMPLES: u32 = 100;
+
 pub struct App {
     driver: IT8915,
-    source: Box<dyn Source>,
+    capture: CaptureThread,
     options: AppOptions,
     current_run_mode: RunMode,
-    mono_imgproc: Rc<RefCell<MonoImgproc>>,
+    control_server: Option<ControlServer>,
+    refresh_policy: Box<dyn RefreshPolicy>,
+    history: UpdateHistory,
+    ghosting: GhostingTracker,
+    animation: AnimationTracker,
+    input: InputTracker,
+    latency_model: LatencyModel,
+    temperature: Option<(u8, std::time::Instant)>, // last read temperature and the read time
+    display_started: Vec<(DisplayMode, i32, std::time::Instant)>, // mode, height and start time of displaying areas
+    t_last_busy: Option<std::time::Instant>, // last time the controller is seen busy with the displaying areas
+    masks: Vec<Mask>,
+    runtime_mask_options: Vec<MaskOptions>, // masks added via control socket
+
+    paused: bool,
+    run_mode_switched: bool, // the run mode is switched and the new frame is not displayed yet
+    full_refresh_requested: bool,
+    area_refresh_requested: Option<Rect>, // in screen coordinate
+    last_latency: Option<std::time::Duration>,
 
     loaded_frame_row_hashes: Vec<u64>,  // hash of each row's pixel value of loaded frame
     dirty_rows: RowSet,
     displaying_rows: RowSet,
-    full_refreshed: bool,
+    loaded_frame: Option<ImageBuffer>, // the frame loaded into the controller
+    displayed_frame: Option<ImageBuffer>, // the frame displayed on the screen (as far as we know)
+    displayed_frame_row_hashes: Vec<u64>,
+    fast_loaded_rows: RowSet,           // rows with the thresholded content in the controller, see `progressive_gray`
+    quality_pending_rows: RowSet,       // rows displayed with fast modes, waiting for a quality pass
 }
 
-const FULL_REFRESH_IDLE_DELAY: std::time::Duration = std::time::Duration::from_secs(120);
-const FULL_REFRESH_MIN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3); // prevent duplicated full refresh request within this period, if nothing is changed
-const TEXT_ROW_TYPICAL_HEIGHT: i32 = 40; // when considering "row ratio" below, "expand" each pixel row to this height,
-                                         // so that the "row ratio" is more close to what we assume
-const SLOW_REFRESH_ROW_RATIO_THRESHOLD: f32 = 0.5; // do a slow (e.g. DU instead of A2) refresh if more than this ratio of rows are changed
-
 impl App {
-    pub fn new(driver: IT8915, source: Box<dyn Source>, options: AppOptions) -> App {
-        let current_run_mode = (options.get_run_mode_callback)();
-        let mono_imgproc = MonoImgproc::new(MonoImgprocOptions {
-            rotation: options.rotation,
-            input_size: source.frame_size(),
-            output_size: driver.get_screen_size(),
+    pub fn new(driver: IT8915, source_factory: SourceFactory, mut options: AppOptions) -> anyhow::Result<App> {
+        let control_server = options.control_server.take();
+        Self::check_policy_options(&driver, &mut options.policy);
+        let current_run_mode = options.run_mode;
+        let capture = CaptureThread::spawn(
+            source_factory,
+            CaptureSettings {
+                run_mode: current_run_mode,
+                rotation: options.rotation,
+                naive_threshold: options.policy.naive_dithering_threshold,
+                font_recovery: options.policy.font_recovery,
+                embolden: Embolden { direction: options.policy.embolden, radius: options.policy.embolden_radius },
+                hysteresis: options.policy.stabilize_hysteresis,
+            },
+            options.source_poll_interval,
+            driver.get_screen_size(),
+            driver.get_mem_pitch(MemMode::Mem1bpp),
+            options.imgproc_backend,
+        )?;
+        let refresh_policy = create_policy(&options.policy.refresh_policy).unwrap_or_else(|e| {
+            warn!("{}, using default", e);
+            Box::new(DefaultPolicy)
         });
-        App {
+        let ghosting = GhostingTracker::new(options.policy.ghosting_tile_size, driver.get_screen_size());
+        let screen_height = driver.get_screen_size().height;
+        let latency_model = options.latency_model_path.as_deref().map(LatencyModel::load).unwrap_or_default();
+        let mut app = App {
             driver,
-            source,
+            capture,
             options,
             current_run_mode,
-            mono_imgproc: Rc::new(RefCell::new(mono_imgproc)),
+            control_server,
+            refresh_policy,
+            history: UpdateHistory::new(std::time::Instant::now()),
+            ghosting,
+            animation: AnimationTracker::new(screen_height),
+            input: InputTracker::default(),
+            latency_model,
+            temperature: None,
+            display_started: Vec::new(),
+            t_last_busy: None,
+            masks: Vec::new(),
+            runtime_mask_options: Vec::new(),
+            paused: false,
+            run_mode_switched: false,
+            full_refresh_requested: false,
+            area_refresh_requested: None,
+            last_latency: None,
             loaded_frame_row_hashes: Vec::new(),
             dirty_rows: RowSet::default(),
             displaying_rows: RowSet::default(),
-            full_refreshed: false,
-        }
+            loaded_frame: None,
+            displayed_frame: None,
+            displayed_frame_row_hashes: Vec::new(),
+            fast_loaded_rows: RowSet::default(),
+            quality_pending_rows: RowSet::default(),
+        };
+        app.rebuild_masks();
+        Ok(app)
     }
 
-    fn load_frame_generic<ImgprocFn>(&mut self, imgproc_fn: ImgprocFn) -> anyhow::Result<()>
-    where ImgprocFn: FnOnce(&dyn ConstImage) -> ImageBuffer,
-    {
+    fn rebuild_masks(&mut self) {
         let screen_size = self.driver.get_screen_size();
-        let t_load_start = std::time::Instant::now();
+        let rotation = self.options.rotation;
+        self.masks = self
+            .options
+            .policy
+            .masks
+            .iter()
+            .chain(self.runtime_mask_options.iter())
+            .map(|x| Mask::new(x.clone(), rotation, screen_size))
+            .collect();
+    }
 
-        let bgra_img = self.source.get_frame()?;
-        let t_got_frame = std::time::Instant::now();
+    fn capture_settings(&self) -> CaptureSettings {
+        CaptureSettings {
+            run_mode: self.current_run_mode,
+            rotation: self.options.rotation,
+            naive_threshold: self.options.policy.naive_dithering_threshold,
+            font_recovery: self.options.policy.font_recovery,
+            embolden: Embolden { direction: self.options.policy.embolden, radius: self.options.policy.embolden_radius },
+            hysteresis: self.options.policy.stabilize_hysteresis,
+        }
+    }
 
-        let new_frame = imgproc_fn(bgra_img.as_ref());
-        let t_imgproc = std::time::Instant::now();
+    // take the newest processed frame from the capture thread (if any) and load the modified rows into driver,
+    // modify dirty_rows
+    fn load_frame(&mut self) -> anyhow::Result<()> {
+        let screen_size = self.driver.get_screen_size();
+        let captured = match self.take_frame()? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let t_load_start = std::time::Instant::now();
+        self.input.update_pointer(captured.pointer, captured.t_got_frame);
 
+        let mut new_frame = captured.frame;
+        apply_masks(&mut self.masks, &mut new_frame, self.loaded_frame.as_ref(), t_load_start);
         let new_frame_row_hashes = compute_row_hashes(&new_frame);
         let mut modified_range = if self.loaded_frame_row_hashes.is_empty() {
             RowSet::from_iter(0..screen_size.height)
@@ -110,202 +220,633 @@ impl App {
                  *modified_range.last().unwrap() - modified_range.first().unwrap() + 1).into(),
             );
             let load_offset = *modified_range.first().unwrap() as u32;
-            match self.current_run_mode.mem_mode() {
-                MemMode::Mem1bpp => {
-                    if load_subimg.format() == ImageFormat::Mono1Bpp {
-                        self.driver.load_image_fullwidth_1bpp(load_offset, &load_subimg)?;
-                    } else {
-                        let packed = convert::repack_mono(&load_subimg, ImageFormat::Mono1Bpp,
-                                                          self.driver.get_mem_pitch(MemMode::Mem1bpp));
-                        self.driver.load_image_fullwidth_1bpp(load_offset, &packed)?;
-                    }
-                },
-                MemMode::Mem8bpp => {
-                    if load_subimg.format() == ImageFormat::Mono8Bpp {
-                        self.driver.load_image_fullwidth_8bpp(load_offset, &load_subimg)?;
-                    } else {
-                        let unpacked = convert::repack_mono(&load_subimg, ImageFormat::Mono8Bpp,
-                                                            self.driver.get_mem_pitch(MemMode::Mem8bpp));
-                        self.driver.load_image_fullwidth_8bpp(load_offset, &unpacked)?;
-                    }
-                },
+            let load_rows = load_offset as i32..load_offset as i32 + load_subimg.height();
+            if self.progressive_gray() {
+                // show the thresholded content first (with a fast mode), the dithered content
+                // would be reloaded for the quality pass, see `load_quality_rows`
+                let thresholded = convert::threshold_mono8(&load_subimg, 0x80);
+                self.load_rows(load_offset, &thresholded.view())?;
+                self.fast_loaded_rows.extend(load_rows);
+            } else {
+                self.load_rows(load_offset, &load_subimg)?;
+                self.fast_loaded_rows.retain(|y| !load_rows.contains(y));
             }
+            self.ghosting.load(&new_frame);
+            self.animation.record_changes(&modified_range, std::time::Instant::now());
             self.dirty_rows.append(&mut modified_range);
             drop(modified_range);
             self.loaded_frame_row_hashes = new_frame_row_hashes;
+            self.loaded_frame = Some(new_frame);
         }
         let t_loaded = std::time::Instant::now();
 
-        debug!("New mono frame loaded, {} rows dirty accumulated. Cost: get frame: {:?}, imgproc: {:?}, load: {:?}",
+        debug!("New frame loaded, {} rows dirty accumulated. Cost: get frame: {:?}, imgproc: {:?}, queued: {:?}, load: {:?}",
                self.dirty_rows.len(),
-               t_got_frame - t_load_start,
-               t_imgproc - t_got_frame,
-               t_loaded - t_imgproc);
+               captured.t_got_frame - captured.t_start,
+               captured.t_processed - captured.t_got_frame,
+               t_load_start.saturating_duration_since(captured.t_processed),
+               t_loaded - t_load_start);
         Ok(())
     }
 
-    // get frame and load into driver,modify display_dirty_range
-    fn load_frame_mono(&mut self) -> anyhow::Result<()> {
-        let screen_size = self.driver.get_screen_size();
-        let mempitch_1bpp = self.driver.get_mem_pitch(MemMode::Mem1bpp);
-        let dithering_method = self.current_run_mode.dithering_method().unwrap();
-        let mono_imgproc = self.mono_imgproc.clone();
-        self.load_frame_generic(
-            |bgra_img| {
-                let mut new_frame = ImageBuffer::new(ImageFormat::Mono1Bpp, screen_size.width, screen_size.height, Some(mempitch_1bpp));
-                mono_imgproc.borrow_mut().process(bgra_img, &mut new_frame, dithering_method);
-                new_frame
+    fn load_rows(&mut self, row_offset: u32, img: &ConstImageView) -> anyhow::Result<()> {
+        match self.current_run_mode.mem_mode() {
+            MemMode::Mem1bpp => {
+                if img.format() == ImageFormat::Mono1Bpp {
+                    self.driver.load_image_fullwidth_1bpp(row_offset, img)?;
+                } else {
+                    let packed = convert::repack_mono(img, ImageFormat::Mono1Bpp,
+                                                      self.driver.get_mem_pitch(MemMode::Mem1bpp));
+                    self.driver.load_image_fullwidth_1bpp(row_offset, &packed)?;
+                }
+            },
+            MemMode::Mem8bpp => {
+                if img.format() == ImageFormat::Mono8Bpp {
+                    self.driver.load_image_fullwidth_8bpp(row_offset, img)?;
+                } else {
+                    let unpacked = convert::repack_mono(img, ImageFormat::Mono8Bpp,
+                                                        self.driver.get_mem_pitch(MemMode::Mem8bpp));
+                    self.driver.load_image_fullwidth_8bpp(row_offset, &unpacked)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // in progressive gray mode, the controller memory may contain the thresholded content (for fast modes).
+    // reload the original content of these rows before the quality pass
+    fn load_quality_rows(&mut self, rows: std::ops::Range<i32>) -> anyhow::Result<()> {
+        let reload_rows = self.fast_loaded_rows.range(rows).copied().collect::<RowSet>();
+        let (start, end) = match (reload_rows.first(), reload_rows.last()) {
+            (Some(start), Some(end)) => (*start, *end + 1),
+            _ => return Ok(()),
+        };
+        let loaded_frame = match self.loaded_frame.take() {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let result = self.load_rows(
+            start as u32,
+            &loaded_frame.subimg((0, start).into(), (loaded_frame.width(), end - start).into()),
+        );
+        self.loaded_frame = Some(loaded_frame);
+        result?;
+        self.fast_loaded_rows.retain(|y| *y < start || *y >= end);
+        Ok(())
+    }
+
+    // copy the rows of the loaded frame to the displayed frame
+    fn update_displayed_frame(&mut self, rows: std::ops::Range<i32>) {
+        let loaded_frame = match self.loaded_frame.as_ref() {
+            Some(v) => v,
+            None => return,
+        };
+        if self.displayed_frame_row_hashes.len() != self.loaded_frame_row_hashes.len() {
+            self.displayed_frame_row_hashes = self.loaded_frame_row_hashes.clone();
+        } else {
+            let rows = rows.start as usize..rows.end as usize;
+            self.displayed_frame_row_hashes[rows.clone()].copy_from_slice(&self.loaded_frame_row_hashes[rows]);
+        }
+        match self.displayed_frame.as_mut() {
+            Some(displayed_frame)
+                if displayed_frame.size() == loaded_frame.size() && displayed_frame.format() == loaded_frame.format() =>
+            {
+                let pt = (0, rows.start).into();
+                let size = (loaded_frame.width(), rows.end - rows.start).into();
+                displayed_frame.mut_subimg(pt, size).copy_from(&loaded_frame.subimg(pt, size));
+            }
+            _ => {
+                let mut img = ImageBuffer::new(loaded_frame.format(), loaded_frame.width(),
+                                               loaded_frame.height(), Some(loaded_frame.pitch()));
+                img.copy_from(loaded_frame);
+                self.displayed_frame = Some(img);
             }
-        )
+        }
     }
 
-    fn load_frame_gray(&mut self) -> anyhow::Result<()> {
-        let screen_size = self.driver.get_screen_size();
-        let rotation = self.options.rotation;
-        self.load_frame_generic(
-            |bgra_img| {
-                let bgra_img = rotate_image(bgra_img, rotation, screen_size);
-                dithering::floyd_steinberg(&bgra_img, dithering::GREY16_TARGET_COLOR_SPACE)
+    // gray levels of the dirty rows, in both the displayed and the loaded frame.
+    // only inspected in gray run mode (without progressive refresh)
+    fn classify_dirty_content(&self, dirty_rows: &RowSet) -> Option<ContentClass> {
+        if !self.current_run_mode.is_gray() || self.progressive_gray() {
+            return None;
+        }
+        let start = *dirty_rows.first()?;
+        let end = *dirty_rows.last()? + 1;
+        match (self.displayed_frame.as_ref(), self.loaded_frame.as_ref()) {
+            (Some(displayed), Some(loaded)) if displayed.size() == loaded.size() => {
+                Some(classify_rows(&[displayed, loaded], start..end))
             }
-        )
+            _ => Some(ContentClass::Gray),
+        }
     }
 
-    // return if current display dirty range is actually not blocked by displaying rows
-    // NOTE that we only display one full range
-    fn can_display_nonoverlapping(&self) -> bool {
-        if self.dirty_rows.is_empty() || self.displaying_rows.is_empty() {
-            return false;
+    // kind of change between the displayed and loaded frame, ignoring the held rows
+    fn analyze_dirty_change(&self, held_rows: &RowSet) -> ChangeKind {
+        let page_turn_row_ratio = self.options.policy.page_turn_row_ratio;
+        let (displayed, loaded) = (&self.displayed_frame_row_hashes, &self.loaded_frame_row_hashes);
+        if held_rows.is_empty() || displayed.len() != loaded.len() {
+            return analyze_change(displayed, loaded, page_turn_row_ratio);
         }
-        let dirty_start = *self.dirty_rows.first().unwrap();
-        let dirty_end = *self.dirty_rows.last().unwrap() + 1;
-        self.displaying_rows
-            .iter()
-            .all(|x| *x < dirty_start || *x >= dirty_end)
+        let mut masked = loaded.clone();
+        for y in held_rows.iter().map(|y| *y as usize).filter(|y| *y < displayed.len()) {
+            masked[y] = displayed[y];
+        }
+        analyze_change(displayed, &masked, page_turn_row_ratio)
+    }
+
+    // animated dirty rows, which should not be displayed now (rate limited or frozen).
+    // they are released only if no other rows are dirty, so that they never delay other updates
+    fn held_animated_rows(&self, now: std::time::Instant) -> RowSet {
+        let policy = &self.options.policy;
+        if policy.animation_interval == 0 || self.dirty_rows.is_empty() {
+            return RowSet::new();
+        }
+        let animated = self.animation.animated_rows(now);
+        let held = self.dirty_rows.intersection(&animated).copied().collect::<RowSet>();
+        let interval = std::time::Duration::from_millis(policy.animation_interval);
+        let due = !policy.animation_freeze
+            && held.len() == self.dirty_rows.len()
+            && self.animation.since_last_displayed(now).is_none_or(|x| x >= interval);
+        if due {
+            RowSet::new()
+        } else {
+            held
+        }
+    }
+
+    fn progressive_gray(&self) -> bool {
+        self.options.policy.progressive_refresh && self.current_run_mode.is_gray()
     }
 
     fn poll_display_ready(&mut self, block: bool) -> anyhow::Result<bool> {
         while self.driver.read_busy_state()? {
+            self.t_last_busy = Some(std::time::Instant::now());
             if !block {
                 return Ok(false);
             }
             std::thread::sleep(self.options.driver_poll_ready_interval);
         }
         self.displaying_rows.clear();
+        // the busy time can only be attributed to the area if it's the only one displayed.
+        // the display finished between the last busy poll and now, which may be apart by a frame load:
+        // take the middle, so that the time spent outside polling does not inflate the latency
+        if let [(mode, height, t_start)] = self.display_started[..] {
+            let now = std::time::Instant::now();
+            let t_last_busy = self.t_last_busy.map_or(t_start, |x| x.max(t_start));
+            let latency = (t_last_busy - t_start) + (now - t_last_busy) / 2;
+            self.record_latency(mode, height, latency)?;
+        }
+        self.display_started.clear();
+        self.t_last_busy = None;
         return Ok(true);
     }
 
-    fn do_display_nonblock(&mut self) -> anyhow::Result<DisplayMode> {
-        assert!(!self.dirty_rows.is_empty());
-        let screen_size = self.driver.get_screen_size();
-        let num_dirty_rows_expanded = self
-            .dirty_rows
-            .iter()
-            .map(|x| *x / TEXT_ROW_TYPICAL_HEIGHT)
-            .collect::<std::collections::BTreeSet<i32>>()
-            .len() as i32
-            * TEXT_ROW_TYPICAL_HEIGHT;
-        let mode = if num_dirty_rows_expanded
-            < (screen_size.height as f32 * SLOW_REFRESH_ROW_RATIO_THRESHOLD) as i32
-        {
-            self.current_run_mode.display_mode_fast()
+    // wait for the next processed frame (at most the source poll interval).
+    // the controller is polled meanwhile if displaying, so that it is seen ready (and the latency measured) in time
+    fn take_frame(&mut self) -> anyhow::Result<Option<CapturedFrame>> {
+        let deadline = std::time::Instant::now() + self.options.source_poll_interval;
+        loop {
+            let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+            if self.displaying_rows.is_empty() {
+                return self.capture.take_frame(timeout);
+            }
+            if let Some(v) = self.capture.take_frame(timeout.min(self.options.driver_poll_ready_interval))? {
+                return Ok(Some(v));
+            }
+            self.poll_display_ready(/* block */ false)?;
+            if std::time::Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn current_temperature(&mut self) -> anyhow::Result<u8> {
+        match self.temperature {
+            Some((temperature, t)) if t.elapsed() < TEMPERATURE_READ_INTERVAL => Ok(temperature),
+            _ => {
+                let temperature = self.driver.read_temperature()?;
+                self.temperature = Some((temperature, std::time::Instant::now()));
+                Ok(temperature)
+            }
+        }
+    }
+
+    fn record_latency(&mut self, mode: DisplayMode, height: i32, latency: std::time::Duration) -> anyhow::Result<()> {
+        let temperature = self.current_temperature()?;
+        debug!("Display latency of {:?} with height {} at {} degrees: {:?}", mode, height, temperature, latency);
+        self.latency_model.record(temperature, mode, height, latency);
+        if self.latency_model.unsaved_samples() >= LATENCY_MODEL_SAVE_SAMPLES {
+            self.save_latency_model();
+        }
+        Ok(())
+    }
+
+    fn save_latency_model(&mut self) {
+        if let Some(path) = self.options.latency_model_path.as_deref() {
+            if let Err(e) = self.latency_model.save(path) {
+                warn!("Failed to save latency model to {:?}: {}", path, e);
+            }
+        }
+    }
+
+    fn do_display_nonblock(&mut self, request: DisplayRequest, cleanup: bool) -> anyhow::Result<()> {
+        let area = self.driver.align_display_area(request.area);
+        if request.mode.is_quality() {
+            self.load_quality_rows(area.pt.y..area.bottom())?;
+        }
+        let now = std::time::Instant::now();
+        self.driver.display_area(area.pt, area.size, request.mode, false)?;
+        self.display_started.push((request.mode, area.size.height, now));
+        self.displaying_rows.extend(area.pt.y..area.bottom());
+        if area.size.width == self.driver.get_screen_size().width {
+            self.dirty_rows.retain(|y| *y < area.pt.y || *y >= area.bottom());
+        }
+        self.ghosting.displayed(&area, request.mode, now);
+        if self.animation.animated_rows(now).range(area.pt.y..area.bottom()).next().is_some() {
+            self.animation.displayed(now);
+        }
+        self.update_displayed_frame(area.pt.y..area.bottom());
+        if request.mode.is_quality() {
+            if area.size.width == self.driver.get_screen_size().width {
+                self.quality_pending_rows.retain(|y| *y < area.pt.y || *y >= area.bottom());
+            }
         } else {
-            self.current_run_mode.display_mode_slow()
-        };
-        let dirty_start = *self.dirty_rows.first().unwrap();
-        let dirty_end = *self.dirty_rows.last().unwrap() + 1;
-        self.driver.display_area(
-            (0, dirty_start).into(),
-            (screen_size.width, dirty_end - dirty_start).into(),
-            mode,
-            false,
-        )?;
-        self.displaying_rows.extend(dirty_start..dirty_end);
-        self.dirty_rows.clear();
-        self.full_refreshed = false;
-        Ok(mode)
+            self.quality_pending_rows.extend(area.pt.y..area.bottom());
+        }
+        let record = UpdateRecord { time: now, area, mode: request.mode };
+        if cleanup {
+            self.history.record_cleanup(record);
+        } else {
+            self.history.record(record, false);
+        }
+        Ok(())
     }
 
     fn do_display_full_refresh_block(&mut self) -> anyhow::Result<()> {
+        self.load_quality_rows(0..self.driver.get_screen_size().height)?;
+        let t_start = std::time::Instant::now();
         self.driver.display_area(
             (0, 0).into(),
             self.driver.get_screen_size(),
             DisplayMode::GC16,
             true,
         )?;
+        self.record_latency(DisplayMode::GC16, self.driver.get_screen_size().height, t_start.elapsed())?;
         self.dirty_rows.clear();
         self.displaying_rows.clear();
-        self.full_refreshed = true;
+        self.quality_pending_rows.clear();
+        let screen = Rect::new((0, 0).into(), self.driver.get_screen_size());
+        self.ghosting.displayed(&screen, DisplayMode::GC16, std::time::Instant::now());
+        self.update_displayed_frame(0..screen.size.height);
+        self.history.record(
+            UpdateRecord { time: std::time::Instant::now(), area: screen, mode: DisplayMode::GC16 },
+            true,
+        );
+        Ok(())
+    }
+
+    fn do_display_area_refresh_block(&mut self, area: Rect) -> anyhow::Result<()> {
+        let area = self.driver.align_display_area(area);
+        self.load_quality_rows(area.pt.y..area.bottom())?;
+        let t_start = std::time::Instant::now();
+        self.driver.display_area(area.pt, area.size, DisplayMode::GC16, true)?;
+        self.record_latency(DisplayMode::GC16, area.size.height, t_start.elapsed())?;
+        self.displaying_rows.clear();
+        self.ghosting.displayed(&area, DisplayMode::GC16, std::time::Instant::now());
+        self.update_displayed_frame(area.pt.y..area.bottom());
         Ok(())
     }
 
+    fn switch_run_mode(&mut self, new_run_mode: RunMode) -> anyhow::Result<()> {
+        if new_run_mode == self.current_run_mode {
+            return Ok(());
+        }
+        info!("Switching to new run mode: {:?}", new_run_mode);
+        self.poll_display_ready(/* block */ true)?;
+        // no need to reset the display: the whole frame would be reloaded in the new memory format,
+        // and displayed with the transition mode of the new run mode (see `PolicyContext::run_mode_transition`)
+        self.loaded_frame_row_hashes.clear();
+        self.run_mode_switched = true;
+        self.fast_loaded_rows.clear();
+        self.quality_pending_rows.clear();
+        self.current_run_mode = new_run_mode;
+        self.capture.set_settings(self.capture_settings());
+        self.emit_event(serde_json::json!({
+            "event": "run_mode",
+            "run_mode": new_run_mode.to_string(),
+        }));
+        Ok(())
+    }
+
+    fn set_rotation(&mut self, rotation: Rotation) {
+        if rotation == self.options.rotation {
+            return;
+        }
+        info!("Switching to new rotation: {:?}", rotation);
+        self.options.rotation = rotation;
+        self.capture.set_settings(self.capture_settings());
+        self.loaded_frame_row_hashes.clear();
+        self.ghosting.reset();
+        self.rebuild_masks();
+    }
+
+    // quirk: devices with only 6 modes do not support the regal modes
+    fn check_policy_options(driver: &IT8915, policy: &mut PolicyOptions) {
+        if !driver.supports_display_mode(policy.gray_update_mode) {
+            warn!("Display mode {:?} is not supported by this device, falling back to GL16", policy.gray_update_mode);
+            policy.gray_update_mode = DisplayMode::GL16;
+        }
+    }
+
+    fn set_refresh_policy(&mut self, name: &str) -> anyhow::Result<()> {
+        self.refresh_policy = create_policy(name)?;
+        self.options.policy.refresh_policy = name.to_string();
+        info!("Switched to refresh policy: {}", name);
+        Ok(())
+    }
+
+    // only the changed settings are applied, so that e.g. a run mode set via control socket
+    // is not reverted by unrelated changes in config file
+    fn apply_settings(&mut self, old: &Settings, new: &Settings) -> anyhow::Result<()> {
+        if new.run_mode != old.run_mode {
+            self.switch_run_mode(new.run_mode()?)?;
+        }
+        if new.rotation != old.rotation {
+            self.set_rotation(new.rotation());
+        }
+        if new.vcom != old.vcom && new.vcom.is_some() {
+            self.driver.pmic_control(new.vcom, None)?;
+        }
+        self.options.driver_poll_ready_interval = new.driver_poll_ready_interval();
+        self.options.source_poll_interval = new.source_poll_interval();
+        self.capture.set_poll_interval(self.options.source_poll_interval);
+        if new.policy.ghosting_tile_size != old.policy.ghosting_tile_size {
+            self.ghosting = GhostingTracker::new(new.policy.ghosting_tile_size, self.driver.get_screen_size());
+        }
+        if new.policy.refresh_policy != old.policy.refresh_policy {
+            self.set_refresh_policy(&new.policy.refresh_policy)?;
+        }
+        self.options.policy = new.policy.clone();
+        Self::check_policy_options(&self.driver, &mut self.options.policy);
+        self.rebuild_masks();
+        self.capture.set_settings(self.capture_settings());
+
+        let restart_required = [
+            ("device", new.device != old.device),
+            ("source", new.source != old.source),
+            ("source offset", new.source_offset() != old.source_offset()),
+            ("run mode config", new.run_mode_config != old.run_mode_config),
+            ("control socket", new.control_socket() != old.control_socket()),
+            ("latency model", new.latency_model() != old.latency_model()),
+            ("imgproc", new.imgproc() != old.imgproc()),
+        ];
+        for (name, _) in restart_required.iter().filter(|x| x.1) {
+            warn!("Changing {} requires restart, ignored", name);
+        }
+        Ok(())
+    }
+
+    fn reload_config(&mut self) {
+        let reloaded = match self.options.config_watcher.as_mut().and_then(|x| x.poll()) {
+            Some(v) => v,
+            None => return,
+        };
+        let result = reloaded.and_then(|(old, new)| self.apply_settings(&old, &new));
+        if let Err(e) = result {
+            warn!("Failed to reload config: {:#}", e);
+        }
+    }
+
+    fn status(&mut self) -> anyhow::Result<serde_json::Value> {
+        let temperature = self.current_temperature()?;
+        Ok(serde_json::json!({
+            "run_mode": self.current_run_mode.to_string(),
+            "rotation": self.options.rotation.to_string(),
+            "refresh_policy": self.refresh_policy.name(),
+            "gray_update_mode": format!("{:?}", self.options.policy.gray_update_mode),
+            "paused": self.paused,
+            "temperature": temperature,
+            "last_latency_ms": self.last_latency.map(|x| x.as_secs_f64() * 1000.0),
+            "dirty_rows": self.dirty_rows.len(),
+            "displaying_rows": self.displaying_rows.len(),
+            "ghosting_max_fast_updates": self.ghosting.max_fast_updates(),
+            "masks": self.masks.len(),
+            "animated_rows": self.animation.animated_rows(std::time::Instant::now()).len(),
+            "input_row": self.input_row(std::time::Instant::now()),
+            // estimated latency (ms) of each mode, for a single row and the full screen
+            "latency_model": self.latency_model.describe(temperature, self.driver.get_screen_size().height),
+        }))
+    }
+
+    fn emit_event(&mut self, event: serde_json::Value) {
+        if let Some(server) = self.control_server.as_mut() {
+            server.broadcast(&event);
+        }
+    }
+
+    // screen row of recent input
+    fn input_row(&self, now: std::time::Instant) -> Option<i32> {
+        let delay = std::time::Duration::from_millis(self.options.policy.input_recent_delay);
+        let pos = self.input.recent_position(now, delay)?;
+        let area = self.options.rotation.map_rect(Rect::new(pos, (1, 1).into()), self.driver.get_screen_size());
+        (!area.is_empty()).then_some(area.pt.y)
+    }
+
+    fn handle_command(&mut self, client_id: ClientId, command: Command) -> anyhow::Result<serde_json::Value> {
+        let mut response = serde_json::json!({"ok": true});
+        match command {
+            Command::SetRunMode(run_mode) => self.switch_run_mode(run_mode)?,
+            Command::Refresh(None) => self.full_refresh_requested = true,
+            Command::Refresh(Some(rect)) => {
+                let area = self.options.rotation.map_rect(rect, self.driver.get_screen_size());
+                if area.is_empty() {
+                    anyhow::bail!("Refresh area {:?} is out of screen", rect);
+                }
+                self.area_refresh_requested = Some(area);
+            }
+            Command::Pause | Command::Resume => {
+                self.paused = command == Command::Pause;
+                self.emit_event(serde_json::json!({"event": "paused", "paused": self.paused}));
+            }
+            Command::SetRotation(rotation) => self.set_rotation(rotation),
+            Command::SetRefreshPolicy(ref name) => self.set_refresh_policy(name)?,
+            Command::AddMask(ref mask) => {
+                self.runtime_mask_options.push(mask.clone());
+                self.rebuild_masks();
+            }
+            Command::ClearMasks => {
+                self.runtime_mask_options.clear();
+                self.rebuild_masks();
+            }
+            Command::SetCaret(pos) => self.input.update_caret(pos, std::time::Instant::now()),
+            Command::Status => response["status"] = self.status()?,
+            Command::Subscribe => {
+                if let Some(server) = self.control_server.as_mut() {
+                    server.subscribe(client_id);
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    fn handle_control_commands(&mut self) {
+        let commands = match self.control_server.as_mut() {
+            Some(server) => server.poll(),
+            None => return,
+        };
+        for (client_id, command) in commands {
+            let response = match command.and_then(|c| self.handle_command(client_id, c)) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Failed to handle control command: {}", e);
+                    serde_json::json!({"ok": false, "error": e.to_string()})
+                }
+            };
+            if let Some(server) = self.control_server.as_mut() {
+                server.reply(client_id, &response);
+            }
+        }
+    }
+
     pub fn run(&mut self) -> anyhow::Result<()> {
-        let mut t_last_update = std::time::Instant::now();
         let mut t_last_need_update: Option<std::time::Instant> = None;
         while !self.options.terminate_flag.swap(false, Ordering::Relaxed) {
             let reload_requested = self.options.reload_flag.swap(false, Ordering::Relaxed);
             if reload_requested {
-                let new_run_mode = (self.options.get_run_mode_callback)();
-                if new_run_mode != self.current_run_mode {
-                    info!("Switching to new run mode: {:?}", new_run_mode);
-                    self.poll_display_ready(/* block */ true)?;
-                    self.driver.reset_display()?;
-                    self.loaded_frame_row_hashes.clear();
-                    self.current_run_mode = new_run_mode;
+                if let Some(run_mode) = (self.options.get_run_mode_callback)() {
+                    self.switch_run_mode(run_mode)?;
                 }
             }
 
-            let load_result = match self.current_run_mode {
-                RunMode::Mono(_) | RunMode::MonoForce8bpp(_) => self.load_frame_mono(),
-                RunMode::Gray => self.load_frame_gray(),
-            };
-            if load_result.is_err() {   // TODO: check the error type
-                // frame not ready
+            self.reload_config();
+            self.handle_control_commands();
+            if self.paused {
                 std::thread::sleep(self.options.source_poll_interval);
                 continue;
             }
+            let refresh_requested = reload_requested || std::mem::take(&mut self.full_refresh_requested);
 
-            let need_display = !self.dirty_rows.is_empty();
-            let full_refresh = (reload_requested
-                && (!self.full_refreshed || t_last_update.elapsed() > FULL_REFRESH_MIN_INTERVAL))
-                || (!need_display
-                    && t_last_update.elapsed() > FULL_REFRESH_IDLE_DELAY
-                    && !self.full_refreshed);
-            if full_refresh {
-                info!("Full refresh!");
-                self.poll_display_ready(/* block */ true)?;
-                self.do_display_full_refresh_block()?;
-                t_last_update = std::time::Instant::now();
-                t_last_need_update = None;
-                continue;
-            }
-
-            if !need_display {
-                // frame not changed
+            // waits for the next frame (at most the poll interval), so no need to sleep when idle
+            if let Err(e) = self.load_frame() {
+                if !self.capture.is_running() {
+                    return Err(e);
+                }
+                // e.g. a USB transfer error, the rows are loaded again with the next frame
+                warn!("Failed to load frame: {:#}", e);
                 std::thread::sleep(self.options.source_poll_interval);
                 continue;
             }
 
-            if t_last_need_update.is_none() {
-                t_last_need_update = Some(std::time::Instant::now());
+            if let Some(area) = self.area_refresh_requested.take() {
+                info!("Area refresh: {:?}", area);
+                self.poll_display_ready(/* block */ true)?;
+                self.do_display_area_refresh_block(area)?;
+                self.emit_event(serde_json::json!({
+                    "event": "area_refresh",
+                    "area": [area.pt.x, area.pt.y, area.size.width, area.size.height],
+                }));
             }
 
-            if !self.poll_display_ready(/* block */ false)? && !self.can_display_nonoverlapping() {
-                // cannot display now. we would wait for ready and loop again to get the newest frame
-                self.poll_display_ready(/* block */ true)?;
-                continue;
+            if !self.displaying_rows.is_empty() {
+                self.poll_display_ready(/* block */ false)?;
+            }
+            let temperature = Some(self.current_temperature()?);
+            let now = std::time::Instant::now();
+            let held_rows = self.held_animated_rows(now);
+            let pending_rows;
+            let dirty_rows = if held_rows.is_empty() {
+                &self.dirty_rows
+            } else {
+                pending_rows = self.dirty_rows.difference(&held_rows).copied().collect::<RowSet>();
+                &pending_rows
+            };
+            if !dirty_rows.is_empty() && t_last_need_update.is_none() {
+                t_last_need_update = Some(now);
             }
 
-            let displayed_mode = self.do_display_nonblock()?;
+            let dirty_content = self.classify_dirty_content(dirty_rows);
+            let quality_areas = quality_areas(&self.masks);
+            let dirty_change = if dirty_rows.is_empty() {
+                ChangeKind::Minor
+            } else {
+                self.analyze_dirty_change(&held_rows)
+            };
+            let dirty_rows_count = dirty_rows.len();
+            let decision = self.refresh_policy.decide(&PolicyContext {
+                now,
+                run_mode: self.current_run_mode,
+                screen_size: self.driver.get_screen_size(),
+                dirty_rows,
+                displaying_rows: &self.displaying_rows,
+                quality_pending_rows: &self.quality_pending_rows,
+                dirty_content,
+                dirty_change,
+                quality_areas: &quality_areas,
+                refresh_requested,
+                run_mode_switched: self.run_mode_switched,
+                input_row: self.input_row(now),
+                temperature,
+                latency: &self.latency_model,
+                history: &self.history,
+                ghosting: &self.ghosting,
+                options: &self.options.policy,
+            });
+            match decision {
+                Decision::Idle => {
+                    // frame not changed, wait for the next frame
+                }
+                Decision::WaitReady => {
+                    // cannot display now. we would wait for ready and loop again to get the newest frame
+                    self.poll_display_ready(/* block */ true)?;
+                }
+                Decision::FullRefresh => {
+                    info!("Full refresh!");
+                    self.poll_display_ready(/* block */ true)?;
+                    self.do_display_full_refresh_block()?;
+                    self.run_mode_switched = false;
+                    self.emit_event(serde_json::json!({"event": "full_refresh"}));
+                    t_last_need_update = None;
+                }
+                Decision::Cleanup(cleanup, requests) => {
+                    // nothing new, only improving the displayed content
+                    info!("Cleanup ({}): {:?}", cleanup.event_name(), requests);
+                    let areas = requests
+                        .iter()
+                        .map(|x| [x.area.pt.x, x.area.pt.y, x.area.size.width, x.area.size.height])
+                        .collect::<Vec<_>>();
+                    for request in requests {
+                        self.do_display_nonblock(request, /* cleanup */ true)?;
+                    }
+                    self.emit_event(serde_json::json!({"event": cleanup.event_name(), "areas": areas}));
+                }
+                Decision::Display(requests) => {
+                    let modes = requests.iter().map(|x| format!("{:?}", x.mode)).collect::<Vec<_>>();
+                    for request in requests {
+                        self.do_display_nonblock(request, /* cleanup */ false)?;
+                    }
+                    self.run_mode_switched = false;
 
-            let t_update = std::time::Instant::now();
-            info!(
-                "New frame displayed, process delay: {:?}, mode: {:?}",
-                t_update - t_last_need_update.unwrap(),
-                displayed_mode
-            );
-            t_last_update = t_update;
-            t_last_need_update = None;
+                    let latency = t_last_need_update.map(|x| x.elapsed()).unwrap_or_default();
+                    info!(
+                        "New frame displayed, process delay: {:?}, mode: {}",
+                        latency,
+                        modes.join(",")
+                    );
+                    self.last_latency = Some(latency);
+                    self.emit_event(serde_json::json!({
+                        "event": "displayed",
+                        "mode": modes.join(","),
+                        "dirty_rows": dirty_rows_count,
+                        "latency_ms": latency.as_secs_f64() * 1000.0,
+                    }));
+                    // the held animated rows are not waiting to be displayed
+                    if self.dirty_rows.is_subset(&held_rows) {
+                        t_last_need_update = None;
+                    }
+                }
+            }
         }
+        self.save_latency_model();
         self.driver.reset_display()?;
         Ok(())
     }
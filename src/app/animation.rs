@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::RowSet;
+
+// number of recent changes kept for each row
+const HISTORY_LEN: usize = 6;
+// rows changing slower than this are not considered animated
+const MAX_PERIOD: Duration = Duration::from_secs(5);
+// allowed deviation of the intervals between changes, relative to the mean interval
+const PERIOD_TOLERANCE: f32 = 0.25;
+// ...plus this absolute deviation, for the jitter of frame polling
+const PERIOD_JITTER: Duration = Duration::from_millis(30);
+
+// Detect rows that change periodically (spinners, blinking cursors, clocks...),
+// by the timing of changes in row hashes. Irregular changes (e.g. typing) are not animated.
+pub struct AnimationTracker {
+    changes: Vec<VecDeque<Instant>>, // recent change times of each row
+    last_displayed: Option<Instant>,  // last time any animated rows are displayed
+}
+
+impl AnimationTracker {
+    pub fn new(height: i32) -> Self {
+        AnimationTracker {
+            changes: vec![VecDeque::new(); height as usize],
+            last_displayed: None,
+        }
+    }
+
+    pub fn record_changes(&mut self, rows: &RowSet, now: Instant) {
+        for row in rows {
+            if let Some(changes) = self.changes.get_mut(*row as usize) {
+                if changes.len() >= HISTORY_LEN {
+                    changes.pop_front();
+                }
+                changes.push_back(now);
+            }
+        }
+    }
+
+    fn is_animated(changes: &VecDeque<Instant>, now: Instant) -> bool {
+        if changes.len() < HISTORY_LEN {
+            return false;
+        }
+        let intervals = changes
+            .iter()
+            .zip(changes.iter().skip(1))
+            .map(|(a, b)| *b - *a)
+            .collect::<Vec<_>>();
+        let mean = intervals.iter().sum::<Duration>() / intervals.len() as u32;
+        let tolerance = mean.mul_f32(PERIOD_TOLERANCE) + PERIOD_JITTER;
+        mean <= MAX_PERIOD
+            // still animating
+            && now.saturating_duration_since(*changes.back().unwrap()) <= mean * 2 + PERIOD_JITTER
+            && intervals.iter().all(|x| x.max(&mean).saturating_sub(*x.min(&mean)) <= tolerance)
+    }
+
+    pub fn animated_rows(&self, now: Instant) -> RowSet {
+        self.changes
+            .iter()
+            .enumerate()
+            .filter(|(_, changes)| Self::is_animated(changes, now))
+            .map(|(row, _)| row as i32)
+            .collect()
+    }
+
+    pub fn displayed(&mut self, now: Instant) {
+        self.last_displayed = Some(now);
+    }
+
+    pub fn since_last_displayed(&self, now: Instant) -> Option<Duration> {
+        self.last_displayed.map(|x| now.saturating_duration_since(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_animation_tracker() {
+        let mut tracker = AnimationTracker::new(100);
+        let start = Instant::now();
+        let blinking = RowSet::from_iter(10..20);
+        // typing in row 50
+        let typing_times = [0, 120, 200, 450, 520, 900, 1000];
+        for i in 0..8 {
+            let now = start + Duration::from_millis(i * 500);
+            tracker.record_changes(&blinking, now);
+        }
+        for t in typing_times {
+            tracker.record_changes(&RowSet::from([50]), start + Duration::from_millis(t));
+        }
+        let now = start + Duration::from_millis(3600);
+        assert_eq!(tracker.animated_rows(now), blinking);
+        // stopped
+        assert!(tracker.animated_rows(now + Duration::from_secs(2)).is_empty());
+    }
+}
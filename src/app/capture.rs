@@ -0,0 +1,576 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use log::{debug, error, info, trace};
+
+use crate::image::*;
+use crate::imgproc::dithering::{self, DiffusionKernel, ErrorDiffusion};
+use crate::imgproc::stabilize::Stabilizer;
+use crate::imgproc::stroke::Embolden;
+use crate::imgproc::{GrayImgproc, ImgprocBackend, MonoImgproc, MonoImgprocOptions, Rotation};
+use crate::run_mode::RunMode;
+use crate::source::{Source, SourceFactory};
+
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// Everything that affects the content of processed frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CaptureSettings {
+    pub run_mode: RunMode,
+    pub rotation: Rotation,
+    pub naive_threshold: u8,
+    pub font_recovery: bool,
+    pub embolden: Embolden,
+    pub hysteresis: u8, // see `Stabilizer`, 0 to disable
+}
+
+pub struct CapturedFrame {
+    pub frame: ImageBuffer, // processed frame in screen coordinate, ready to load into the controller
+    pub pointer: Option<Point>, // pointer position in source coordinate, see `Source::pointer_position`
+    pub settings: CaptureSettings,
+    pub t_start: Instant,
+    pub t_got_frame: Instant,
+    pub t_processed: Instant,
+}
+
+struct State {
+    settings: CaptureSettings,
+    poll_interval: Duration,
+    latest: Option<CapturedFrame>, // the queue, only the newest frame is kept
+    dropped_frames: u64,
+    stop: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+// Capture and process frames in a separate thread, so that they are done while the main thread
+// is busy with USB transfers or waiting for the controller.
+// Frames not taken in time are dropped in favour of the newer one.
+pub struct CaptureThread {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Worker {
+    source_factory: SourceFactory,
+    source: Box<dyn Source>,
+    mono_imgproc: Option<MonoImgproc>, // created on first use, only required by mono run modes
+    gray_imgproc: Option<GrayImgproc>, // likewise, for the gray levels and error diffusion run modes
+    stabilizer: Option<Stabilizer>, // holds the previous output, if hysteresis is enabled
+    imgproc_backend: ImgprocBackend,
+    settings: CaptureSettings,
+    screen_size: Size,
+    mem_pitch_1bpp: i32,
+}
+
+impl Worker {
+    fn update_settings(&mut self, settings: CaptureSettings) -> anyhow::Result<()> {
+        if settings.rotation != self.settings.rotation {
+            let max_size = settings.rotation.rotated_size(self.screen_size);
+            if max_size != self.settings.rotation.rotated_size(self.screen_size) {
+                // the source is sized to the rotated screen, which is swapped between landscape and portrait
+                info!("Re-creating source for rotation {:?}", settings.rotation);
+                self.source = (self.source_factory)(max_size)?;
+            }
+            self.mono_imgproc = None;
+            self.gray_imgproc = None;
+        } else if let Some(mono_imgproc) = self.mono_imgproc.as_mut() {
+            mono_imgproc.set_naive_threshold(settings.naive_threshold);
+            mono_imgproc.set_font_recovery(settings.font_recovery);
+            mono_imgproc.set_embolden(settings.embolden);
+        }
+        if settings.run_mode != self.settings.run_mode {
+            // the gray levels may be different
+            self.gray_imgproc = None;
+        }
+        if (settings.hysteresis > 0) != (self.settings.hysteresis > 0) {
+            // see `effective_backend`
+            self.mono_imgproc = None;
+            self.gray_imgproc = None;
+        }
+        // the previous output is made with different settings
+        self.stabilizer = None;
+        self.settings = settings;
+        Ok(())
+    }
+
+    // the stabilizer needs the gray levels of each frame, which the GPU does not read back:
+    // computing them again in the CPU would cost more than processing the whole frame in the CPU
+    fn effective_backend(&self) -> ImgprocBackend {
+        if self.settings.hysteresis > 0 {
+            ImgprocBackend::Cpu
+        } else {
+            self.imgproc_backend
+        }
+    }
+
+    fn init_imgproc(&mut self) -> anyhow::Result<()> {
+        let backend = self.effective_backend();
+        let options = MonoImgprocOptions {
+            rotation: self.settings.rotation,
+            input_size: self.source.frame_size(),
+            output_size: self.screen_size,
+        };
+        match self.settings.run_mode {
+            RunMode::Mono(_) | RunMode::MonoForce8bpp(_) if self.mono_imgproc.is_none() => {
+                let mut mono_imgproc = MonoImgproc::with_backend(options, backend)?;
+                mono_imgproc.set_naive_threshold(self.settings.naive_threshold);
+                mono_imgproc.set_font_recovery(self.settings.font_recovery);
+                mono_imgproc.set_embolden(self.settings.embolden);
+                self.mono_imgproc = Some(mono_imgproc);
+            }
+            RunMode::Mono4 | RunMode::Gray if self.gray_imgproc.is_none() => {
+                let target_color_space = if self.settings.run_mode == RunMode::Mono4 {
+                    dithering::GREY4_TARGET_COLOR_SPACE
+                } else {
+                    dithering::GREY16_TARGET_COLOR_SPACE
+                };
+                self.gray_imgproc = Some(GrayImgproc::error_diffusion(options, target_color_space, ErrorDiffusion {
+                    kernel: DiffusionKernel::FloydSteinberg,
+                    serpentine: false,
+                }));
+            }
+            RunMode::Mono4Ordered | RunMode::GrayOrdered if self.gray_imgproc.is_none() => {
+                let target_color_space = if self.settings.run_mode == RunMode::Mono4Ordered {
+                    dithering::GREY4_TARGET_COLOR_SPACE
+                } else {
+                    dithering::GREY16_TARGET_COLOR_SPACE
+                };
+                self.gray_imgproc = Some(GrayImgproc::ordered(options, target_color_space, backend)?);
+            }
+            RunMode::MonoDiffusion(v) | RunMode::Mono4Diffusion(v) | RunMode::GrayDiffusion(v)
+                if self.gray_imgproc.is_none() => {
+                let target_color_space = match self.settings.run_mode {
+                    RunMode::MonoDiffusion(_) => dithering::BW_TARGET_COLOR_SPACE,
+                    RunMode::Mono4Diffusion(_) => dithering::GREY4_TARGET_COLOR_SPACE,
+                    _ => dithering::GREY16_TARGET_COLOR_SPACE,
+                };
+                self.gray_imgproc = Some(GrayImgproc::error_diffusion(options, target_color_space, v));
+            }
+            _ => (),
+        }
+        if self.settings.hysteresis > 0 && self.stabilizer.is_none() {
+            self.stabilizer = Some(Stabilizer::new(options, self.settings.hysteresis));
+        }
+        Ok(())
+    }
+
+    // a separate function from `capture`, since the frame borrows the source
+    fn process_gray(gray_imgproc: Option<&mut GrayImgproc>, stabilizer: Option<&mut Stabilizer>,
+                    bgra_img: &dyn ConstImage, frame: &mut ImageBuffer) {
+        let gray_imgproc = gray_imgproc.expect("gray imgproc is not initialized");
+        match stabilizer {
+            Some(stabilizer) => gray_imgproc.process_stabilized(bgra_img, frame, stabilizer),
+            None => gray_imgproc.process(bgra_img, frame),
+        }
+    }
+
+    fn capture(&mut self) -> anyhow::Result<CapturedFrame> {
+        let t_start = Instant::now();
+        let bgra_img = self.source.get_frame()?;
+        let t_got_frame = Instant::now();
+
+        let screen_size = self.screen_size;
+        let frame = match self.settings.run_mode {
+            RunMode::Mono(_) | RunMode::MonoForce8bpp(_) => {
+                let mut frame = ImageBuffer::new(ImageFormat::Mono1Bpp, screen_size.width, screen_size.height,
+                                                 Some(self.mem_pitch_1bpp));
+                let dithering_method = self.settings.run_mode.dithering_method().unwrap();
+                let mono_imgproc = self.mono_imgproc.as_mut().expect("mono imgproc is not initialized");
+                mono_imgproc.process(bgra_img.as_ref(), &mut frame, dithering_method);
+                if let Some(stabilizer) = self.stabilizer.as_mut() {
+                    // an output pixel depends on the gray levels of its neighbours within this radius
+                    let (radius_x, radius_y) = self.settings.embolden.radius_xy();
+                    let radius = radius_x.max(radius_y).max(self.settings.font_recovery as usize);
+                    match mono_imgproc.output_gray() {
+                        Some(gray) => stabilizer.update_gray(gray, radius),
+                        None => stabilizer.update(bgra_img.as_ref(), radius),
+                    }
+                    stabilizer.apply(&mut frame);
+                }
+                frame
+            }
+            RunMode::MonoDiffusion(_) => {
+                let mut frame = ImageBuffer::new(ImageFormat::Mono8Bpp, screen_size.width, screen_size.height, None);
+                Self::process_gray(self.gray_imgproc.as_mut(), self.stabilizer.as_mut(), bgra_img.as_ref(), &mut frame);
+                convert::repack_mono(&frame, ImageFormat::Mono1Bpp, self.mem_pitch_1bpp)
+            }
+            RunMode::Mono4 | RunMode::Gray | RunMode::Mono4Ordered | RunMode::GrayOrdered
+            | RunMode::Mono4Diffusion(_) | RunMode::GrayDiffusion(_) => {
+                let mut frame = ImageBuffer::new(ImageFormat::Mono8Bpp, screen_size.width, screen_size.height, None);
+                Self::process_gray(self.gray_imgproc.as_mut(), self.stabilizer.as_mut(), bgra_img.as_ref(), &mut frame);
+                frame
+            }
+        };
+        drop(bgra_img);
+        Ok(CapturedFrame {
+            frame,
+            pointer: self.source.pointer_position(),
+            settings: self.settings,
+            t_start,
+            t_got_frame,
+            t_processed: Instant::now(),
+        })
+    }
+
+    fn run(mut self, shared: Arc<Shared>) {
+        loop {
+            let (settings, poll_interval) = {
+                let state = shared.state.lock().unwrap();
+                if state.stop {
+                    return;
+                }
+                (state.settings, state.poll_interval)
+            };
+            // failures here may be transient (e.g. the display server is restarting), so retry later
+            // instead of exiting, which would stop the whole program
+            let prepared = if settings != self.settings {
+                self.update_settings(settings).context("Failed to update capture settings")
+            } else {
+                Ok(())
+            }
+            .and_then(|_| self.init_imgproc().context("Failed to initialize imgproc"));
+            if let Err(e) = prepared {
+                error!("{:#}, retrying in {:?}", e, RETRY_DELAY);
+                let state = shared.state.lock().unwrap();
+                let _ = shared
+                    .cond
+                    .wait_timeout_while(state, RETRY_DELAY, |s| !s.stop && s.settings == settings)
+                    .unwrap();
+                continue;
+            }
+            match self.capture() {
+                Ok(frame) => {
+                    let mut state = shared.state.lock().unwrap();
+                    if state.latest.replace(frame).is_some() {
+                        state.dropped_frames += 1;
+                    }
+                    shared.cond.notify_all();
+                }
+                Err(e) => trace!("Failed to capture frame: {}", e), // frame not ready
+            }
+            // capture again after the poll interval, or immediately if the settings are changed
+            let state = shared.state.lock().unwrap();
+            let _ = shared
+                .cond
+                .wait_timeout_while(state, poll_interval, |s| !s.stop && s.settings == settings)
+                .unwrap();
+        }
+    }
+}
+
+impl CaptureThread {
+    pub fn spawn(
+        mut source_factory: SourceFactory,
+        settings: CaptureSettings,
+        poll_interval: Duration,
+        screen_size: Size,
+        mem_pitch_1bpp: i32,
+        imgproc_backend: ImgprocBackend,
+    ) -> anyhow::Result<Self> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                settings,
+                poll_interval,
+                latest: None,
+                dropped_frames: 0,
+                stop: false,
+            }),
+            cond: Condvar::new(),
+        });
+        // the source is created in the capture thread, since it may not be sendable
+        let (init_sender, init_receiver) = std::sync::mpsc::sync_channel(1);
+        let thread_shared = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || {
+                let source = match source_factory(settings.rotation.rotated_size(screen_size)) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = init_sender.send(Err(e));
+                        return;
+                    }
+                };
+                let worker = Worker {
+                    source_factory,
+                    source,
+                    mono_imgproc: None,
+                    gray_imgproc: None,
+                    stabilizer: None,
+                    imgproc_backend,
+                    settings,
+                    screen_size,
+                    mem_pitch_1bpp,
+                };
+                let _ = init_sender.send(Ok(()));
+                worker.run(thread_shared);
+            })?;
+        init_receiver
+            .recv()
+            .map_err(|_| anyhow::format_err!("Capture thread exited during initialization"))??;
+        info!("Capture thread started");
+        Ok(CaptureThread {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn set_settings(&self, settings: CaptureSettings) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.settings != settings {
+            state.settings = settings;
+            self.shared.cond.notify_all();
+        }
+    }
+
+    pub fn set_poll_interval(&self, poll_interval: Duration) {
+        self.shared.state.lock().unwrap().poll_interval = poll_interval;
+    }
+
+    // the thread only exits when stopped, or if it panicked
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|x| !x.is_finished())
+    }
+
+    // take the newest frame processed with current settings, waiting at most `timeout`
+    pub fn take_frame(&self, timeout: Duration) -> anyhow::Result<Option<CapturedFrame>> {
+        if !self.is_running() {
+            anyhow::bail!("Capture thread exited unexpectedly");
+        }
+        let state = self.shared.state.lock().unwrap();
+        let (mut state, _) = self
+            .shared
+            .cond
+            .wait_timeout_while(state, timeout, |s| {
+                !s.latest.as_ref().is_some_and(|x| x.settings == s.settings)
+            })
+            .unwrap();
+        let frame = match state.latest.take() {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        if frame.settings != state.settings {
+            debug!("Dropping frame captured with old settings");
+            return Ok(None);
+        }
+        Ok(Some(frame))
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.state.lock().unwrap().dropped_frames
+    }
+}
+
+impl Drop for CaptureThread {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stop = true;
+        self.shared.cond.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgproc::cpu::CpuMonoImgproc;
+    use crate::imgproc::stroke::StrokeDirection;
+    use crate::imgproc::DitheringMethod;
+
+    struct FakeSource {
+        frame: ImageBuffer,
+    }
+
+    impl Source for FakeSource {
+        fn get_frame(&mut self) -> anyhow::Result<Box<dyn ConstImage + '_>> {
+            Ok(Box::new(self.frame.view()))
+        }
+
+        fn frame_size(&self) -> Size {
+            self.frame.size()
+        }
+    }
+
+    // returns the frames in order, then keeps returning the last one
+    struct SequenceSource {
+        frames: Vec<ImageBuffer>,
+        calls: Arc<Mutex<usize>>,
+    }
+
+    impl Source for SequenceSource {
+        fn get_frame(&mut self) -> anyhow::Result<Box<dyn ConstImage + '_>> {
+            let mut calls = self.calls.lock().unwrap();
+            let frame = &self.frames[(*calls).min(self.frames.len() - 1)];
+            *calls += 1;
+            Ok(Box::new(frame.view()))
+        }
+
+        fn frame_size(&self) -> Size {
+            self.frames[0].size()
+        }
+    }
+
+    // white, except for the given (x, gray level) columns
+    fn columns_frame(size: Size, columns: &[(usize, u8)]) -> ImageBuffer {
+        let mut frame = ImageBuffer::new(ImageFormat::BGRA, size.width, size.height, None);
+        frame.fill(0xff);
+        for y in 0..size.height {
+            let row = unsafe { std::slice::from_raw_parts_mut(frame.mut_ptr(y), size.width as usize * 4) };
+            for &(x, v) in columns {
+                row[x * 4..(x + 1) * 4].copy_from_slice(&[v, v, v, 0xff]);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn test_capture_thread() {
+        let settings = CaptureSettings {
+            run_mode: RunMode::Gray,
+            rotation: Rotation::NoRotation,
+            naive_threshold: 128,
+            font_recovery: true,
+            embolden: Embolden::default(),
+            hysteresis: 0,
+        };
+        let source_factory: SourceFactory = Box::new(|max_size: Size| {
+            let mut frame = ImageBuffer::new(ImageFormat::BGRA, max_size.width, max_size.height, None);
+            frame.fill(0xff);
+            Ok(Box::new(FakeSource { frame }) as Box<dyn Source>)
+        });
+        let capture = CaptureThread::spawn(source_factory, settings, Duration::from_millis(1), (64, 8).into(), 8, ImgprocBackend::Cpu)
+            .unwrap();
+        let captured = capture.take_frame(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(captured.settings, settings);
+        assert_eq!(captured.frame.format(), ImageFormat::Mono8Bpp);
+        assert!(captured.frame.data().iter().all(|x| *x == 0xf0));
+    }
+
+    #[test]
+    fn test_rotation_recreates_source() {
+        let settings = CaptureSettings {
+            run_mode: RunMode::Gray,
+            rotation: Rotation::NoRotation,
+            naive_threshold: 128,
+            font_recovery: true,
+            embolden: Embolden::default(),
+            hysteresis: 0,
+        };
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let factory_sizes = sizes.clone();
+        let source_factory: SourceFactory = Box::new(move |max_size: Size| {
+            factory_sizes.lock().unwrap().push(max_size);
+            // black, so that any part of the screen not covered by the frame would show as white
+            let mut frame = ImageBuffer::new(ImageFormat::BGRA, max_size.width, max_size.height, None);
+            frame.fill(0);
+            Ok(Box::new(FakeSource { frame }) as Box<dyn Source>)
+        });
+        let capture = CaptureThread::spawn(source_factory, settings, Duration::from_millis(1), (64, 8).into(), 8, ImgprocBackend::Cpu)
+            .unwrap();
+        capture.take_frame(Duration::from_secs(5)).unwrap().unwrap();
+
+        let settings = CaptureSettings { rotation: Rotation::Rotate90, ..settings };
+        capture.set_settings(settings);
+        let captured = loop {
+            if let Some(v) = capture.take_frame(Duration::from_secs(5)).unwrap() {
+                break v;
+            }
+        };
+        assert_eq!(captured.settings, settings);
+        assert_eq!(captured.frame.size(), (64, 8).into());
+        assert!(captured.frame.data().iter().all(|x| *x == 0));
+        assert_eq!(*sizes.lock().unwrap(), [(64, 8).into(), (8, 64).into()]);
+
+        // 90 -> 270 keeps the orientation, and the source
+        capture.set_settings(CaptureSettings { rotation: Rotation::Rotate270, ..settings });
+        while capture.take_frame(Duration::from_secs(5)).unwrap().is_none() {}
+        assert_eq!(sizes.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_source_failure_is_retried() {
+        let settings = CaptureSettings {
+            run_mode: RunMode::Gray,
+            rotation: Rotation::NoRotation,
+            naive_threshold: 128,
+            font_recovery: true,
+            embolden: Embolden::default(),
+            hysteresis: 0,
+        };
+        let calls = Arc::new(Mutex::new(0));
+        let factory_calls = calls.clone();
+        let source_factory: SourceFactory = Box::new(move |max_size: Size| {
+            let mut calls = factory_calls.lock().unwrap();
+            *calls += 1;
+            // the source for the rotated screen is not available at first
+            if *calls == 2 {
+                anyhow::bail!("display server is restarting");
+            }
+            let frame = ImageBuffer::new(ImageFormat::BGRA, max_size.width, max_size.height, None);
+            Ok(Box::new(FakeSource { frame }) as Box<dyn Source>)
+        });
+        let capture = CaptureThread::spawn(source_factory, settings, Duration::from_millis(1), (64, 8).into(), 8, ImgprocBackend::Cpu)
+            .unwrap();
+        capture.take_frame(Duration::from_secs(5)).unwrap().unwrap();
+
+        let settings = CaptureSettings { rotation: Rotation::Rotate90, ..settings };
+        capture.set_settings(settings);
+        let captured = loop {
+            if let Some(v) = capture.take_frame(Duration::from_secs(5)).unwrap() {
+                break v;
+            }
+        };
+        assert_eq!(captured.settings, settings);
+        assert_eq!(*calls.lock().unwrap(), 3);
+        assert!(capture.is_running());
+    }
+
+    #[test]
+    fn test_hysteresis_with_embolden() {
+        let size: Size = (64, 8).into();
+        let embolden = Embolden { direction: StrokeDirection::Horizontal, radius: 1 };
+        let settings = CaptureSettings {
+            run_mode: RunMode::Mono(DitheringMethod::Bayers4),
+            rotation: Rotation::NoRotation,
+            naive_threshold: 128,
+            font_recovery: true,
+            embolden,
+            hysteresis: 8,
+        };
+        // the gray level of column 30 is kept, but it is no longer on the edge of the stroke in column 31
+        let mut frames = vec![columns_frame(size, &[(30, 0x90), (31, 0)]), columns_frame(size, &[(30, 0x90)])];
+        let last_frame = columns_frame(size, &[(30, 0x90)]);
+        let calls = Arc::new(Mutex::new(0));
+        let source_calls = calls.clone();
+        let source_factory: SourceFactory = Box::new(move |_| {
+            Ok(Box::new(SequenceSource { frames: std::mem::take(&mut frames), calls: source_calls.clone() })
+                as Box<dyn Source>)
+        });
+        let capture = CaptureThread::spawn(source_factory, settings, Duration::from_millis(1), size, 8, ImgprocBackend::Cpu)
+            .unwrap();
+        while *calls.lock().unwrap() < 2 {
+            capture.take_frame(Duration::from_secs(5)).unwrap();
+        }
+        // the frame taken before may be processed from an earlier source frame, but not the next one
+        capture.take_frame(Duration::from_secs(5)).unwrap();
+        let captured = capture.take_frame(Duration::from_secs(5)).unwrap().unwrap();
+
+        // the pixels around the changed stroke are updated as if processed without the stabilizer
+        let mut imgproc = CpuMonoImgproc::new(MonoImgprocOptions {
+            input_size: size,
+            output_size: size,
+            rotation: Rotation::NoRotation,
+        });
+        imgproc.set_font_recovery(true);
+        imgproc.set_embolden(embolden);
+        let mut expected = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, Some(8));
+        imgproc.process(&last_frame, &mut expected, DitheringMethod::Bayers4);
+        assert_eq!(captured.frame.data(), expected.data());
+    }
+}
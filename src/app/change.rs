@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+// What kind of change is between the displayed frame and the loaded frame, judged by row hashes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeKind {
+    Minor,       // e.g. typing
+    PageTurn,    // most of the rows changed, not by scrolling
+    Scroll(i32), // most of the changed rows are shifted by this many rows (positive: content moved down)
+}
+
+// rows with such common hashes (e.g. blank rows) are not used for matching
+const MAX_SAME_ROWS: usize = 8;
+
+// find the vertical shift matching most of the changed rows, return (shift, number of matched rows)
+pub fn find_scroll(old: &[u64], new: &[u64]) -> Option<(i32, usize)> {
+    assert_eq!(old.len(), new.len());
+    let mut positions = HashMap::<u64, Vec<i32>>::new();
+    for (y, hash) in old.iter().enumerate() {
+        positions.entry(*hash).or_default().push(y as i32);
+    }
+    let mut votes = HashMap::<i32, usize>::new();
+    for (y, hash) in new.iter().enumerate() {
+        if old[y] == *hash {
+            continue;
+        }
+        match positions.get(hash) {
+            Some(v) if v.len() <= MAX_SAME_ROWS => {
+                for pos in v {
+                    *votes.entry(y as i32 - pos).or_default() += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+    votes.into_iter().max_by_key(|(shift, count)| (*count, -shift.abs()))
+}
+
+pub fn analyze_change(old: &[u64], new: &[u64], page_turn_row_ratio: f32) -> ChangeKind {
+    if old.len() != new.len() || old.is_empty() {
+        return ChangeKind::Minor;
+    }
+    let changed = old.iter().zip(new).filter(|(a, b)| a != b).count();
+    if changed == 0 {
+        return ChangeKind::Minor;
+    }
+    match find_scroll(old, new) {
+        Some((shift, matched)) if matched * 2 >= changed => return ChangeKind::Scroll(shift),
+        _ => (),
+    }
+    if page_turn_row_ratio > 0.0 && changed as f32 >= new.len() as f32 * page_turn_row_ratio {
+        ChangeKind::PageTurn
+    } else {
+        ChangeKind::Minor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_change() {
+        // 0 for blank rows
+        let old = (0..100).map(|x| if x % 3 == 0 { 0 } else { x + 1000 }).collect::<Vec<u64>>();
+        assert_eq!(analyze_change(&old, &old, 0.6), ChangeKind::Minor);
+
+        let mut typing = old.clone();
+        typing[10] = 1;
+        assert_eq!(analyze_change(&old, &typing, 0.6), ChangeKind::Minor);
+
+        // scroll up by 5 rows
+        let scrolled = (0..100).map(|x| old.get(x + 5).copied().unwrap_or(0)).collect::<Vec<u64>>();
+        assert_eq!(analyze_change(&old, &scrolled, 0.6), ChangeKind::Scroll(-5));
+
+        let new_page = (0..100).map(|x| if x % 3 == 0 { 0 } else { x + 2000 }).collect::<Vec<u64>>();
+        assert_eq!(analyze_change(&old, &new_page, 0.6), ChangeKind::PageTurn);
+        assert_eq!(analyze_change(&old, &new_page, 0.0), ChangeKind::Minor);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::image::*;
+
+// Gray levels used by the content, to choose the fastest suitable display mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContentClass {
+    BlackWhite, // only black and white, suitable for A2 or DU
+    FourLevel,  // the 4 levels of DU4 (0x00, 0x50, 0xa0, 0xf0)
+    Gray,       // anything else
+}
+
+fn pixel_class(val: u8) -> ContentClass {
+    // only the upper 4 bits are used by the controller
+    match val >> 4 {
+        0x0 | 0xf => ContentClass::BlackWhite,
+        0x5 | 0xa => ContentClass::FourLevel,
+        _ => ContentClass::Gray,
+    }
+}
+
+// classify the rows of 8bpp images (all images must have the same size),
+// e.g. both the old content on the screen and the new content
+pub fn classify_rows(images: &[&dyn ConstImage], rows: std::ops::Range<i32>) -> ContentClass {
+    let mut lut = [ContentClass::BlackWhite; 256];
+    for (val, class) in lut.iter_mut().enumerate() {
+        *class = pixel_class(val as u8);
+    }
+    let mut res = ContentClass::BlackWhite;
+    for img in images {
+        if img.format() != ImageFormat::Mono8Bpp {
+            return ContentClass::Gray;
+        }
+        for y in rows.clone() {
+            let row = unsafe { std::slice::from_raw_parts(img.ptr(y), img.width() as usize) };
+            res = res.max(row.iter().map(|x| lut[*x as usize]).max().unwrap_or(res));
+            if res == ContentClass::Gray {
+                return res;
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_rows() {
+        let mut old = ImageBuffer::new(ImageFormat::Mono8Bpp, 16, 4, None);
+        let mut new = ImageBuffer::new(ImageFormat::Mono8Bpp, 16, 4, None);
+        old.fill(0xf0);
+        new.fill(0xff);
+        new.mut_data()[0] = 0x00;
+        assert_eq!(classify_rows(&[&old, &new], 0..4), ContentClass::BlackWhite);
+
+        old.mut_data()[16] = 0x50;
+        assert_eq!(classify_rows(&[&old, &new], 0..4), ContentClass::FourLevel);
+        assert_eq!(classify_rows(&[&old, &new], 2..4), ContentClass::BlackWhite);
+
+        new.mut_data()[48] = 0x80;
+        assert_eq!(classify_rows(&[&old, &new], 0..4), ContentClass::Gray);
+        assert_eq!(classify_rows(&[&old, &new], 0..3), ContentClass::FourLevel);
+    }
+}
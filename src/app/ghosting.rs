@@ -0,0 +1,184 @@
+use std::hash::Hasher;
+use std::time::{Duration, Instant};
+
+use crate::driver::it8915::DisplayMode;
+use crate::image::*;
+
+#[derive(Clone, Copy, Debug)]
+struct Tile {
+    hash: u64,
+    changed: bool,      // content changed since last displayed
+    fast_updates: u32,  // number of fast (A2, DU) updates since last quality refresh
+    last_update: Instant,
+}
+
+// Per-tile accounting of fast updates, to clean up ghosting of only the heavily updated areas
+pub struct GhostingTracker {
+    tile_size: i32,
+    screen_size: Size,
+    grid: Size, // number of tiles in each direction
+    tiles: Vec<Tile>,
+}
+
+impl GhostingTracker {
+    pub fn new(tile_size: i32, screen_size: Size) -> Self {
+        // the display area must be aligned to 32 pixels horizontally
+        let tile_size = i32::max((tile_size + 31) / 32 * 32, 32);
+        let grid = Size {
+            width: (screen_size.width + tile_size - 1) / tile_size,
+            height: (screen_size.height + tile_size - 1) / tile_size,
+        };
+        let mut res = GhostingTracker {
+            tile_size,
+            screen_size,
+            grid,
+            tiles: Vec::new(),
+        };
+        res.reset();
+        res
+    }
+
+    pub fn tile_size(&self) -> i32 {
+        self.tile_size
+    }
+
+    // forget everything, e.g. after the screen is re-initialized
+    pub fn reset(&mut self) {
+        let tile = Tile {
+            hash: 0,
+            changed: true,
+            fast_updates: 0,
+            last_update: Instant::now(),
+        };
+        self.tiles = vec![tile; (self.grid.width * self.grid.height) as usize];
+    }
+
+    fn tile_rect(&self, col: i32, row: i32) -> Rect {
+        let screen = Rect::new((0, 0).into(), self.screen_size);
+        Rect::new((col * self.tile_size, row * self.tile_size).into(), (self.tile_size, self.tile_size).into())
+            .intersect(&screen)
+    }
+
+    // indices of tiles intersecting with the area
+    fn tiles_in(&self, area: &Rect) -> impl Iterator<Item = usize> {
+        let cols = (area.pt.x / self.tile_size).max(0)
+            ..((area.right() + self.tile_size - 1) / self.tile_size).min(self.grid.width);
+        let rows = (area.pt.y / self.tile_size).max(0)
+            ..((area.bottom() + self.tile_size - 1) / self.tile_size).min(self.grid.height);
+        let grid_width = self.grid.width;
+        rows.flat_map(move |row| cols.clone().map(move |col| (row * grid_width + col) as usize))
+    }
+
+    // the frame loaded into the controller (in screen coordinate)
+    pub fn load(&mut self, frame: &impl ConstImage) {
+        assert_eq!(frame.size(), self.screen_size);
+        let bpp = frame.bpp();
+        for row in 0..self.grid.height {
+            let mut hashers = (0..self.grid.width)
+                .map(|_| std::collections::hash_map::DefaultHasher::new())
+                .collect::<Vec<_>>();
+            let y_end = i32::min((row + 1) * self.tile_size, self.screen_size.height);
+            for y in (row * self.tile_size)..y_end {
+                let line = unsafe { std::slice::from_raw_parts(frame.ptr(y), frame.pitch() as usize) };
+                for (col, hasher) in hashers.iter_mut().enumerate() {
+                    let rect = self.tile_rect(col as i32, row);
+                    let start = (rect.pt.x * bpp / 8) as usize;
+                    let end = ((rect.right() * bpp + 7) / 8) as usize;
+                    hasher.write(&line[start..end]);
+                }
+            }
+            for (col, hasher) in hashers.into_iter().enumerate() {
+                let tile = &mut self.tiles[(row * self.grid.width) as usize + col];
+                let hash = hasher.finish();
+                tile.changed |= tile.hash != hash;
+                tile.hash = hash;
+            }
+        }
+    }
+
+    // the area is displayed with the mode, using the content from last `load`
+    pub fn displayed(&mut self, area: &Rect, mode: DisplayMode, now: Instant) {
+        let indices = self.tiles_in(area).collect::<Vec<_>>();
+        for idx in indices {
+            let tile = &mut self.tiles[idx];
+            if mode.is_quality() {
+                tile.fast_updates = 0;
+            } else if tile.changed {
+                tile.fast_updates += 1;
+            } else {
+                continue;
+            }
+            tile.changed = false;
+            tile.last_update = now;
+        }
+    }
+
+    pub fn max_fast_updates(&self) -> u32 {
+        self.tiles.iter().map(|x| x.fast_updates).max().unwrap_or(0)
+    }
+
+    // areas to clean up: tiles with at least `threshold` fast updates, not updated since `quiet_delay`.
+    // at most one area per tile row (covering all such tiles in the row), so that they do not overlap
+    pub fn cleanup_areas(&self, now: Instant, threshold: u32, quiet_delay: Duration) -> Vec<Rect> {
+        if threshold == 0 {
+            return Vec::new();
+        }
+        let mut areas = Vec::new();
+        for row in 0..self.grid.height {
+            let cols = (0..self.grid.width)
+                .filter(|col| {
+                    let tile = &self.tiles[(row * self.grid.width + col) as usize];
+                    tile.fast_updates >= threshold
+                        && !tile.changed
+                        && now.saturating_duration_since(tile.last_update) >= quiet_delay
+                })
+                .collect::<Vec<_>>();
+            if let (Some(first), Some(last)) = (cols.first(), cols.last()) {
+                let left = self.tile_rect(*first, row);
+                let right = self.tile_rect(*last, row);
+                areas.push(Rect::new(left.pt, (right.right() - left.pt.x, left.size.height).into()));
+            }
+        }
+        areas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ghosting_tracker() {
+        let screen_size: Size = (200, 100).into();
+        let screen = Rect::new((0, 0).into(), screen_size);
+        let mut tracker = GhostingTracker::new(50, screen_size);
+        assert_eq!(tracker.tile_size(), 64);
+
+        let mut frame = ImageBuffer::new(ImageFormat::Mono8Bpp, 200, 100, None);
+        let now = Instant::now();
+        tracker.load(&frame);
+        tracker.displayed(&screen, DisplayMode::GC16, now);
+        assert_eq!(tracker.max_fast_updates(), 0);
+
+        for i in 0..3 {
+            // only change the pixel in the tile at (1, 1)
+            frame.mut_data()[70 * 200 + 100] = i + 1;
+            tracker.load(&frame);
+            // displayed row band covers all tiles in the row
+            tracker.displayed(&Rect::new((0, 64).into(), (200, 36).into()), DisplayMode::A2, now);
+        }
+        assert_eq!(tracker.max_fast_updates(), 3);
+
+        let quiet_delay = Duration::from_millis(100);
+        assert!(tracker.cleanup_areas(now, 3, quiet_delay).is_empty());
+        let later = now + quiet_delay;
+        assert_eq!(
+            tracker.cleanup_areas(later, 3, quiet_delay),
+            vec![Rect::new((64, 64).into(), (64, 36).into())]
+        );
+        assert!(tracker.cleanup_areas(later, 4, quiet_delay).is_empty());
+
+        tracker.displayed(&Rect::new((64, 64).into(), (64, 36).into()), DisplayMode::GC16, later);
+        assert_eq!(tracker.max_fast_updates(), 0);
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+use crate::image::*;
+
+// Track where the user is working, by the movement of the pointer and the text caret
+// (reported by applications via control socket), in source coordinate.
+// A pointer that is not moving says nothing about it, so only movements count as input.
+#[derive(Default)]
+pub struct InputTracker {
+    pointer: Option<Point>,
+    last_input: Option<(Point, Instant)>,
+}
+
+impl InputTracker {
+    pub fn update_pointer(&mut self, pos: Option<Point>, now: Instant) {
+        let pos = match pos {
+            Some(v) => v,
+            None => return,
+        };
+        if self.pointer.is_some_and(|x| x != pos) {
+            self.last_input = Some((pos, now));
+        }
+        self.pointer = Some(pos);
+    }
+
+    pub fn update_caret(&mut self, pos: Point, now: Instant) {
+        self.last_input = Some((pos, now));
+    }
+
+    // position of the last input, if within `delay`
+    pub fn recent_position(&self, now: Instant, delay: Duration) -> Option<Point> {
+        match self.last_input {
+            Some((pos, t)) if now.saturating_duration_since(t) <= delay => Some(pos),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_tracker() {
+        let mut tracker = InputTracker::default();
+        let now = Instant::now();
+        let delay = Duration::from_secs(1);
+        tracker.update_pointer(Some((10, 10).into()), now);
+        assert_eq!(tracker.recent_position(now, delay), None);
+        tracker.update_pointer(None, now);
+        tracker.update_pointer(Some((10, 10).into()), now);
+        assert_eq!(tracker.recent_position(now, delay), None);
+
+        tracker.update_pointer(Some((20, 10).into()), now);
+        assert_eq!(tracker.recent_position(now, delay), Some((20, 10).into()));
+        tracker.update_caret((100, 200).into(), now + delay);
+        assert_eq!(tracker.recent_position(now + delay * 2, delay), Some((100, 200).into()));
+        assert_eq!(tracker.recent_position(now + delay * 3, delay), None);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::driver::it8915::DisplayMode;
+
+// weight of the old samples after each new sample, so that the model follows the changes of the device
+const DECAY: f64 = 0.98;
+// minimal (decayed) number of samples for an estimation
+const MIN_SAMPLES: f64 = 3.0;
+// use the measurements of nearby temperatures if not measured at the current temperature
+const MAX_TEMPERATURE_DISTANCE: i32 = 3;
+
+// Least squares fit of latency = a + b * height (in milliseconds and pixels), with decayed samples
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+struct LatencyFit {
+    n: f64,
+    sum_h: f64,
+    sum_t: f64,
+    sum_hh: f64,
+    sum_ht: f64,
+}
+
+impl LatencyFit {
+    fn add(&mut self, height: f64, ms: f64) {
+        self.n = self.n * DECAY + 1.0;
+        self.sum_h = self.sum_h * DECAY + height;
+        self.sum_t = self.sum_t * DECAY + ms;
+        self.sum_hh = self.sum_hh * DECAY + height * height;
+        self.sum_ht = self.sum_ht * DECAY + height * ms;
+    }
+
+    fn estimate(&self, height: f64) -> Option<f64> {
+        if self.n < MIN_SAMPLES {
+            return None;
+        }
+        let var = self.n * self.sum_hh - self.sum_h * self.sum_h;
+        // all samples are of (almost) the same height
+        if var < self.n * self.n {
+            return Some(self.sum_t / self.n);
+        }
+        let b = (self.n * self.sum_ht - self.sum_h * self.sum_t) / var;
+        let a = (self.sum_t - b * self.sum_h) / self.n;
+        Some(f64::max(a + b * height, 0.0))
+    }
+}
+
+// Measured time of the controller being busy for each display mode and height of the display area,
+// per temperature (the waveforms depend on it). Persisted between runs.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct LatencyModel {
+    // temperature -> display mode -> fit
+    entries: BTreeMap<u8, BTreeMap<String, LatencyFit>>,
+    #[serde(skip)]
+    unsaved_samples: u32,
+}
+
+fn mode_key(mode: DisplayMode) -> String {
+    format!("{:?}", mode)
+}
+
+impl LatencyModel {
+    // $XDG_STATE_HOME/rabbitink/latency.json
+    pub fn default_path() -> Option<PathBuf> {
+        let state_dir = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".local").join("state")))?;
+        Some(state_dir.join("rabbitink").join("latency.json"))
+    }
+
+    // start from scratch if the file does not exist or is invalid
+    pub fn load(path: &Path) -> Self {
+        let content = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(_) => return Self::default(),
+        };
+        match serde_json::from_str::<Self>(&content) {
+            Ok(v) => {
+                info!("Loaded latency model from {:?}, {} temperatures", path, v.entries.len());
+                v
+            }
+            Err(e) => {
+                warn!("Invalid latency model {:?}: {}, ignored", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        self.unsaved_samples = 0;
+        Ok(())
+    }
+
+    pub fn unsaved_samples(&self) -> u32 {
+        self.unsaved_samples
+    }
+
+    pub fn record(&mut self, temperature: u8, mode: DisplayMode, height: i32, latency: Duration) {
+        self.entries
+            .entry(temperature)
+            .or_default()
+            .entry(mode_key(mode))
+            .or_default()
+            .add(height as f64, latency.as_secs_f64() * 1000.0);
+        self.unsaved_samples += 1;
+    }
+
+    pub fn estimate(&self, temperature: u8, mode: DisplayMode, height: i32) -> Option<Duration> {
+        let key = mode_key(mode);
+        let t = temperature as i32;
+        let low = (t - MAX_TEMPERATURE_DISTANCE).max(0) as u8;
+        let high = (t + MAX_TEMPERATURE_DISTANCE).min(255) as u8;
+        let ms = self
+            .entries
+            .range(low..=high)
+            .filter_map(|(temp, fits)| Some(((*temp as i32 - t).abs(), fits.get(&key)?.estimate(height as f64)?)))
+            .min_by_key(|(distance, _)| *distance)?
+            .1;
+        Some(Duration::from_secs_f64(ms / 1000.0))
+    }
+
+    // estimated latency of each measured mode, for a single row and the full screen
+    pub fn describe(&self, temperature: u8, screen_height: i32) -> serde_json::Value {
+        let modes = [
+            DisplayMode::A2,
+            DisplayMode::DU,
+            DisplayMode::DU4,
+            DisplayMode::GL16,
+            DisplayMode::GLR16,
+            DisplayMode::GLD16,
+            DisplayMode::GC16,
+        ];
+        let to_ms = |x: Option<Duration>| x.map(|x| x.as_secs_f64() * 1000.0);
+        modes
+            .into_iter()
+            .filter_map(|mode| {
+                let row = self.estimate(temperature, mode, 1)?;
+                let full = self.estimate(temperature, mode, screen_height);
+                Some((mode_key(mode), serde_json::json!([to_ms(Some(row)), to_ms(full)])))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_model() {
+        let mut model = LatencyModel::default();
+        assert_eq!(model.estimate(25, DisplayMode::A2, 100), None);
+        for height in [100, 200, 400, 800, 1200] {
+            // 130ms + 0.05ms per row
+            model.record(25, DisplayMode::A2, height, Duration::from_micros(130_000 + height as u64 * 50));
+        }
+        let estimated = model.estimate(27, DisplayMode::A2, 600).unwrap().as_secs_f64() * 1000.0;
+        assert!((estimated - 160.0).abs() < 0.1, "{}", estimated);
+        assert_eq!(model.estimate(29, DisplayMode::A2, 600), None);
+        assert_eq!(model.estimate(25, DisplayMode::DU, 600), None);
+
+        let path = std::env::temp_dir().join(format!("rabbitink-test-latency-{}.json", std::process::id()));
+        model.save(&path).unwrap();
+        assert_eq!(model.unsaved_samples(), 0);
+        let loaded = LatencyModel::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            loaded.estimate(25, DisplayMode::A2, 600).unwrap().as_millis(),
+            model.estimate(25, DisplayMode::A2, 600).unwrap().as_millis()
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::image::*;
+use crate::imgproc::Rotation;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskKind {
+    Ignore,   // changes in the area are never displayed
+    Throttle, // changes in the area are displayed at most once per `interval`
+    Quality,  // changes in the area are always displayed with quality mode (e.g. GL16)
+}
+
+impl FromStr for MaskKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "ignore" => MaskKind::Ignore,
+            "throttle" => MaskKind::Throttle,
+            "quality" => MaskKind::Quality,
+            _ => anyhow::bail!("Unknown mask kind: {}", s),
+        })
+    }
+}
+
+// A rectangle in source coordinate, see `[[policy.masks]]` in config file
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaskOptions {
+    pub kind: MaskKind,
+    pub rect: [i32; 4], // x, y, w, h
+    // in milliseconds, for throttle
+    #[serde(default)]
+    pub interval: u64,
+}
+
+pub struct Mask {
+    pub options: MaskOptions,
+    pub area: Rect, // in screen coordinate, aligned to 32 pixels horizontally
+    last_release: Option<Instant>,
+}
+
+impl Mask {
+    pub fn new(options: MaskOptions, rotation: Rotation, screen_size: Size) -> Self {
+        let [x, y, w, h] = options.rect;
+        let area = rotation
+            .map_rect(Rect::new((x, y).into(), (w, h).into()), screen_size)
+            .align_x(32, screen_size.width);
+        Mask {
+            options,
+            area,
+            last_release: None,
+        }
+    }
+}
+
+// keep the content of the ignored (or throttled) areas in the new frame the same as the loaded frame,
+// so that changes in them are not considered at all
+pub fn apply_masks(masks: &mut [Mask], new_frame: &mut ImageBuffer, loaded_frame: Option<&ImageBuffer>, now: Instant) {
+    let loaded_frame = match loaded_frame {
+        Some(v) if v.size() == new_frame.size() && v.format() == new_frame.format() => v,
+        _ => return,
+    };
+    for mask in masks.iter_mut() {
+        let keep = match mask.options.kind {
+            MaskKind::Ignore => true,
+            MaskKind::Throttle => {
+                let interval = Duration::from_millis(mask.options.interval);
+                match mask.last_release {
+                    Some(t) if now.saturating_duration_since(t) < interval => true,
+                    _ => {
+                        mask.last_release = Some(now);
+                        false
+                    }
+                }
+            }
+            MaskKind::Quality => false,
+        };
+        if keep && !mask.area.is_empty() {
+            new_frame
+                .mut_subimg(mask.area.pt, mask.area.size)
+                .copy_from(&loaded_frame.subimg(mask.area.pt, mask.area.size));
+        }
+    }
+}
+
+pub fn quality_areas(masks: &[Mask]) -> Vec<Rect> {
+    masks
+        .iter()
+        .filter(|x| x.options.kind == MaskKind::Quality && !x.area.is_empty())
+        .map(|x| x.area)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_masks() {
+        let options = MaskOptions {
+            kind: MaskKind::Throttle,
+            rect: [10, 0, 20, 2],
+            interval: 1000,
+        };
+        let mut masks = vec![Mask::new(options, Rotation::Rotate180, (64, 4).into())];
+        assert_eq!(masks[0].area, Rect::new((32, 2).into(), (32, 2).into()));
+
+        let loaded = ImageBuffer::new(ImageFormat::Mono8Bpp, 64, 4, None);
+        let now = Instant::now();
+        let mut new_frame = ImageBuffer::new(ImageFormat::Mono8Bpp, 64, 4, None);
+        new_frame.fill(0xff);
+        // released for the first time
+        apply_masks(&mut masks, &mut new_frame, Some(&loaded), now);
+        assert!(new_frame.data().iter().all(|x| *x == 0xff));
+
+        apply_masks(&mut masks, &mut new_frame, Some(&loaded), now + Duration::from_millis(500));
+        assert_eq!(new_frame.data()[2 * 64 + 40], 0);
+        assert_eq!(new_frame.data()[2 * 64 + 20], 0xff);
+        assert_eq!(new_frame.data()[40], 0xff);
+    }
+}
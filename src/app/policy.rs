@@ -0,0 +1,916 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::change::ChangeKind;
+use super::content::ContentClass;
+use super::ghosting::GhostingTracker;
+use super::latency::LatencyModel;
+use super::mask::MaskOptions;
+use super::RowSet;
+use crate::driver::it8915::DisplayMode;
+use crate::image::*;
+use crate::imgproc::stroke::StrokeDirection;
+use crate::run_mode::RunMode;
+
+// Tunable knobs of the display policy, see `[policy]` in config file.
+// Durations are in milliseconds.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyOptions {
+    // name of the RefreshPolicy, see `create_policy`
+    pub refresh_policy: String,
+    // do a full refresh after the screen is not updated for this period
+    pub full_refresh_idle_delay: u64,
+    // prevent duplicated full refresh request within this period, if nothing is changed
+    pub full_refresh_min_interval: u64,
+    // when considering "row ratio" below, "expand" each pixel row to this height,
+    // so that the "row ratio" is more close to what we assume
+    pub text_row_typical_height: i32,
+    // do a slow (e.g. DU instead of A2) refresh if more than this ratio of rows are changed.
+    // only used until enough latency is measured, if `latency_mode_selection` is enabled
+    pub slow_refresh_row_ratio_threshold: f32,
+    // choose between the fast and slow mode by the measured latency, see `PolicyContext::latency_prefers_fast`
+    pub latency_mode_selection: bool,
+    // gray level threshold (0-255) of mono run modes without dithering (e.g. mono_naive)
+    pub naive_dithering_threshold: u8,
+    // in mono run modes, threshold the gray pixels of anti-aliased glyph edges instead of dithering them,
+    // see `imgproc::antialias`. Off by default as it costs CPU time on rows with high contrast
+    pub font_recovery: bool,
+    // in mono run modes, dilate dark strokes by `embolden_radius` pixels in this direction before binarization,
+    // so that thin fonts and hairlines do not break up, see `imgproc::stroke`
+    pub embolden: StrokeDirection,
+    pub embolden_radius: u8,
+    // keep the displayed pixels while their gray level (0-255) stays within this distance of the level they were
+    // made from, and confine error diffusion to the changed regions, which avoids shimmering. 0 to disable
+    pub stabilize_hysteresis: u8,
+    // ghosting is accounted per tile of this size (in pixels, rounded up to multiple of 32)
+    pub ghosting_tile_size: i32,
+    // GC16 refresh a tile after this many fast (A2, DU) updates. 0 to disable
+    pub ghosting_update_threshold: u32,
+    // ...only after the tile is not updated for this period
+    pub ghosting_quiet_delay: u64,
+    // display the changes with a fast mode first (in gray run mode, DU with thresholded content),
+    // then do a quality (GL16) pass after the screen is not updated for `progressive_quality_delay`
+    pub progressive_refresh: bool,
+    pub progressive_quality_delay: u64,
+    // the mode for quality updates in gray run mode, e.g. "GLR16" or "GLD16" (regal) to reduce ghosting.
+    // falls back to GL16 if not supported by the device
+    pub gray_update_mode: DisplayMode,
+    // in gray run mode, GC16 refresh the changed rows if more than this ratio of rows changed
+    // (not by scrolling), e.g. turning pages in PDF. 0 to disable
+    pub page_turn_row_ratio: f32,
+    // the mode for scrolled content, e.g. "DU". if not set, scrolling is treated like other changes
+    pub scroll_mode: Option<DisplayMode>,
+    // hold scrolled frames until this period after last update, so that rapid scroll frames are coalesced.
+    // 0 to disable
+    pub scroll_coalesce_delay: u64,
+    // rows changing periodically (spinners, blinking cursors, clocks...) are updated at most once per
+    // this period, and only when nothing else to display. 0 to disable
+    pub animation_interval: u64,
+    // never update the animated rows (until they stop changing)
+    pub animation_freeze: bool,
+    // static masks, see `MaskOptions`
+    pub masks: Vec<MaskOptions>,
+    // changed rows within this distance (in pixels) of recent input (pointer movement or text caret)
+    // are displayed first, separately from other changes. 0 to disable
+    pub input_proximity: i32,
+    // input is considered recent for this period
+    pub input_recent_delay: u64,
+    // while input is recent, changes far from it are held until the screen is not updated for this period,
+    // so that they are batched and do not keep the controller busy
+    pub input_defer_delay: u64,
+}
+
+impl Default for PolicyOptions {
+    fn default() -> Self {
+        PolicyOptions {
+            refresh_policy: "default".to_string(),
+            full_refresh_idle_delay: 120_000,
+            full_refresh_min_interval: 3_000,
+            text_row_typical_height: 40,
+            slow_refresh_row_ratio_threshold: 0.5,
+            latency_mode_selection: true,
+            naive_dithering_threshold: 128,
+            font_recovery: false,
+            embolden: StrokeDirection::None,
+            embolden_radius: 1,
+            stabilize_hysteresis: 0,
+            ghosting_tile_size: 128,
+            ghosting_update_threshold: 30,
+            ghosting_quiet_delay: 2_000,
+            progressive_refresh: false,
+            progressive_quality_delay: 500,
+            gray_update_mode: DisplayMode::GL16,
+            page_turn_row_ratio: 0.6,
+            scroll_mode: None,
+            scroll_coalesce_delay: 0,
+            animation_interval: 1_000,
+            animation_freeze: false,
+            masks: Vec::new(),
+            input_proximity: 150,
+            input_recent_delay: 5_000,
+            input_defer_delay: 300,
+        }
+    }
+}
+
+impl PolicyOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !matches!(self.gray_update_mode, DisplayMode::GL16 | DisplayMode::GLR16 | DisplayMode::GLD16) {
+            anyhow::bail!("Invalid gray_update_mode {:?}, should be one of GL16, GLR16, GLD16", self.gray_update_mode);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct UpdateRecord {
+    pub time: Instant,
+    pub area: Rect,
+    pub mode: DisplayMode,
+}
+
+pub struct UpdateHistory {
+    records: VecDeque<UpdateRecord>,
+    last_update: Instant,
+    full_refreshed: bool,
+}
+
+impl UpdateHistory {
+    const MAX_RECORDS: usize = 64;
+
+    pub fn new(now: Instant) -> Self {
+        UpdateHistory {
+            records: VecDeque::new(),
+            last_update: now,
+            full_refreshed: false,
+        }
+    }
+
+    pub fn record(&mut self, record: UpdateRecord, full_refresh: bool) {
+        if self.records.len() >= Self::MAX_RECORDS {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+        self.last_update = record.time;
+        self.full_refreshed = full_refresh;
+    }
+
+    // a cleanup only improves the displayed content: it is kept in the records, but is not
+    // an update of its own, so it neither delays nor replaces the idle full refresh
+    pub fn record_cleanup(&mut self, record: UpdateRecord) {
+        if self.records.len() >= Self::MAX_RECORDS {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    // most recent first
+    pub fn records(&self) -> impl Iterator<Item = &UpdateRecord> {
+        self.records.iter().rev()
+    }
+
+    pub fn last_update(&self) -> Instant {
+        self.last_update
+    }
+
+    // whether the last update is a full refresh, i.e. the screen is clean
+    pub fn full_refreshed(&self) -> bool {
+        self.full_refreshed
+    }
+}
+
+pub struct PolicyContext<'a> {
+    pub now: Instant,
+    pub run_mode: RunMode,
+    pub screen_size: Size,
+    pub dirty_rows: &'a RowSet,      // rows loaded into the controller but not displayed yet (except held animations)
+    pub displaying_rows: &'a RowSet, // rows being displayed, empty if the controller is ready
+    pub quality_pending_rows: &'a RowSet, // rows displayed with fast modes since last quality pass
+    pub dirty_content: Option<ContentClass>, // gray levels of dirty rows (before and after), if inspected
+    pub dirty_change: ChangeKind,            // kind of change between the displayed and loaded frame
+    pub quality_areas: &'a [Rect],           // areas that should always be displayed with quality mode
+    pub refresh_requested: bool,     // full refresh is requested by user
+    pub run_mode_switched: bool,     // the run mode is just switched, the screen contains content of the old run mode
+    pub input_row: Option<i32>,      // screen row of recent input, see `InputTracker`
+    pub temperature: Option<u8>,     // temperature of the device, if known
+    pub latency: &'a LatencyModel,
+    pub history: &'a UpdateHistory,
+    pub ghosting: &'a GhostingTracker,
+    pub options: &'a PolicyOptions,
+}
+
+impl<'a> PolicyContext<'a> {
+    pub fn since_last_update(&self) -> Duration {
+        self.now.saturating_duration_since(self.history.last_update())
+    }
+
+    pub fn should_full_refresh(&self) -> bool {
+        let min_interval = Duration::from_millis(self.options.full_refresh_min_interval);
+        let idle_delay = Duration::from_millis(self.options.full_refresh_idle_delay);
+        (self.refresh_requested
+            && (!self.history.full_refreshed() || self.since_last_update() > min_interval))
+            || (self.dirty_rows.is_empty()
+                && self.since_last_update() > idle_delay
+                && !self.history.full_refreshed())
+    }
+
+    // the full-width area covering all dirty rows
+    pub fn dirty_span(&self) -> Option<Rect> {
+        let start = *self.dirty_rows.first()?;
+        let end = *self.dirty_rows.last()? + 1;
+        Some(Rect::new((0, start).into(), (self.screen_size.width, end - start).into()))
+    }
+
+    // the span of dirty rows near recent input.
+    // not used for scrolling or page turns, which change the whole screen anyway
+    fn input_near_rows(&self) -> Option<std::ops::Range<i32>> {
+        let row = self.input_row?;
+        let proximity = self.options.input_proximity;
+        if proximity <= 0 || self.dirty_change != ChangeKind::Minor {
+            return None;
+        }
+        let mut near = self.dirty_rows.range(row - proximity..row + proximity + 1);
+        let start = *near.next()?;
+        let end = near.next_back().map_or(start, |x| *x) + 1;
+        Some(start..end)
+    }
+
+    // the area to display first: the dirty rows near recent input, or all dirty rows
+    pub fn input_priority_span(&self) -> Option<Rect> {
+        let span = self.dirty_span()?;
+        match self.input_near_rows() {
+            Some(rows) => Some(Rect::new((0, rows.start).into(), (span.size.width, rows.len() as i32).into())),
+            None => Some(span),
+        }
+    }
+
+    // whether to hold the dirty rows because they are all far from recent input
+    pub fn input_defer(&self) -> bool {
+        let delay = Duration::from_millis(self.options.input_defer_delay);
+        self.input_row.is_some()
+            && self.options.input_proximity > 0
+            && self.dirty_change == ChangeKind::Minor
+            && !self.dirty_rows.is_empty()
+            && self.input_near_rows().is_none()
+            && self.since_last_update() < delay
+    }
+
+    // whether displaying the area now would overlap with rows being displayed
+    pub fn is_blocked(&self, area: &Rect) -> bool {
+        self.displaying_rows.range(area.pt.y..area.bottom()).next().is_some()
+    }
+
+    // after switching run mode, display the whole new frame with the transition mode at once
+    pub fn run_mode_transition(&self) -> Option<Decision> {
+        if !self.run_mode_switched {
+            return None;
+        }
+        let area = self.dirty_span()?;
+        if self.is_blocked(&area) {
+            return Some(Decision::WaitReady);
+        }
+        let mode = self.run_mode.display_mode_transition();
+        Some(Decision::Display(vec![DisplayRequest { area, mode }]))
+    }
+
+    pub fn fast_mode(&self) -> DisplayMode {
+        if self.options.progressive_refresh && self.run_mode.is_gray() {
+            DisplayMode::DU
+        } else {
+            self.run_mode.display_mode_fast()
+        }
+    }
+
+    // the mode to display the dirty rows in the area, according to the content if inspected
+    pub fn display_mode(&self, area: &Rect, small_change: bool) -> DisplayMode {
+        let in_quality_area = self.quality_areas.iter().any(|x| !x.intersect(area).is_empty());
+        if in_quality_area {
+            return self.gray_update_mode(DisplayMode::GL16);
+        }
+        match (self.dirty_change, self.options.scroll_mode) {
+            (ChangeKind::PageTurn, _) if self.run_mode.is_gray() => return DisplayMode::GC16,
+            (ChangeKind::Scroll(_), Some(mode)) => return mode,
+            _ => (),
+        }
+        let mode = match self.dirty_content {
+            Some(ContentClass::BlackWhite) if small_change => DisplayMode::A2,
+            Some(ContentClass::BlackWhite) => DisplayMode::DU,
+            Some(ContentClass::FourLevel) => DisplayMode::DU4,
+            _ if small_change => self.fast_mode(),
+            _ => self.run_mode.display_mode_slow(),
+        };
+        self.gray_update_mode(mode)
+    }
+
+    fn gray_update_mode(&self, mode: DisplayMode) -> DisplayMode {
+        if mode == DisplayMode::GL16 && self.run_mode.is_gray() {
+            self.options.gray_update_mode
+        } else {
+            mode
+        }
+    }
+
+    // wait for more scroll frames if the last update is too recent
+    pub fn scroll_coalesce(&self) -> Option<Decision> {
+        let delay = Duration::from_millis(self.options.scroll_coalesce_delay);
+        match self.dirty_change {
+            ChangeKind::Scroll(_) if self.since_last_update() < delay => Some(Decision::Idle),
+            _ => None,
+        }
+    }
+
+    // in progressive refresh, GL16 the rows displayed with fast modes, after the screen is stable
+    pub fn quality_pass(&self) -> Option<Decision> {
+        let delay = Duration::from_millis(self.options.progressive_quality_delay);
+        if !self.options.progressive_refresh || !self.dirty_rows.is_empty() || self.since_last_update() < delay {
+            return None;
+        }
+        let start = *self.quality_pending_rows.first()?;
+        let end = *self.quality_pending_rows.last()? + 1;
+        let area = Rect::new((0, start).into(), (self.screen_size.width, end - start).into());
+        if self.is_blocked(&area) {
+            return None;
+        }
+        let mode = self.gray_update_mode(DisplayMode::GL16);
+        Some(Decision::Cleanup(Cleanup::QualityPass, vec![DisplayRequest { area, mode }]))
+    }
+
+    // GC16 refresh the ghosting tiles, only when nothing else to display
+    pub fn ghosting_cleanup(&self) -> Option<Decision> {
+        if !self.dirty_rows.is_empty() {
+            return None;
+        }
+        let areas = self.ghosting.cleanup_areas(
+            self.now,
+            self.options.ghosting_update_threshold,
+            Duration::from_millis(self.options.ghosting_quiet_delay),
+        );
+        if areas.is_empty() || areas.iter().any(|x| self.is_blocked(x)) {
+            return None;
+        }
+        Some(Decision::Cleanup(
+            Cleanup::Ghosting,
+            areas
+                .into_iter()
+                .map(|area| DisplayRequest { area, mode: DisplayMode::GC16 })
+                .collect(),
+        ))
+    }
+
+    // whether the fast mode (e.g. A2) is better than the slow mode (e.g. DU) for the area,
+    // by the expected time until the screen is clean: the measured latency of the mode,
+    // plus for A2 (which leaves the most ghosting) a GC16 cleanup of the changed part of the screen.
+    // None if not enough latency is measured yet
+    pub fn latency_prefers_fast(&self, area: &Rect) -> Option<bool> {
+        if !self.options.latency_mode_selection {
+            return None;
+        }
+        let temperature = self.temperature?;
+        let (fast, slow) = (self.display_mode(area, true), self.display_mode(area, false));
+        if fast == slow {
+            return None;
+        }
+        let height = area.size.height;
+        let changed_ratio = self.num_dirty_rows_expanded(area).min(self.screen_size.height) as f64
+            / self.screen_size.height as f64;
+        let cleanup = self.latency.estimate(temperature, DisplayMode::GC16, height)?.as_secs_f64() * changed_ratio;
+        let cost = |mode: DisplayMode| -> Option<f64> {
+            let latency = self.latency.estimate(temperature, mode, height)?.as_secs_f64();
+            Some(if mode == DisplayMode::A2 { latency + cleanup } else { latency })
+        };
+        Some(cost(fast)? <= cost(slow)?)
+    }
+
+    // number of dirty rows in the area, each "expanded" to `text_row_typical_height`
+    pub fn num_dirty_rows_expanded(&self, area: &Rect) -> i32 {
+        let text_row_typical_height = self.options.text_row_typical_height;
+        self.dirty_rows
+            .range(area.pt.y..area.bottom())
+            .map(|x| *x / text_row_typical_height)
+            .collect::<RowSet>()
+            .len() as i32
+            * text_row_typical_height
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayRequest {
+    pub area: Rect,
+    pub mode: DisplayMode,
+}
+
+// why areas without new content are displayed again
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cleanup {
+    QualityPass, // see `PolicyContext::quality_pass`
+    Ghosting,    // see `PolicyContext::ghosting_cleanup`
+}
+
+impl Cleanup {
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Cleanup::QualityPass => "quality_pass",
+            Cleanup::Ghosting => "ghosting_cleanup",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Idle,                         // nothing to do until next frame
+    WaitReady,                    // wait for the controller to be ready, then decide again with the newest frame
+    FullRefresh,                  // GC16 the whole screen, blocking
+    Display(Vec<DisplayRequest>), // display these areas now, the rows in the areas are no longer dirty
+    // display these areas again, only when no dirty rows are to be displayed
+    Cleanup(Cleanup, Vec<DisplayRequest>),
+}
+
+pub trait RefreshPolicy {
+    fn name(&self) -> &'static str;
+    fn decide(&mut self, ctx: &PolicyContext) -> Decision;
+}
+
+// The default heuristic: A2 (or the fast mode of run mode) for small changes,
+// DU (or the slow mode) if a large portion of the screen is changed,
+// GC16 full refresh if requested or after idle for a while.
+pub struct DefaultPolicy;
+
+impl RefreshPolicy for DefaultPolicy {
+    fn name(&self) -> &'static str {
+        "default"
+    }
+
+    fn decide(&mut self, ctx: &PolicyContext) -> Decision {
+        if ctx.should_full_refresh() {
+            return Decision::FullRefresh;
+        }
+        if let Some(v) = ctx.run_mode_transition() {
+            return v;
+        }
+        if let Some(v) = ctx.quality_pass().or_else(|| ctx.ghosting_cleanup()) {
+            return v;
+        }
+        let area = match ctx.input_priority_span() {
+            Some(v) => v,
+            None => return Decision::Idle,
+        };
+        if ctx.input_defer() {
+            return Decision::Idle;
+        }
+        if ctx.is_blocked(&area) {
+            return Decision::WaitReady;
+        }
+        if let Some(v) = ctx.scroll_coalesce() {
+            return v;
+        }
+        let threshold =
+            (ctx.screen_size.height as f32 * ctx.options.slow_refresh_row_ratio_threshold) as i32;
+        let small_change = ctx
+            .latency_prefers_fast(&area)
+            .unwrap_or_else(|| ctx.num_dirty_rows_expanded(&area) < threshold);
+        let mode = ctx.display_mode(&area, small_change);
+        Decision::Display(vec![DisplayRequest { area, mode }])
+    }
+}
+
+// Always use the fast (or slow) mode of the run mode, regardless of the changed area
+pub struct FixedModePolicy {
+    fast: bool,
+}
+
+impl RefreshPolicy for FixedModePolicy {
+    fn name(&self) -> &'static str {
+        if self.fast {
+            "fast"
+        } else {
+            "slow"
+        }
+    }
+
+    fn decide(&mut self, ctx: &PolicyContext) -> Decision {
+        if ctx.should_full_refresh() {
+            return Decision::FullRefresh;
+        }
+        if let Some(v) = ctx.run_mode_transition() {
+            return v;
+        }
+        if let Some(v) = ctx.quality_pass().or_else(|| ctx.ghosting_cleanup()) {
+            return v;
+        }
+        let area = match ctx.input_priority_span() {
+            Some(v) => v,
+            None => return Decision::Idle,
+        };
+        if ctx.input_defer() {
+            return Decision::Idle;
+        }
+        if ctx.is_blocked(&area) {
+            return Decision::WaitReady;
+        }
+        if let Some(v) = ctx.scroll_coalesce() {
+            return v;
+        }
+        let mode = ctx.display_mode(&area, self.fast);
+        Decision::Display(vec![DisplayRequest { area, mode }])
+    }
+}
+
+pub const POLICY_NAMES: &[&str] = &["default", "fast", "slow"];
+
+pub fn create_policy(name: &str) -> anyhow::Result<Box<dyn RefreshPolicy>> {
+    Ok(match name {
+        "default" => Box::new(DefaultPolicy),
+        "fast" => Box::new(FixedModePolicy { fast: true }),
+        "slow" => Box::new(FixedModePolicy { fast: false }),
+        _ => anyhow::bail!("Unknown refresh policy {}, available: {:?}", name, POLICY_NAMES),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgproc::DitheringMethod;
+
+    struct Fixture {
+        now: Instant,
+        dirty_rows: RowSet,
+        displaying_rows: RowSet,
+        quality_pending_rows: RowSet,
+        history: UpdateHistory,
+        ghosting: GhostingTracker,
+        latency: LatencyModel,
+        options: PolicyOptions,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let now = Instant::now();
+            Fixture {
+                now,
+                dirty_rows: RowSet::new(),
+                displaying_rows: RowSet::new(),
+                quality_pending_rows: RowSet::new(),
+                history: UpdateHistory::new(now),
+                ghosting: GhostingTracker::new(128, (1600, 1200).into()),
+                latency: LatencyModel::default(),
+                options: PolicyOptions::default(),
+            }
+        }
+
+        fn ctx(&self) -> PolicyContext<'_> {
+            PolicyContext {
+                now: self.now,
+                run_mode: RunMode::Mono(DitheringMethod::Bayers4),
+                screen_size: (1600, 1200).into(),
+                dirty_rows: &self.dirty_rows,
+                displaying_rows: &self.displaying_rows,
+                quality_pending_rows: &self.quality_pending_rows,
+                dirty_content: None,
+                dirty_change: ChangeKind::Minor,
+                quality_areas: &[],
+                refresh_requested: false,
+                run_mode_switched: false,
+                input_row: None,
+                temperature: Some(25),
+                latency: &self.latency,
+                history: &self.history,
+                ghosting: &self.ghosting,
+                options: &self.options,
+            }
+        }
+    }
+
+    // mode of the first display request, panics on any other decision
+    fn display_mode(decision: Decision) -> DisplayMode {
+        match decision {
+            Decision::Display(v) => v[0].mode,
+            v => panic!("unexpected decision {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::Idle);
+
+        fixture.dirty_rows.extend(100..120);
+        assert_eq!(
+            policy.decide(&fixture.ctx()),
+            Decision::Display(vec![DisplayRequest {
+                area: Rect::new((0, 100).into(), (1600, 20).into()),
+                mode: DisplayMode::A2,
+            }])
+        );
+
+        fixture.displaying_rows.extend(110..130);
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::WaitReady);
+
+        fixture.displaying_rows.clear();
+        fixture.dirty_rows.extend(0..1000);
+        assert_eq!(display_mode(policy.decide(&fixture.ctx())), DisplayMode::DU);
+    }
+
+    #[test]
+    fn test_latency_mode_selection() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        for height in [40, 1200] {
+            for _ in 0..3 {
+                fixture.latency.record(25, DisplayMode::A2, height, Duration::from_millis(140));
+                fixture.latency.record(25, DisplayMode::DU, height, Duration::from_millis(260));
+                fixture.latency.record(25, DisplayMode::GC16, height, Duration::from_millis(900));
+            }
+        }
+        // A2 + 10% of a GC16 cleanup is better than DU
+        fixture.dirty_rows.extend(100..180);
+        assert_eq!(display_mode(policy.decide(&fixture.ctx())), DisplayMode::A2);
+        // ...but not with a third (which is below `slow_refresh_row_ratio_threshold`)
+        fixture.dirty_rows.extend(180..460);
+        assert_eq!(display_mode(policy.decide(&fixture.ctx())), DisplayMode::DU);
+    }
+
+    #[test]
+    fn test_full_refresh() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.now += Duration::from_millis(fixture.options.full_refresh_idle_delay + 1);
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::FullRefresh);
+
+        fixture.history.record(
+            UpdateRecord {
+                time: fixture.now,
+                area: Rect::new((0, 0).into(), (1600, 1200).into()),
+                mode: DisplayMode::GC16,
+            },
+            true,
+        );
+        fixture.now += Duration::from_millis(fixture.options.full_refresh_idle_delay + 1);
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::Idle);
+
+        let ctx = PolicyContext {
+            refresh_requested: true,
+            ..fixture.ctx()
+        };
+        assert_eq!(policy.decide(&ctx), Decision::FullRefresh);
+    }
+
+    #[test]
+    fn test_cleanup_after_full_refresh() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        let screen = Rect::new((0, 0).into(), (1600, 1200).into());
+        fixture.history.record(UpdateRecord { time: fixture.now, area: screen, mode: DisplayMode::GC16 }, true);
+        fixture.now += Duration::from_millis(fixture.options.progressive_quality_delay);
+        fixture.history.record_cleanup(UpdateRecord {
+            time: fixture.now,
+            area: Rect::new((0, 100).into(), (1600, 20).into()),
+            mode: DisplayMode::GL16,
+        });
+        // the screen is still clean after the cleanup
+        fixture.now += Duration::from_millis(fixture.options.full_refresh_idle_delay + 1);
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::Idle);
+
+        // and a cleanup does not postpone the idle full refresh after a content update
+        fixture.history.record(UpdateRecord { time: fixture.now, area: screen, mode: DisplayMode::DU }, false);
+        let t_update = fixture.now;
+        fixture.now += Duration::from_millis(fixture.options.full_refresh_idle_delay);
+        fixture.history.record_cleanup(UpdateRecord { time: fixture.now, area: screen, mode: DisplayMode::GC16 });
+        fixture.now = t_update + Duration::from_millis(fixture.options.full_refresh_idle_delay + 1);
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::FullRefresh);
+    }
+
+    #[test]
+    fn test_content_aware_mode() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.dirty_rows.extend(100..120);
+        for (content, mode) in [
+            (ContentClass::BlackWhite, DisplayMode::A2),
+            (ContentClass::FourLevel, DisplayMode::DU4),
+            (ContentClass::Gray, DisplayMode::GL16),
+        ] {
+            let ctx = PolicyContext {
+                run_mode: RunMode::Gray,
+                dirty_content: Some(content),
+                ..fixture.ctx()
+            };
+            assert_eq!(display_mode(policy.decide(&ctx)), mode);
+        }
+    }
+
+    #[test]
+    fn test_page_turn() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.dirty_rows.extend(0..1000);
+        let ctx = PolicyContext {
+            run_mode: RunMode::Gray,
+            dirty_change: ChangeKind::PageTurn,
+            ..fixture.ctx()
+        };
+        assert_eq!(display_mode(policy.decide(&ctx)), DisplayMode::GC16);
+        let ctx = PolicyContext {
+            run_mode: RunMode::Gray,
+            dirty_change: ChangeKind::Scroll(10),
+            ..fixture.ctx()
+        };
+        assert_eq!(display_mode(policy.decide(&ctx)), DisplayMode::GL16);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.dirty_rows.extend(100..120);
+        fixture.options.scroll_mode = Some(DisplayMode::DU);
+        fixture.options.scroll_coalesce_delay = 100;
+        fixture.history.record(
+            UpdateRecord {
+                time: fixture.now,
+                area: Rect::new((0, 0).into(), (1600, 100).into()),
+                mode: DisplayMode::DU,
+            },
+            false,
+        );
+        let ctx = PolicyContext {
+            dirty_change: ChangeKind::Scroll(-20),
+            ..fixture.ctx()
+        };
+        assert_eq!(policy.decide(&ctx), Decision::Idle);
+
+        fixture.now += Duration::from_millis(100);
+        let ctx = PolicyContext {
+            dirty_change: ChangeKind::Scroll(-20),
+            ..fixture.ctx()
+        };
+        assert_eq!(display_mode(policy.decide(&ctx)), DisplayMode::DU);
+    }
+
+    #[test]
+    fn test_scroll_fixed_mode() {
+        let mut policy = create_policy("slow").unwrap();
+        let mut fixture = Fixture::new();
+        fixture.dirty_rows.extend(100..120);
+        fixture.options.scroll_mode = Some(DisplayMode::A2);
+        fixture.options.scroll_coalesce_delay = 100;
+        fixture.history.record(
+            UpdateRecord {
+                time: fixture.now,
+                area: Rect::new((0, 0).into(), (1600, 100).into()),
+                mode: DisplayMode::DU,
+            },
+            false,
+        );
+        let ctx = PolicyContext {
+            dirty_change: ChangeKind::Scroll(-20),
+            ..fixture.ctx()
+        };
+        assert_eq!(policy.decide(&ctx), Decision::Idle);
+
+        fixture.now += Duration::from_millis(100);
+        let ctx = PolicyContext {
+            dirty_change: ChangeKind::Scroll(-20),
+            ..fixture.ctx()
+        };
+        assert_eq!(display_mode(policy.decide(&ctx)), DisplayMode::A2);
+        // other changes keep the fixed mode
+        assert_eq!(display_mode(policy.decide(&fixture.ctx())), DisplayMode::DU);
+    }
+
+    #[test]
+    fn test_quality_area() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.dirty_rows.extend(100..120);
+        let quality_areas = [Rect::new((0, 110).into(), (320, 100).into())];
+        let ctx = PolicyContext {
+            quality_areas: &quality_areas,
+            ..fixture.ctx()
+        };
+        assert_eq!(display_mode(policy.decide(&ctx)), DisplayMode::GL16);
+    }
+
+    #[test]
+    fn test_gray_update_mode() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.options.gray_update_mode = DisplayMode::GLR16;
+        fixture.dirty_rows.extend(100..120);
+        let ctx = PolicyContext {
+            run_mode: RunMode::Gray,
+            ..fixture.ctx()
+        };
+        assert_eq!(display_mode(policy.decide(&ctx)), DisplayMode::GLR16);
+        // not used in mono run modes
+        assert_eq!(display_mode(policy.decide(&fixture.ctx())), DisplayMode::A2);
+    }
+
+    #[test]
+    fn test_run_mode_transition() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.dirty_rows.extend(0..1200);
+        let ctx = PolicyContext {
+            run_mode: RunMode::Gray,
+            run_mode_switched: true,
+            ..fixture.ctx()
+        };
+        assert_eq!(
+            policy.decide(&ctx),
+            Decision::Display(vec![DisplayRequest {
+                area: Rect::new((0, 0).into(), (1600, 1200).into()),
+                mode: DisplayMode::GL16,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_progressive_refresh() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        fixture.options.progressive_refresh = true;
+        fixture.dirty_rows.extend(100..120);
+        let ctx = PolicyContext {
+            run_mode: RunMode::Gray,
+            ..fixture.ctx()
+        };
+        assert_eq!(display_mode(policy.decide(&ctx)), DisplayMode::DU);
+
+        fixture.dirty_rows.clear();
+        fixture.quality_pending_rows.extend(100..120);
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::Idle);
+        fixture.now += Duration::from_millis(fixture.options.progressive_quality_delay);
+        assert_eq!(
+            policy.decide(&fixture.ctx()),
+            Decision::Cleanup(
+                Cleanup::QualityPass,
+                vec![DisplayRequest {
+                    area: Rect::new((0, 100).into(), (1600, 20).into()),
+                    mode: DisplayMode::GL16,
+                }]
+            )
+        );
+    }
+
+    #[test]
+    fn test_input_priority() {
+        let mut policy = DefaultPolicy;
+        let mut fixture = Fixture::new();
+        // typing at row 500, with a clock at the top being displayed
+        fixture.dirty_rows.extend(0..20);
+        fixture.dirty_rows.extend(490..510);
+        fixture.displaying_rows.extend(0..20);
+        let ctx = PolicyContext {
+            input_row: Some(500),
+            ..fixture.ctx()
+        };
+        assert_eq!(
+            policy.decide(&ctx),
+            Decision::Display(vec![DisplayRequest {
+                area: Rect::new((0, 490).into(), (1600, 20).into()),
+                mode: DisplayMode::A2,
+            }])
+        );
+        // without input, wait for the whole span
+        assert_eq!(policy.decide(&fixture.ctx()), Decision::WaitReady);
+
+        // distant changes are held for a while
+        fixture.displaying_rows.clear();
+        fixture.dirty_rows.retain(|x| *x < 20);
+        fixture.history.record(
+            UpdateRecord {
+                time: fixture.now,
+                area: Rect::new((0, 490).into(), (1600, 20).into()),
+                mode: DisplayMode::A2,
+            },
+            false,
+        );
+        let ctx = PolicyContext {
+            input_row: Some(500),
+            ..fixture.ctx()
+        };
+        assert_eq!(policy.decide(&ctx), Decision::Idle);
+        fixture.now += Duration::from_millis(fixture.options.input_defer_delay);
+        let ctx = PolicyContext {
+            input_row: Some(500),
+            ..fixture.ctx()
+        };
+        assert_eq!(
+            policy.decide(&ctx),
+            Decision::Display(vec![DisplayRequest {
+                area: Rect::new((0, 0).into(), (1600, 20).into()),
+                mode: DisplayMode::A2,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_create_policy() {
+        for name in POLICY_NAMES {
+            assert_eq!(create_policy(name).unwrap().name(), *name);
+        }
+        assert!(create_policy("unknown").is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::app::policy::PolicyOptions;
+use crate::imgproc::{ImgprocBackend, Rotation};
+use crate::run_mode::RunMode;
+
+// Config file layout (TOML):
+//
+//   profile = "13inch"            # optional, default profile (overridden by --profile)
+//   run_mode = "mono_bayers4"     # any option below can be set globally...
+//   [policy]
+//   full_refresh_idle_delay = 60000
+//   [profiles.13inch]             # ...or in a profile, which takes precedence
+//   device = "1,5"
+//   vcom = 1.5
+//   [profiles.13inch.policy]
+//   text_row_typical_height = 32
+//
+// Command line options take precedence over both.
+
+// All options that can be set via command line and config file.
+// Unset options are None, use the accessors to get the value with defaults.
+#[derive(clap::Args, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    #[arg(long, short)]
+    pub device: Option<String>,
+
+    #[arg(long, short)]
+    pub source: Option<String>,
+
+    #[arg(long)]
+    pub source_offx: Option<i32>,
+
+    #[arg(long)]
+    pub source_offy: Option<i32>,
+
+    #[arg(long)]
+    pub vcom: Option<f32>,
+
+    #[arg(long, short)]
+    pub rotation: Option<Rotation>,
+
+    // in milliseconds
+    #[arg(long)]
+    pub driver_poll_ready_interval: Option<u64>,
+
+    // in milliseconds
+    #[arg(long)]
+    pub source_poll_interval: Option<u64>,
+
+    #[arg(long)]
+    pub run_mode: Option<String>,
+
+    // legacy run mode file, read on SIGUSR1; disabled by default, the control socket replaces it
+    #[arg(long)]
+    pub run_mode_config: Option<PathBuf>,
+
+    // default: $XDG_RUNTIME_DIR/rabbitink.sock
+    #[arg(long)]
+    pub control_socket: Option<PathBuf>,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub no_control_socket: Option<bool>,
+
+    // image processing backend of mono and bayers4 gray run modes, default: auto (GPU if available)
+    #[arg(long)]
+    pub imgproc: Option<ImgprocBackend>,
+
+    // default: $XDG_STATE_HOME/rabbitink/latency.json
+    #[arg(long)]
+    pub latency_model: Option<PathBuf>,
+
+    // only configurable in config file
+    #[arg(skip)]
+    #[serde(skip_serializing)]
+    pub policy: PolicyOptions,
+}
+
+impl Settings {
+    pub fn device(&self) -> &str {
+        self.device.as_deref().unwrap_or("")
+    }
+
+    pub fn source_offset(&self) -> (i32, i32) {
+        (self.source_offx.unwrap_or(0), self.source_offy.unwrap_or(0))
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation.unwrap_or(Rotation::NoRotation)
+    }
+
+    pub fn driver_poll_ready_interval(&self) -> Duration {
+        Duration::from_millis(self.driver_poll_ready_interval.unwrap_or(1))
+    }
+
+    pub fn source_poll_interval(&self) -> Duration {
+        Duration::from_millis(self.source_poll_interval.unwrap_or(10))
+    }
+
+    pub fn run_mode(&self) -> anyhow::Result<RunMode> {
+        RunMode::from_str(self.run_mode.as_deref().unwrap_or("mono_bayers4"))
+    }
+
+    pub fn run_mode_config(&self) -> Option<PathBuf> {
+        self.run_mode_config.clone()
+    }
+
+    pub fn control_socket(&self) -> Option<PathBuf> {
+        if self.no_control_socket.unwrap_or(false) {
+            None
+        } else {
+            Some(self.control_socket.clone().unwrap_or_else(crate::control::default_socket_path))
+        }
+    }
+
+    pub fn imgproc(&self) -> ImgprocBackend {
+        self.imgproc.unwrap_or_default()
+    }
+
+    pub fn latency_model(&self) -> Option<PathBuf> {
+        self.latency_model.clone().or_else(crate::app::latency::LatencyModel::default_path)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.policy.validate()?;
+        // see `capture::Worker::effective_backend`
+        if self.imgproc() == ImgprocBackend::Gpu && self.policy.stabilize_hysteresis > 0 {
+            anyhow::bail!("stabilize_hysteresis is only supported by the CPU imgproc, not with --imgproc gpu");
+        }
+        Ok(())
+    }
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".config")))?;
+    Some(config_dir.join("rabbitink").join("config.toml"))
+}
+
+// recursively merge `src` into `dst`, values in `src` take precedence
+fn merge_value(dst: &mut toml::Value, src: toml::Value) {
+    match (dst, src) {
+        (toml::Value::Table(dst), toml::Value::Table(src)) => {
+            for (k, v) in src {
+                match dst.get_mut(&k) {
+                    Some(dst_v) => merge_value(dst_v, v),
+                    None => {
+                        dst.insert(k, v);
+                    }
+                }
+            }
+        }
+        (dst, src) => *dst = src,
+    }
+}
+
+// resolve the final settings from config file content, profile name (override the one in config) and cli options
+pub fn resolve_settings(
+    config_content: &str,
+    profile: Option<&str>,
+    cli: &Settings,
+) -> anyhow::Result<Settings> {
+    let mut value = toml::Value::Table(toml::from_str(config_content)?);
+    let table = value.as_table_mut().unwrap();
+    let mut profiles = table.remove("profiles").unwrap_or(toml::Value::Table(Default::default()));
+    let default_profile = table.remove("profile");
+    let profile = match (profile, &default_profile) {
+        (Some(name), _) => Some(name),
+        (None, Some(toml::Value::String(name))) => Some(name.as_str()),
+        (None, Some(v)) => anyhow::bail!("Invalid profile name {}", v),
+        (None, None) => None,
+    };
+    if let Some(profile) = profile {
+        let profile_value = profiles
+            .get_mut(profile)
+            .ok_or_else(|| anyhow::format_err!("Profile {} not found in config", profile))?;
+        debug!("Using config profile {}", profile);
+        merge_value(&mut value, std::mem::replace(profile_value, toml::Value::Boolean(false)));
+    }
+    merge_value(&mut value, toml::Value::try_from(cli)?);
+    let settings: Settings = value.try_into()?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+pub fn load_settings(path: Option<&Path>, profile: Option<&str>, cli: &Settings) -> anyhow::Result<Settings> {
+    let content = match path {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config file {:?}", path))?,
+        None => String::new(),
+    };
+    resolve_settings(&content, profile, cli).with_context(|| match path {
+        Some(path) => format!("Invalid config file {}", path.display()),
+        None => "Invalid settings".to_string(),
+    })
+}
+
+// Watch the config file for changes (via inotify in linux, or by checking mtime otherwise)
+pub struct ConfigWatcher {
+    path: PathBuf,
+    profile: Option<String>,
+    cli: Settings,
+    current: Settings,
+
+    #[cfg(target_os = "linux")]
+    inotify: nix::sys::inotify::Inotify,
+    #[cfg(not(target_os = "linux"))]
+    mtime: Option<std::time::SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path, profile: Option<&str>, cli: &Settings, current: &Settings) -> anyhow::Result<ConfigWatcher> {
+        info!("Watching config file {:?}", path);
+        Ok(ConfigWatcher {
+            path: path.to_path_buf(),
+            profile: profile.map(str::to_string),
+            cli: cli.clone(),
+            current: current.clone(),
+            #[cfg(target_os = "linux")]
+            inotify: Self::init_inotify(path)?,
+            #[cfg(not(target_os = "linux"))]
+            mtime: std::fs::metadata(path).and_then(|x| x.modified()).ok(),
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn init_inotify(path: &Path) -> anyhow::Result<nix::sys::inotify::Inotify> {
+        use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        // watch the directory instead of the file, because editors usually replace the file
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        // IN_CREATE is not watched: the file is still empty at that point, IN_CLOSE_WRITE follows
+        inotify.add_watch(dir, AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO)?;
+        Ok(inotify)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn changed(&mut self) -> bool {
+        let file_name = self.path.file_name();
+        let mut changed = false;
+        while let Ok(events) = self.inotify.read_events() {
+            changed |= events.iter().any(|e| e.name.as_deref() == file_name);
+        }
+        changed
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn changed(&mut self) -> bool {
+        let mtime = std::fs::metadata(&self.path).and_then(|x| x.modified()).ok();
+        std::mem::replace(&mut self.mtime, mtime) != mtime
+    }
+
+    // return (old, new) settings if the config file is changed, never blocks
+    pub fn poll(&mut self) -> Option<anyhow::Result<(Settings, Settings)>> {
+        if !self.changed() {
+            return None;
+        }
+        info!("Config file {:?} changed, reloading", self.path);
+        Some(
+            load_settings(Some(&self.path), self.profile.as_deref(), &self.cli)
+                .map(|new| (std::mem::replace(&mut self.current, new.clone()), new)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::it8915::DisplayMode;
+
+    const CONFIG: &str = r#"
+profile = "large"
+run_mode = "gray"
+vcom = 2.0
+
+[policy]
+full_refresh_idle_delay = 60000
+text_row_typical_height = 20
+
+[[policy.masks]]
+kind = "throttle"
+rect = [1400, 0, 200, 20]
+interval = 10000
+
+[profiles.large]
+vcom = 1.5
+rotation = "rotate90"
+
+[profiles.large.policy]
+text_row_typical_height = 32
+gray_update_mode = "GLR16"
+"#;
+
+    #[test]
+    fn test_resolve_settings() {
+        let settings = resolve_settings(CONFIG, None, &Settings::default()).unwrap();
+        assert_eq!(settings.run_mode().unwrap(), RunMode::Gray);
+        assert_eq!(settings.vcom, Some(1.5));
+        assert_eq!(settings.rotation(), Rotation::Rotate90);
+        assert_eq!(settings.policy.full_refresh_idle_delay, 60000);
+        assert_eq!(settings.policy.text_row_typical_height, 32);
+        assert_eq!(settings.policy.gray_update_mode, DisplayMode::GLR16);
+        assert_eq!(settings.policy.masks.len(), 1);
+        assert_eq!(settings.policy.masks[0].interval, 10000);
+        assert_eq!(settings.policy.full_refresh_min_interval, PolicyOptions::default().full_refresh_min_interval);
+        assert_eq!(settings.device(), "");
+    }
+
+    #[test]
+    fn test_resolve_settings_with_cli() {
+        let cli = Settings {
+            vcom: Some(1.8),
+            run_mode: Some("mono_bayers2".to_string()),
+            ..Settings::default()
+        };
+        let settings = resolve_settings(CONFIG, None, &cli).unwrap();
+        assert_eq!(settings.vcom, Some(1.8));
+        assert_eq!(settings.run_mode, cli.run_mode);
+        assert_eq!(settings.rotation(), Rotation::Rotate90);
+    }
+
+    #[test]
+    fn test_resolve_settings_errors() {
+        assert!(resolve_settings(CONFIG, Some("small"), &Settings::default()).is_err());
+        assert!(resolve_settings("unknown_option = 1", None, &Settings::default()).is_err());
+        assert!(resolve_settings("", None, &Settings::default()).is_ok());
+        assert!(resolve_settings("[policy]\ngray_update_mode = \"A2\"", None, &Settings::default()).is_err());
+        let gpu = Settings { imgproc: Some(ImgprocBackend::Gpu), ..Settings::default() };
+        assert!(resolve_settings("[policy]\nstabilize_hysteresis = 8", None, &gpu).is_err());
+        assert!(resolve_settings("[policy]\nstabilize_hysteresis = 8", None, &Settings::default()).is_ok());
+    }
+}
@@ -0,0 +1,411 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use log::{debug, info, warn};
+
+use crate::app::mask::{MaskKind, MaskOptions};
+use crate::image::{Point, Rect};
+use crate::imgproc::Rotation;
+use crate::run_mode::RunMode;
+
+// Protocol: each request is a single line of text (e.g. "set-run-mode gray"),
+// each response or event is a single line of JSON.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    SetRunMode(RunMode),
+    Refresh(Option<Rect>), // full screen if None, otherwise the rect in source coordinate
+    Pause,
+    Resume,
+    SetRotation(Rotation),
+    SetRefreshPolicy(String),
+    AddMask(MaskOptions),
+    ClearMasks,
+    SetCaret(Point), // text caret position in source coordinate, reported by applications
+    Status,
+    Subscribe,
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<&str>>();
+        Ok(match words.as_slice() {
+            ["set-run-mode", run_mode] => Command::SetRunMode(RunMode::from_str(run_mode)?),
+            ["refresh"] => Command::Refresh(None),
+            ["refresh", x, y, w, h] => Command::Refresh(Some(Rect::new(
+                (x.parse::<i32>()?, y.parse::<i32>()?).into(),
+                (w.parse::<i32>()?, h.parse::<i32>()?).into(),
+            ))),
+            ["pause"] => Command::Pause,
+            ["resume"] => Command::Resume,
+            ["set-rotation", rotation] => Command::SetRotation(
+                <Rotation as clap::ValueEnum>::from_str(rotation, true)
+                    .map_err(anyhow::Error::msg)?,
+            ),
+            ["set-refresh-policy", name] => Command::SetRefreshPolicy(name.to_string()),
+            ["add-mask", kind, x, y, w, h, interval @ ..] if interval.len() <= 1 => Command::AddMask(MaskOptions {
+                kind: MaskKind::from_str(kind)?,
+                rect: [x.parse()?, y.parse()?, w.parse()?, h.parse()?],
+                interval: interval.first().map(|x| x.parse()).transpose()?.unwrap_or(0),
+            }),
+            ["clear-masks"] => Command::ClearMasks,
+            ["set-caret", x, y] => Command::SetCaret((x.parse::<i32>()?, y.parse::<i32>()?).into()),
+            ["status"] => Command::Status,
+            ["subscribe"] => Command::Subscribe,
+            _ => anyhow::bail!("Unsupported command: {}", s),
+        })
+    }
+}
+
+pub type ClientId = u64;
+
+// clients not reading their responses and events are disconnected when this much output is queued
+const MAX_OUTPUT_SIZE: usize = 1 << 20;
+
+struct Client {
+    id: ClientId,
+    stream: UnixStream,
+    pending: Vec<u8>, // received bytes without newline yet
+    output: Vec<u8>,  // bytes not written yet, since the stream is nonblocking
+    subscribed: bool,
+    read_closed: bool,  // no more commands, but the replies are still sent
+    write_failed: bool,
+}
+
+impl Client {
+    // subscribers may close their side after the command, and still wait for events
+    fn is_done(&self) -> bool {
+        self.write_failed || (self.read_closed && !self.subscribed && self.output.is_empty())
+    }
+
+    fn send(&mut self, msg: &serde_json::Value) {
+        if self.write_failed {
+            return;
+        }
+        self.output.extend_from_slice(msg.to_string().as_bytes());
+        self.output.push(b'\n');
+        self.flush();
+        if self.output.len() > MAX_OUTPUT_SIZE {
+            warn!("Control client {} is not reading, disconnecting", self.id);
+            self.write_failed = true;
+        }
+    }
+
+    // write as much queued output as the stream takes now
+    fn flush(&mut self) {
+        let mut written = 0;
+        while written < self.output.len() {
+            match self.stream.write(&self.output[written..]) {
+                Ok(0) => {
+                    self.write_failed = true;
+                    break;
+                }
+                Ok(n) => written += n,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    debug!("Failed to write to control client {}: {}", self.id, e);
+                    self.write_failed = true;
+                    break;
+                }
+            }
+        }
+        self.output.drain(..written);
+    }
+
+    fn read_lines(&mut self) -> Vec<String> {
+        let mut buf = [0_u8; 1024];
+        while !self.read_closed {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.read_closed = true;
+                    break;
+                }
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    debug!("Failed to read from control client {}: {}", self.id, e);
+                    self.read_closed = true;
+                    break;
+                }
+            }
+        }
+        let mut lines = Vec::new();
+        while let Some(pos) = self.pending.iter().position(|x| *x == b'\n') {
+            let line = self.pending.drain(..=pos).collect::<Vec<u8>>();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        lines
+    }
+}
+
+pub struct ControlServer {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+    next_client_id: ClientId,
+}
+
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("rabbitink.sock"),
+        None => std::env::temp_dir().join(format!("rabbitink-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+impl ControlServer {
+    pub fn bind(path: &Path) -> anyhow::Result<ControlServer> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                anyhow::bail!("Control socket {:?} is used by another instance", path);
+            }
+            // stale socket from a previous run
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        info!("Listening on control socket {:?}", path);
+        Ok(ControlServer {
+            path: path.to_path_buf(),
+            listener,
+            clients: Vec::new(),
+            next_client_id: 0,
+        })
+    }
+
+    // accept new clients and read available commands, never blocks
+    pub fn poll(&mut self) -> Vec<(ClientId, anyhow::Result<Command>)> {
+        // closed clients are only removed here, after their replies are written, so that the commands
+        // sent right before closing (e.g. `echo status | nc -U`) can still be replied
+        for client in self.clients.iter_mut().filter(|c| !c.output.is_empty()) {
+            client.flush();
+        }
+        self.clients.retain(|c| !c.is_done());
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!("Cannot set control client nonblocking: {}", e);
+                        continue;
+                    }
+                    debug!("New control client {}", self.next_client_id);
+                    self.clients.push(Client {
+                        id: self.next_client_id,
+                        stream,
+                        pending: Vec::new(),
+                        output: Vec::new(),
+                        subscribed: false,
+                        read_closed: false,
+                        write_failed: false,
+                    });
+                    self.next_client_id += 1;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Failed to accept control client: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut commands = Vec::new();
+        for client in self.clients.iter_mut() {
+            for line in client.read_lines() {
+                if !line.is_empty() {
+                    debug!("Control client {} command: {}", client.id, line);
+                    commands.push((client.id, Command::from_str(&line)));
+                }
+            }
+        }
+        commands
+    }
+
+    pub fn reply(&mut self, client_id: ClientId, msg: &serde_json::Value) {
+        if let Some(client) = self.clients.iter_mut().find(|c| c.id == client_id) {
+            client.send(msg);
+        }
+    }
+
+    pub fn subscribe(&mut self, client_id: ClientId) {
+        if let Some(client) = self.clients.iter_mut().find(|c| c.id == client_id) {
+            client.subscribed = true;
+        }
+    }
+
+    pub fn broadcast(&mut self, event: &serde_json::Value) {
+        for client in self.clients.iter_mut().filter(|c| c.subscribed) {
+            client.send(event);
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// send one command to the server, return the iterator of response lines
+// (the first one is the response, the following ones are events, if subscribed)
+pub fn send_command(
+    path: &Path,
+    command: &str,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<serde_json::Value>>> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Cannot connect to control socket {:?}", path))?;
+    stream.write_all(format!("{}\n", command.trim()).as_bytes())?;
+    Ok(BufReader::new(stream)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            Command::from_str("set-run-mode gray").unwrap(),
+            Command::SetRunMode(RunMode::Gray)
+        );
+        assert_eq!(Command::from_str(" refresh ").unwrap(), Command::Refresh(None));
+        assert_eq!(
+            Command::from_str("refresh 10 20 30 40").unwrap(),
+            Command::Refresh(Some(Rect::new((10, 20).into(), (30, 40).into())))
+        );
+        assert_eq!(
+            Command::from_str("set-rotation rotate90").unwrap(),
+            Command::SetRotation(Rotation::Rotate90)
+        );
+        assert_eq!(
+            Command::from_str("add-mask throttle 0 0 100 20 5000").unwrap(),
+            Command::AddMask(MaskOptions {
+                kind: MaskKind::Throttle,
+                rect: [0, 0, 100, 20],
+                interval: 5000,
+            })
+        );
+        assert_eq!(Command::from_str("set-caret 10 20").unwrap(), Command::SetCaret((10, 20).into()));
+        assert!(Command::from_str("add-mask hide 0 0 100 20").is_err());
+        assert!(Command::from_str("refresh 10 20").is_err());
+        assert!(Command::from_str("set-run-mode colorful").is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let path = std::env::temp_dir().join(format!("rabbitink-test-{}.sock", std::process::id()));
+        let mut server = ControlServer::bind(&path).unwrap();
+
+        let client_path = path.clone();
+        let client = std::thread::spawn(move || {
+            let mut responses = send_command(&client_path, "subscribe").unwrap();
+            let response = responses.next().unwrap().unwrap();
+            let event = responses.next().unwrap().unwrap();
+            (response, event)
+        });
+
+        let (client_id, command) = loop {
+            if let Some(v) = server.poll().pop() {
+                break v;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!(command.unwrap(), Command::Subscribe);
+        server.reply(client_id, &serde_json::json!({"ok": true}));
+        server.subscribe(client_id);
+        server.broadcast(&serde_json::json!({"event": "full_refresh"}));
+
+        let (response, event) = client.join().unwrap();
+        assert_eq!(response["ok"], true);
+        assert_eq!(event["event"], "full_refresh");
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_slow_subscriber() {
+        let path = std::env::temp_dir().join(format!("rabbitink-test-slow-{}.sock", std::process::id()));
+        let mut server = ControlServer::bind(&path).unwrap();
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"subscribe\n").unwrap();
+        let client_id = loop {
+            if let Some((id, _)) = server.poll().pop() {
+                break id;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        server.subscribe(client_id);
+
+        // events are queued once the socket buffer is full, instead of disconnecting the client
+        let mut count = 0;
+        while server.clients[0].output.is_empty() {
+            server.broadcast(&serde_json::json!({"event": "displayed", "seq": count}));
+            count += 1;
+        }
+        for _ in 0..10 {
+            server.broadcast(&serde_json::json!({"event": "displayed", "seq": count}));
+            count += 1;
+        }
+        server.poll();
+        assert!(!server.clients[0].write_failed);
+
+        // all events arrive complete and in order, while the server keeps flushing
+        let reader = std::thread::spawn(move || {
+            BufReader::new(stream)
+                .lines()
+                .take(count)
+                .map(|x| serde_json::from_str::<serde_json::Value>(&x.unwrap()).unwrap()["seq"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        });
+        while !server.clients[0].output.is_empty() {
+            server.poll();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(reader.join().unwrap(), (0..count as u64).collect::<Vec<_>>());
+
+        // a client that never reads is disconnected at the cap
+        let _stream = UnixStream::connect(&path).unwrap();
+        server.poll();
+        let client = server.clients.last_mut().unwrap();
+        let slow_id = client.id;
+        client.subscribed = true;
+        while server.clients.iter().any(|c| c.id == slow_id && !c.write_failed) {
+            server.broadcast(&serde_json::json!({"event": "displayed"}));
+        }
+        server.poll();
+        assert!(server.clients.iter().all(|c| c.id != slow_id));
+    }
+
+    #[test]
+    fn test_half_closed_client() {
+        let path = std::env::temp_dir().join(format!("rabbitink-test-half-{}.sock", std::process::id()));
+        let mut server = ControlServer::bind(&path).unwrap();
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"status\n").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let (client_id, command) = loop {
+            if let Some(v) = server.poll().pop() {
+                break v;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!(command.unwrap(), Command::Status);
+        server.reply(client_id, &serde_json::json!({"ok": true}));
+        server.poll();
+        assert!(server.clients.is_empty());
+
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "{\"ok\":true}\n");
+    }
+}
@@ -8,7 +8,7 @@ use super::serde::{BigEndianU16, BigEndianU32};
 use super::waveform::Waveform;
 use crate::image::*;
 
-#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, serde::Deserialize)]
 pub enum DisplayMode {
     INIT = 0,
     DU,
@@ -20,6 +20,17 @@ pub enum DisplayMode {
     DU4,
 }
 
+impl DisplayMode {
+    // whether the mode drives the pixels through the full waveform (so it clears ghosting),
+    // as opposed to the fast modes (DU, A2, DU4)
+    pub fn is_quality(&self) -> bool {
+        matches!(
+            self,
+            DisplayMode::INIT | DisplayMode::GC16 | DisplayMode::GL16 | DisplayMode::GLR16 | DisplayMode::GLD16
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MemMode {
     Mem1bpp,
@@ -354,6 +365,38 @@ impl IT8915 {
         self.load_image_fullwidth_generic(row_offset, image, self.mem_pitch_8bpp)
     }
 
+    // expand the area to satisfy the alignment requirement of display_area
+    pub fn align_display_area(&self, area: Rect) -> Rect {
+        let screen_width = self.get_screen_size().width;
+        let aligned = area.align_x(32, screen_width);
+        if aligned.size.width % 32 != 0 && aligned.size.width != screen_width {
+            // reaching the right edge of screen which is not aligned, use full width instead
+            Rect::new((0, aligned.pt.y).into(), (screen_width, aligned.size.height).into())
+        } else {
+            aligned
+        }
+    }
+
+    // the waveform mode number of the display mode in this device, None if not supported
+    fn display_mode_value(&self, mode: DisplayMode) -> Option<u32> {
+        match self.sysinfo.mode_no.val() {
+            8 => Some(mode as u32),
+            // devices with 6 modes do not have the regal modes (GLR16, GLD16)
+            6 => match mode {
+                DisplayMode::INIT | DisplayMode::DU | DisplayMode::GC16 | DisplayMode::GL16 => {
+                    Some(mode as u32)
+                }
+                DisplayMode::A2 | DisplayMode::DU4 => Some(mode as u32 - 2),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn supports_display_mode(&self, mode: DisplayMode) -> bool {
+        self.display_mode_value(mode).is_some()
+    }
+
     pub fn display_area(
         &mut self,
         tl: Point,
@@ -369,14 +412,9 @@ impl IT8915 {
         );
 
         let mode_val = match self.sysinfo.mode_no.val() {
-            8 => mode as u32,
-            6 => match mode {
-                DisplayMode::INIT | DisplayMode::DU | DisplayMode::GC16 | DisplayMode::GL16 => {
-                    mode as u32
-                }
-                DisplayMode::A2 | DisplayMode::DU4 => mode as u32 - 2,
-                _ => anyhow::bail!("unsupported mode {:?} in this device", mode),
-            },
+            6 | 8 => self
+                .display_mode_value(mode)
+                .ok_or_else(|| anyhow::format_err!("unsupported mode {:?} in this device", mode))?,
             _ => anyhow::bail!(
                 "unsupported device with mode_no {}",
                 self.sysinfo.mode_no.val()
@@ -34,6 +34,46 @@ where
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub pt: Point,
+    pub size: Size,
+}
+
+impl Rect {
+    pub fn new(pt: Point, size: Size) -> Self {
+        Rect { pt, size }
+    }
+
+    pub fn right(&self) -> i32 {
+        self.pt.x + self.size.width
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.pt.y + self.size.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size.width <= 0 || self.size.height <= 0
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = i32::max(self.pt.x, other.pt.x);
+        let y = i32::max(self.pt.y, other.pt.y);
+        let w = i32::min(self.right(), other.right()) - x;
+        let h = i32::min(self.bottom(), other.bottom()) - y;
+        Rect::new((x, y).into(), (w.max(0), h.max(0)).into())
+    }
+
+    // expand horizontally so that both left and right edge are aligned to `align` pixels
+    // (or to the right edge of the screen)
+    pub fn align_x(&self, align: i32, max_width: i32) -> Rect {
+        let x = self.pt.x / align * align;
+        let right = i32::min((self.right() + align - 1) / align * align, max_width);
+        Rect::new((x, self.pt.y).into(), (right - x, self.size.height).into())
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ImageFormat {
     Mono1Bpp,  // mono, 1 bit per pixel
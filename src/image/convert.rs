@@ -1,4 +1,5 @@
 use super::*;
+use crate::imgproc::simd;
 
 pub fn repack_mono(src: &impl ConstImage, dst_format: ImageFormat, dst_pitch: i32) -> ImageBuffer {
     assert!(src.format() == ImageFormat::Mono1Bpp || src.format() == ImageFormat::Mono8Bpp);
@@ -12,6 +13,25 @@ pub fn repack_mono(src: &impl ConstImage, dst_format: ImageFormat, dst_pitch: i3
     let mut dst = ImageBuffer::new(dst_format, src.width(), src.height(), Some(dst_pitch));
     assert!(dst_pitch * dst_ppbyte >= dst.width());
 
+    let width = src.width() as usize;
+    if src_bpp == 8 && dst_bpp == 1 {
+        let thresholds = vec![0x7f; width];
+        for y in 0..src.height() {
+            let src_row = unsafe { std::slice::from_raw_parts(src.ptr(y), width) };
+            let dst_row = unsafe { std::slice::from_raw_parts_mut(dst.mut_ptr(y), width.div_ceil(8)) };
+            simd::pack_above_thresholds(src_row, &thresholds, dst_row);
+        }
+        return dst;
+    }
+    if src_bpp == 1 && dst_bpp == 8 {
+        for y in 0..src.height() {
+            let src_row = unsafe { std::slice::from_raw_parts(src.ptr(y), width.div_ceil(8)) };
+            let dst_row = unsafe { std::slice::from_raw_parts_mut(dst.mut_ptr(y), width) };
+            simd::unpack_bits(src_row, dst_row);
+        }
+        return dst;
+    }
+
     for y in 0..src.height() {
         let src_row_ptr = src.ptr(y);
         let dst_row_ptr = dst.mut_ptr(y);
@@ -30,3 +50,34 @@ pub fn repack_mono(src: &impl ConstImage, dst_format: ImageFormat, dst_pitch: i3
 
     return dst;
 }
+
+// 8bpp mono to 8bpp black (0x00) or white (0xff)
+pub fn threshold_mono8(src: &impl ConstImage, threshold: u8) -> ImageBuffer {
+    assert_eq!(src.format(), ImageFormat::Mono8Bpp);
+    let mut dst = ImageBuffer::new(ImageFormat::Mono8Bpp, src.width(), src.height(), Some(src.pitch()));
+    for y in 0..src.height() {
+        let src_row = unsafe { std::slice::from_raw_parts(src.ptr(y), src.width() as usize) };
+        let dst_row = unsafe { std::slice::from_raw_parts_mut(dst.mut_ptr(y), src.width() as usize) };
+        for (d, s) in dst_row.iter_mut().zip(src_row) {
+            *d = if *s >= threshold { 0xff } else { 0x00 };
+        }
+    }
+    dst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repack_mono() {
+        let data: Vec<u8> = (0..40).map(|i| if i % 3 == 0 { 0xff } else { i as u8 }).collect();
+        let src = ConstImageView::new(ImageFormat::Mono8Bpp, data.as_slice(), 20, 2, None);
+        let packed = repack_mono(&src, ImageFormat::Mono1Bpp, 4);
+        assert_eq!(&packed.data()[..3], &[0b01001001, 0b10010010, 0b0100]);
+        let unpacked = repack_mono(&packed, ImageFormat::Mono8Bpp, 20);
+        for (a, b) in unpacked.data().iter().zip(&data) {
+            assert_eq!(*a, if *b >= 0x80 { 0xff } else { 0 });
+        }
+    }
+}
@@ -1,15 +1,64 @@
+pub mod antialias;
 pub mod dithering;
+pub mod hybrid;
 pub mod rotate;
+pub mod stroke;
+pub mod threshold_map;
 
 pub use rotate::Rotation;
 
-use crate::image::Size;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use log::warn;
+
+use crate::image::*;
+use dithering::{DiffusionKernel, ErrorDiffusion, TargetColorSpace};
+use threshold_map::ThresholdMap;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DitheringMethod {
     NoDithering,
     Bayers2,
     Bayers4,
+    Bayers8,
+    BlueNoise, // 64x64
+    Map(threshold_map::LoadedMapId), // loaded from file
+    Hybrid, // thresholding for text blocks, bayers 4x4 for image blocks, see `hybrid`
+}
+
+impl FromStr for DitheringMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "naive" => DitheringMethod::NoDithering,
+            "bayers2" => DitheringMethod::Bayers2,
+            "bayers4" => DitheringMethod::Bayers4,
+            "bayers8" => DitheringMethod::Bayers8,
+            "bluenoise" => DitheringMethod::BlueNoise,
+            "hybrid" => DitheringMethod::Hybrid,
+            _ => match s.strip_prefix("map:") {
+                Some(path) => DitheringMethod::Map(threshold_map::load(Path::new(path))?),
+                None => anyhow::bail!("Unsupported dithering method: {}", s),
+            },
+        })
+    }
+}
+
+impl std::fmt::Display for DitheringMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DitheringMethod::NoDithering => write!(f, "naive"),
+            DitheringMethod::Bayers2 => write!(f, "bayers2"),
+            DitheringMethod::Bayers4 => write!(f, "bayers4"),
+            DitheringMethod::Bayers8 => write!(f, "bayers8"),
+            DitheringMethod::BlueNoise => write!(f, "bluenoise"),
+            DitheringMethod::Map(id) => write!(f, "map:{}", threshold_map::loaded(*id).0.display()),
+            DitheringMethod::Hybrid => write!(f, "hybrid"),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -19,5 +68,217 @@ pub struct MonoImgprocOptions {
     pub rotation: Rotation,
 }
 
+const DEFAULT_NAIVE_THRESHOLD: u8 = 128;
+
+// threshold map of the dithering method, a pixel is black if its gray level <= threshold.
+// For the hybrid method, this is the map of image blocks
+fn dithering_thresholds(dithering_method: DitheringMethod, naive_threshold: u8) -> Arc<ThresholdMap> {
+    static BAYERS: OnceLock<[Arc<ThresholdMap>; 3]> = OnceLock::new();
+    let bayers = BAYERS.get_or_init(|| [2, 4, 8].map(|x| Arc::new(ThresholdMap::bayer(x))));
+    match dithering_method {
+        DitheringMethod::NoDithering => Arc::new(ThresholdMap::flat(naive_threshold)),
+        DitheringMethod::Bayers2 => bayers[0].clone(),
+        DitheringMethod::Bayers4 | DitheringMethod::Hybrid => bayers[1].clone(),
+        DitheringMethod::Bayers8 => bayers[2].clone(),
+        DitheringMethod::BlueNoise => ThresholdMap::blue_noise(),
+        DitheringMethod::Map(id) => threshold_map::loaded(id).1,
+    }
+}
+
+// 2x3 matrix (row major) mapping output pixel center (x + 0.5, y + 0.5, 1) to input coordinate
+fn coord_transform(opts: &MonoImgprocOptions) -> [f32; 6] {
+    match opts.rotation {
+        Rotation::NoRotation => [1.0, 0.0, 0.0,
+                                 0.0, 1.0, 0.0],
+        Rotation::Rotate90 => [0.0, 1.0, 0.0,
+                               -1.0, 0.0, opts.output_size.width as f32],
+        Rotation::Rotate180 => [-1.0, 0.0, opts.output_size.width as f32,
+                                0.0, -1.0, opts.output_size.height as f32],
+        Rotation::Rotate270 => [0.0, -1.0, opts.output_size.height as f32,
+                                1.0, 0.0, 1.0],
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImgprocBackend {
+    #[default]
+    Auto, // GPU if available, otherwise CPU
+    Gpu,
+    Cpu,
+}
+
+pub mod cpu;
 pub mod gpu;
-pub use gpu::MonoImgproc as MonoImgproc;
+pub mod simd;
+pub mod stabilize;
+
+// Convert BGRA frames to packed 1bpp mono frames, with rotation and dithering.
+// The CPU implementation produces exactly the same result as the GPU one.
+pub enum MonoImgproc {
+    Gpu(Box<gpu::GpuMonoImgproc>),
+    Cpu(Box<cpu::CpuMonoImgproc>),
+}
+
+impl MonoImgproc {
+    pub fn new(options: MonoImgprocOptions) -> Self {
+        Self::with_backend(options, ImgprocBackend::Auto).unwrap()
+    }
+
+    pub fn with_backend(options: MonoImgprocOptions, backend: ImgprocBackend) -> anyhow::Result<Self> {
+        match backend {
+            ImgprocBackend::Gpu => Ok(MonoImgproc::Gpu(Box::new(gpu::GpuMonoImgproc::new(options)?))),
+            ImgprocBackend::Cpu => Ok(MonoImgproc::Cpu(Box::new(cpu::CpuMonoImgproc::new(options)))),
+            ImgprocBackend::Auto => match gpu::GpuMonoImgproc::new(options) {
+                Ok(v) => Ok(MonoImgproc::Gpu(Box::new(v))),
+                Err(e) => {
+                    warn!("GPU imgproc is not available ({}), using CPU", e);
+                    Ok(MonoImgproc::Cpu(Box::new(cpu::CpuMonoImgproc::new(options))))
+                }
+            },
+        }
+    }
+
+    pub fn set_naive_threshold(&mut self, threshold: u8) {
+        match self {
+            MonoImgproc::Gpu(v) => v.set_naive_threshold(threshold),
+            MonoImgproc::Cpu(v) => v.set_naive_threshold(threshold),
+        }
+    }
+
+    // see `antialias`
+    pub fn set_font_recovery(&mut self, enabled: bool) {
+        match self {
+            MonoImgproc::Gpu(v) => v.set_font_recovery(enabled),
+            MonoImgproc::Cpu(v) => v.set_font_recovery(enabled),
+        }
+    }
+
+    // see `stroke`
+    pub fn set_embolden(&mut self, embolden: stroke::Embolden) {
+        match self {
+            MonoImgproc::Gpu(v) => v.set_embolden(embolden),
+            MonoImgproc::Cpu(v) => v.set_embolden(embolden),
+        }
+    }
+
+    pub fn process<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
+        &mut self,
+        input_img: &InputT,
+        output_img: &mut OutputT,
+        dithering_method: DitheringMethod,
+    ) {
+        match self {
+            MonoImgproc::Gpu(v) => v.process(input_img, output_img, dithering_method),
+            MonoImgproc::Cpu(v) => v.process(input_img, output_img, dithering_method),
+        }
+    }
+
+    // see `CpuMonoImgproc::output_gray`, None for the GPU, which does not read them back
+    pub fn output_gray(&self) -> Option<&[u8]> {
+        match self {
+            MonoImgproc::Gpu(_) => None,
+            MonoImgproc::Cpu(v) => Some(v.output_gray()),
+        }
+    }
+}
+
+// Convert BGRA frames to 8bpp frames of the gray levels in `target_color_space`, with rotation.
+// Ordered dithering (bayers 4x4) is processed in the GPU if available, the CPU implementation produces
+// exactly the same result. Error diffusion looks better, but is only implemented in the CPU.
+pub enum GrayImgproc {
+    Gpu(Box<gpu::GpuGrayImgproc>),
+    Ordered {
+        options: MonoImgprocOptions,
+        target_color_space: TargetColorSpace,
+    },
+    Cpu {
+        options: MonoImgprocOptions,
+        target_color_space: TargetColorSpace,
+        error_diffusion: ErrorDiffusion,
+    },
+}
+
+impl GrayImgproc {
+    pub fn ordered(
+        options: MonoImgprocOptions,
+        target_color_space: TargetColorSpace,
+        backend: ImgprocBackend,
+    ) -> anyhow::Result<Self> {
+        let cpu = GrayImgproc::Ordered { options, target_color_space };
+        match backend {
+            ImgprocBackend::Gpu => Ok(GrayImgproc::Gpu(Box::new(gpu::GpuGrayImgproc::new(options, target_color_space)?))),
+            ImgprocBackend::Cpu => Ok(cpu),
+            ImgprocBackend::Auto => match gpu::GpuGrayImgproc::new(options, target_color_space) {
+                Ok(v) => Ok(GrayImgproc::Gpu(Box::new(v))),
+                Err(e) => {
+                    warn!("GPU imgproc is not available ({}), using CPU", e);
+                    Ok(cpu)
+                }
+            },
+        }
+    }
+
+    // error diffusion is only implemented in the CPU
+    pub fn error_diffusion(
+        options: MonoImgprocOptions,
+        target_color_space: TargetColorSpace,
+        error_diffusion: ErrorDiffusion,
+    ) -> Self {
+        GrayImgproc::Cpu { options, target_color_space, error_diffusion }
+    }
+
+    fn ordered_thresholds() -> Arc<ThresholdMap> {
+        dithering_thresholds(DitheringMethod::Bayers4, DEFAULT_NAIVE_THRESHOLD)
+    }
+
+    pub fn process<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
+        &mut self,
+        input_img: &InputT,
+        output_img: &mut OutputT,
+    ) {
+        match self {
+            GrayImgproc::Gpu(v) => v.process(input_img, output_img, DitheringMethod::Bayers4),
+            GrayImgproc::Ordered { options, target_color_space } => {
+                let rotated = rotate::rotate(input_img, options.rotation, options.output_size);
+                let width = options.output_size.width as usize;
+                let mut gray = vec![0; width * options.output_size.height as usize];
+                for (y, gray_row) in gray.chunks_exact_mut(width).enumerate() {
+                    let bgra_row = unsafe { std::slice::from_raw_parts(rotated.ptr(y as i32), width * 4) };
+                    simd::bgra_to_gray(bgra_row, gray_row);
+                }
+                dithering::ordered(&gray, output_img, *target_color_space, &Self::ordered_thresholds());
+            }
+            GrayImgproc::Cpu { options, target_color_space, error_diffusion } => {
+                let rotated = rotate::rotate(input_img, options.rotation, options.output_size);
+                output_img.copy_from(&dithering::error_diffusion(&rotated, *target_color_space, *error_diffusion));
+            }
+        }
+    }
+
+    // like `process`, but the unchanged pixels keep the previous output, see `Stabilizer`.
+    // Error diffusion only runs in the changed regions
+    pub fn process_stabilized<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
+        &mut self,
+        input_img: &InputT,
+        output_img: &mut OutputT,
+        stabilizer: &mut stabilize::Stabilizer,
+    ) {
+        match self {
+            GrayImgproc::Gpu(_) => {
+                self.process(input_img, output_img);
+                stabilizer.update(input_img, 0);
+            }
+            GrayImgproc::Ordered { target_color_space, .. } => {
+                stabilizer.update(input_img, 0);
+                dithering::ordered(stabilizer.gray(), output_img, *target_color_space, &Self::ordered_thresholds());
+            }
+            GrayImgproc::Cpu { target_color_space, error_diffusion, .. } => {
+                stabilizer.update(input_img, dithering::DIFFUSION_RADIUS);
+                dithering::diffuse(stabilizer.gray(), output_img, *target_color_space, *error_diffusion,
+                                   Some(stabilizer.changed()));
+            }
+        }
+        stabilizer.apply(output_img);
+    }
+}
@@ -0,0 +1,136 @@
+// Font antialias recovery: the gray pixels around glyph strokes are thresholded at the middle of their
+// 3x3 neighbourhood, instead of being dithered into random dots, which gives crisp 1-bit glyphs from
+// anti-aliased text. Pure black and white content is not affected.
+// Must match `edge_threshold` in gpu.wgsl exactly.
+
+// the gray level range of the neighbourhood of glyph edges
+const EDGE_CONTRAST: u8 = 128;
+
+// `gray` is the whole output, pixels out of the input are white (255).
+// Returns the threshold if (x, y) is on a glyph edge
+pub fn edge_threshold(gray: &[u8], width: usize, height: usize, x: usize, y: usize) -> Option<u8> {
+    let (mut lo, mut hi) = (255u8, 0u8);
+    for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for v in &gray[ny * width + x.saturating_sub(1)..ny * width + (x + 2).min(width)] {
+            lo = lo.min(*v);
+            hi = hi.max(*v);
+        }
+    }
+    (hi - lo >= EDGE_CONTRAST).then_some(((lo as u32 + hi as u32) / 2) as u8)
+}
+
+// Row version of `edge_threshold`, with the 3x3 min / max separated into a vertical and a horizontal pass
+// over whole rows, which the compiler vectorizes. Rows without enough contrast are skipped early,
+// so that plain content keeps the fast path of tiled thresholds.
+#[derive(Default)]
+pub struct EdgeThresholds {
+    col_lo: Vec<u8>, // min / max of the 3 rows
+    col_hi: Vec<u8>,
+    lo: Vec<u8>,
+    hi: Vec<u8>,
+    thresholds: Vec<u8>,
+}
+
+impl EdgeThresholds {
+    // `base` thresholds of row `y` with those of the glyph edges replaced, None if the row has no edge
+    pub fn row(&mut self, gray: &[u8], width: usize, height: usize, y: usize, base: &[u8]) -> Option<&[u8]> {
+        let rows = y.saturating_sub(1)..(y + 2).min(height);
+        let first = &gray[rows.start * width..(rows.start + 1) * width];
+        self.col_lo.clear();
+        self.col_lo.extend_from_slice(first);
+        self.col_hi.clear();
+        self.col_hi.extend_from_slice(first);
+        for ny in rows.start + 1..rows.end {
+            let row = &gray[ny * width..(ny + 1) * width];
+            for ((lo, hi), v) in self.col_lo.iter_mut().zip(self.col_hi.iter_mut()).zip(row) {
+                *lo = (*lo).min(*v);
+                *hi = (*hi).max(*v);
+            }
+        }
+        // every neighbourhood is within the range of the 3 rows
+        let (row_lo, row_hi) = (*self.col_lo.iter().min()?, *self.col_hi.iter().max()?);
+        if row_hi - row_lo < EDGE_CONTRAST {
+            return None;
+        }
+
+        self.lo.resize(width, 0);
+        self.hi.resize(width, 0);
+        spread(&self.col_lo, &mut self.lo, u8::min);
+        spread(&self.col_hi, &mut self.hi, u8::max);
+        self.thresholds.clear();
+        self.thresholds.extend(self.lo.iter().zip(&self.hi).zip(base).map(|((lo, hi), base)| {
+            if hi - lo >= EDGE_CONTRAST {
+                ((*lo as u16 + *hi as u16) / 2) as u8
+            } else {
+                *base
+            }
+        }));
+        Some(&self.thresholds)
+    }
+}
+
+// combine each pixel with its left and right neighbours
+fn spread(src: &[u8], dst: &mut [u8], f: fn(u8, u8) -> u8) {
+    let width = src.len();
+    if width < 2 {
+        dst.copy_from_slice(src);
+        return;
+    }
+    for (((v, left), center), right) in dst[1..width - 1].iter_mut().zip(src).zip(&src[1..]).zip(&src[2..]) {
+        *v = f(f(*left, *center), *right);
+    }
+    dst[0] = f(src[0], src[1]);
+    dst[width - 1] = f(src[width - 2], src[width - 1]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_threshold() {
+        // a vertical anti-aliased stroke, on light gray
+        let row = [200u8, 200, 170, 20, 110, 200, 200, 200, 200];
+        let gray: Vec<u8> = row.repeat(3);
+        let thresholds: Vec<Option<u8>> = (0..row.len()).map(|x| edge_threshold(&gray, row.len(), 3, x, 1)).collect();
+        assert_eq!(thresholds[2..5], [Some(110), Some(95), Some(110)]);
+        assert!(thresholds[0..2].iter().chain(&thresholds[5..]).all(|x| x.is_none()));
+
+        // the gray pixel darker than the middle joins the stroke, the lighter one becomes background
+        assert!(gray[4] <= thresholds[4].unwrap() && gray[2] > thresholds[2].unwrap());
+
+        // flat, also at the border
+        assert_eq!(edge_threshold(&[100, 100], 2, 1, 0, 0), None);
+    }
+
+    #[test]
+    fn test_edge_thresholds_row() {
+        // noise, some flat rows, and thin images
+        let mut seed = 1u32;
+        let mut noise = |n: usize| -> Vec<u8> {
+            (0..n).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            }).collect()
+        };
+        let mut edges = EdgeThresholds::default();
+        for (width, height) in [(37, 9), (1, 5), (2, 3), (8, 1)] {
+            let mut gray = noise(width * height);
+            if height > 4 {
+                gray[..3 * width].fill(90);
+            }
+            let base = noise(width);
+            for y in 0..height {
+                let expected: Vec<u8> = (0..width)
+                    .map(|x| edge_threshold(&gray, width, height, x, y).unwrap_or(base[x]))
+                    .collect();
+                match edges.row(&gray, width, height, y, &base) {
+                    Some(v) => assert_eq!(v, expected, "{} {} {}", width, height, y),
+                    None => assert_eq!(base, expected, "{} {} {}", width, height, y),
+                }
+            }
+        }
+        // rows without contrast are skipped
+        assert!(edges.row(&[0, 0, 127, 127, 0, 0], 2, 3, 1, &[1, 1]).is_none());
+    }
+}
@@ -0,0 +1,334 @@
+use log::debug;
+
+use super::antialias;
+use super::hybrid::BlockInfos;
+use super::simd;
+use super::stroke::{self, Embolden};
+use super::{coord_transform, dithering_thresholds, DitheringMethod, MonoImgprocOptions, DEFAULT_NAIVE_THRESHOLD};
+use crate::image::*;
+
+// CPU implementation of gpu.wgsl, for machines without GPU adapters.
+// Every step (the f32 coordinate transform, the f32 luminosity, the thresholds and the bit order)
+// follows the shader exactly, so that both produce the same output.
+pub struct CpuMonoImgproc {
+    opts: MonoImgprocOptions,
+    coord_transform: [f32; 6],
+    naive_threshold: u8,
+    font_recovery: bool,
+    embolden: Embolden,
+    // buffers reused between frames
+    gray: Vec<u8>,
+    output_gray: Vec<u8>,
+    pixel_thresholds: Vec<u8>,
+    edge_thresholds: antialias::EdgeThresholds,
+    stroke_buf: Vec<u8>,
+    white: Vec<usize>,
+}
+
+impl CpuMonoImgproc {
+    pub fn new(opts: MonoImgprocOptions) -> Self {
+        CpuMonoImgproc {
+            opts,
+            coord_transform: coord_transform(&opts),
+            naive_threshold: DEFAULT_NAIVE_THRESHOLD,
+            font_recovery: false,
+            embolden: Embolden::default(),
+            gray: Vec::new(),
+            output_gray: Vec::new(),
+            pixel_thresholds: Vec::new(),
+            edge_thresholds: Default::default(),
+            stroke_buf: Vec::new(),
+            white: Vec::new(),
+        }
+    }
+
+    // gray levels (after emboldening) of the last processed frame in output coordinate, which the output is made from
+    pub fn output_gray(&self) -> &[u8] {
+        &self.output_gray
+    }
+
+    pub fn set_naive_threshold(&mut self, threshold: u8) {
+        self.naive_threshold = threshold;
+    }
+
+    pub fn set_font_recovery(&mut self, enabled: bool) {
+        self.font_recovery = enabled;
+    }
+
+    pub fn set_embolden(&mut self, embolden: Embolden) {
+        self.embolden = embolden;
+    }
+
+    pub fn process<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
+        &mut self,
+        input_img: &InputT,
+        output_img: &mut OutputT,
+        dithering_method: DitheringMethod,
+    ) {
+        assert_eq!(input_img.format(), ImageFormat::BGRA);
+        assert_eq!(input_img.size(), self.opts.input_size);
+        assert_eq!(output_img.format(), ImageFormat::Mono1Bpp);
+        assert_eq!(output_img.size(), self.opts.output_size);
+        let t_start = std::time::Instant::now();
+
+        // gray levels of the whole input first, so that the rotated reads below touch 1 byte per pixel.
+        // The gray level is truncated as `gray as u32` in the shader.
+        let (input_width, input_height) = (self.opts.input_size.width as usize, self.opts.input_size.height as usize);
+        self.gray.resize(input_width * input_height, 0);
+        for (y, gray_row) in self.gray.chunks_exact_mut(input_width).enumerate() {
+            let bgra_row = unsafe { std::slice::from_raw_parts(input_img.ptr(y as i32), input_width * 4) };
+            simd::bgra_to_gray(bgra_row, gray_row);
+        }
+
+        let thresholds = dithering_thresholds(dithering_method, self.naive_threshold);
+        let (width, height) = (self.opts.output_size.width as usize, self.opts.output_size.height as usize);
+        let m = self.coord_transform;
+        // out of range pixels are white by default, they are set after packing
+        self.white.clear();
+        self.output_gray.resize(width * height, 0);
+        for (y, row) in self.output_gray.chunks_exact_mut(width).enumerate() {
+            let fy = y as f32 + 0.5;
+            for (x, v) in row.iter_mut().enumerate() {
+                let fx = x as f32 + 0.5;
+                // `as` saturates like the conversion in WGSL
+                let input_x = (m[0] * fx + m[1] * fy + m[2]) as u32 as usize;
+                let input_y = (m[3] * fx + m[4] * fy + m[5]) as u32 as usize;
+                if input_x < input_width && input_y < input_height {
+                    *v = self.gray[input_y * input_width + input_x];
+                } else {
+                    // also white in the block classification of the hybrid method
+                    *v = 255;
+                    self.white.push(y * width + x);
+                }
+            }
+        }
+
+        // before everything that depends on the gray levels, as in the shader
+        stroke::embolden(&mut self.output_gray, width, self.embolden, &mut self.stroke_buf);
+
+        let blocks = (dithering_method == DitheringMethod::Hybrid)
+            .then(|| BlockInfos::new(&self.output_gray, width, height));
+        // thresholds of each pixel are computed if they do not simply tile the map
+        let threshold_rows: Vec<Vec<u8>> = match blocks {
+            Some(_) => Vec::new(),
+            None => (0..thresholds.height).map(|y| thresholds.tiled_row(y, width)).collect(),
+        };
+        self.pixel_thresholds.resize(width, 0);
+        for (y, row) in self.output_gray.chunks_exact(width).enumerate() {
+            let mut threshold_row: &[u8] = match blocks.as_ref() {
+                Some(blocks) => {
+                    for (x, v) in self.pixel_thresholds.iter_mut().enumerate() {
+                        *v = blocks.threshold(x, y, &thresholds);
+                    }
+                    &self.pixel_thresholds
+                }
+                None => &threshold_rows[y % thresholds.height],
+            };
+            if self.font_recovery {
+                if let Some(v) = self.edge_thresholds.row(&self.output_gray, width, height, y, threshold_row) {
+                    threshold_row = v;
+                }
+            }
+            let output_row = unsafe { std::slice::from_raw_parts_mut(output_img.mut_ptr(y as i32), width.div_ceil(8)) };
+            // bits are packed from the least significant bit, as the little endian u32 in the shader
+            simd::pack_above_thresholds(row, threshold_row, output_row);
+        }
+        for i in &self.white {
+            let (x, y) = (i % width, i / width);
+            unsafe { *output_img.mut_ptr(y as i32).add(x / 8) |= 1 << (x % 8) };
+        }
+
+        debug!("CPU imgproc processed one frame {:?}: {:?}", self.opts.output_size, t_start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgproc::gpu::GpuMonoImgproc;
+    use crate::imgproc::stroke::StrokeDirection;
+    use crate::imgproc::Rotation;
+
+    // some gray gradients and noise
+    fn test_input(size: Size) -> ImageBuffer {
+        let mut img = ImageBuffer::new(ImageFormat::BGRA, size.width, size.height, None);
+        let mut seed = 12345u32;
+        for y in 0..size.height {
+            let row = unsafe { std::slice::from_raw_parts_mut(img.mut_ptr(y), size.width as usize * 4) };
+            for (x, pixel) in row.chunks_mut(4).enumerate() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as u8;
+                pixel.copy_from_slice(&[(x * 7) as u8, (y * 5) as u8, noise, 0xff]);
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn test_basic() {
+        // alternating white and black
+        let mut color_img = ImageBuffer::new(ImageFormat::BGRA, 32, 1, None);
+        for (i, v) in color_img.mut_data().iter_mut().enumerate() {
+            *v = if (i / 4) % 2 == 0 { 0xff } else { 0 };
+        }
+        let mut output_img = ImageBuffer::new(ImageFormat::Mono1Bpp, 32, 1, None);
+        for (rotation, expected) in [(Rotation::NoRotation, 0b01010101), (Rotation::Rotate180, 0b10101010)] {
+            let mut imgproc = CpuMonoImgproc::new(MonoImgprocOptions {
+                input_size: color_img.size(),
+                output_size: output_img.size(),
+                rotation,
+            });
+            imgproc.process(&color_img, &mut output_img, DitheringMethod::Bayers4);
+            assert!(output_img.data().iter().all(|x| *x == expected));
+        }
+    }
+
+    #[test]
+    fn test_rot90_with_mismatched_size() {
+        // (8, 32) image, (0, 3) is black
+        let mut color_img = ImageBuffer::new(ImageFormat::BGRA, 8, 32, None);
+        color_img.fill(0xff);
+        color_img.mut_data()[3 * 4..4 * 4].fill(0);
+
+        let mut output_img = ImageBuffer::new(ImageFormat::Mono1Bpp, 32, 4, Some(4));
+        let mut imgproc = CpuMonoImgproc::new(MonoImgprocOptions {
+            input_size: color_img.size(),
+            output_size: output_img.size(),
+            rotation: Rotation::Rotate90,
+        });
+        imgproc.process(&color_img, &mut output_img, DitheringMethod::NoDithering);
+        assert_eq!(output_img.data()[3], 255);
+        assert_eq!(output_img.data()[7], 255);
+        assert_eq!(output_img.data()[11], 255);
+        assert_eq!(output_img.data()[15], 127);
+    }
+
+    // straightforward per-pixel implementation of the shader
+    fn reference_process(opts: MonoImgprocOptions, input: &ImageBuffer, naive_threshold: u8,
+                         dithering_method: DitheringMethod, font_recovery: bool, embolden: Embolden) -> ImageBuffer {
+        let m = coord_transform(&opts);
+        let thresholds = dithering_thresholds(dithering_method, naive_threshold);
+        let size = opts.output_size;
+        let mut output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+        // gray level of each output pixel, None if out of range
+        let mut gray = Vec::new();
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let (fx, fy) = (x as f32 + 0.5, y as f32 + 0.5);
+                let input_x = (m[0] * fx + m[1] * fy + m[2]) as u32;
+                let input_y = (m[3] * fx + m[4] * fy + m[5]) as u32;
+                gray.push(if input_x >= opts.input_size.width as u32 || input_y >= opts.input_size.height as u32 {
+                    None
+                } else {
+                    let bgra = unsafe { std::slice::from_raw_parts(input.ptr(input_y as i32).add(input_x as usize * 4), 4) };
+                    Some((0.3 * bgra[2] as f32 + 0.59 * bgra[1] as f32 + 0.11 * bgra[0] as f32) as u8)
+                });
+            }
+        }
+        let (width, height) = (size.width as usize, size.height as usize);
+        let white_gray: Vec<u8> = gray.iter().map(|x| x.unwrap_or(255)).collect();
+        let (radius_x, radius_y) = embolden.radius_xy();
+        let white_gray: Vec<u8> = (0..width * height).map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut v = 255;
+            for ny in y.saturating_sub(radius_y)..=y {
+                for nx in x.saturating_sub(radius_x)..=x {
+                    v = v.min(white_gray[ny * width + nx]);
+                }
+            }
+            v
+        }).collect();
+        let blocks = BlockInfos::new(&white_gray, width, height);
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let (x, y) = (x as usize, y as usize);
+                let mut threshold = match dithering_method {
+                    DitheringMethod::Hybrid => blocks.threshold(x, y, &thresholds),
+                    _ => thresholds.threshold(x, y),
+                };
+                if font_recovery {
+                    threshold = antialias::edge_threshold(&white_gray, width, height, x, y).unwrap_or(threshold);
+                }
+                let white = gray[y * width + x].is_none() || white_gray[y * width + x] > threshold;
+                if white {
+                    unsafe { *output.mut_ptr(y as i32).add(x / 8) |= 1 << (x % 8) };
+                }
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_same_as_reference() {
+        let input_size: Size = (203, 120).into();
+        let input = test_input(input_size);
+        for rotation in [Rotation::NoRotation, Rotation::Rotate90, Rotation::Rotate180, Rotation::Rotate270] {
+            let opts = MonoImgprocOptions {
+                input_size,
+                output_size: rotation.rotated_size(input_size),
+                rotation,
+            };
+            let mut cpu = CpuMonoImgproc::new(opts);
+            cpu.set_naive_threshold(100);
+            let vertical = Embolden { direction: StrokeDirection::Vertical, radius: 2 };
+            let both = Embolden { direction: StrokeDirection::Both, radius: 1 };
+            for (font_recovery, embolden) in [(false, Embolden::default()), (true, Embolden::default()),
+                                              (false, vertical), (true, both)] {
+                cpu.set_font_recovery(font_recovery);
+                cpu.set_embolden(embolden);
+                for dithering_method in [DitheringMethod::NoDithering, DitheringMethod::Bayers4,
+                                         DitheringMethod::BlueNoise, DitheringMethod::Hybrid] {
+                    let size = opts.output_size;
+                    let mut output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+                    cpu.process(&input, &mut output, dithering_method);
+                    let expected = reference_process(opts, &input, 100, dithering_method, font_recovery, embolden);
+                    assert!(output.data() == expected.data(),
+                            "{:?} {:?} {} {:?}", rotation, dithering_method, font_recovery, embolden);
+                }
+            }
+        }
+    }
+
+    // the CPU implementation must produce exactly the same output as the GPU one
+    #[test]
+    #[ignore = "requires a GPU adapter, run with `cargo test -- --ignored`"]
+    fn test_conformance() {
+        let input_size: Size = (200, 120).into();
+        let input = test_input(input_size);
+        for rotation in [Rotation::NoRotation, Rotation::Rotate90, Rotation::Rotate180, Rotation::Rotate270] {
+            let opts = MonoImgprocOptions {
+                input_size,
+                output_size: rotation.rotated_size(input_size),
+                rotation,
+            };
+            let mut gpu = GpuMonoImgproc::new(opts).expect("GPU imgproc is not available");
+            let mut cpu = CpuMonoImgproc::new(opts);
+            gpu.set_naive_threshold(100);
+            cpu.set_naive_threshold(100);
+            for dithering_method in [DitheringMethod::NoDithering, DitheringMethod::Bayers2, DitheringMethod::Bayers4,
+                                     DitheringMethod::Bayers8, DitheringMethod::BlueNoise, DitheringMethod::Hybrid] {
+                let size = opts.output_size;
+                let mut gpu_output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+                let mut cpu_output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+                gpu.process(&input, &mut gpu_output, dithering_method);
+                cpu.process(&input, &mut cpu_output, dithering_method);
+                assert!(gpu_output.data() == cpu_output.data(), "{:?} {:?}", rotation, dithering_method);
+            }
+
+            gpu.set_font_recovery(true);
+            cpu.set_font_recovery(true);
+            let embolden = Embolden { direction: StrokeDirection::Both, radius: 1 };
+            gpu.set_embolden(embolden);
+            cpu.set_embolden(embolden);
+            for dithering_method in [DitheringMethod::Bayers4, DitheringMethod::Hybrid] {
+                let size = opts.output_size;
+                let mut gpu_output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+                let mut cpu_output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+                gpu.process(&input, &mut gpu_output, dithering_method);
+                cpu.process(&input, &mut cpu_output, dithering_method);
+                assert!(gpu_output.data() == cpu_output.data(),
+                        "{:?} {:?} font recovery, embolden", rotation, dithering_method);
+            }
+        }
+    }
+}
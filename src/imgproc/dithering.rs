@@ -1,15 +1,31 @@
+use std::str::FromStr;
+
+use super::simd;
+use super::threshold_map::ThresholdMap;
 use crate::image::*;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TargetColorSpace {
     step: u8,
     n_levels: u8,
 }
 
 impl TargetColorSpace {
-    fn find_nearest_and_residual(&self, src: u8) -> (u8, u8) {
-        let level = (src / (self.step / 2) / 2).min(self.n_levels - 1);
-        let dst = level * self.step;
-        (dst, src.saturating_sub(dst))
+    pub fn n_levels(&self) -> u8 {
+        self.n_levels
+    }
+
+    // value of each level is level * step
+    pub fn step(&self) -> u8 {
+        self.step
+    }
+
+    // nearest level to `src` and the signed error
+    fn find_nearest_and_residual(&self, src: i32) -> (u8, i32) {
+        let step = self.step as i32;
+        let level = ((src + step / 2) / step).min(self.n_levels as i32 - 1);
+        let dst = level * step;
+        (dst as u8, src - dst)
     }
 }
 
@@ -18,48 +34,225 @@ pub const BW_TARGET_COLOR_SPACE: TargetColorSpace = TargetColorSpace {
     n_levels: 2,
 };
 
+// the levels of DU4
+pub const GREY4_TARGET_COLOR_SPACE: TargetColorSpace = TargetColorSpace {
+    step: 0x50,
+    n_levels: 4,
+};
+
 pub const GREY16_TARGET_COLOR_SPACE: TargetColorSpace = TargetColorSpace {
     step: 0x10,
     n_levels: 16,
 };
 
-fn bgra_to_gray(bgra: *const u8) -> u8 {
-    unsafe {
-        let r = *bgra.add(2);
-        let g = *bgra.add(1);
-        let b = *bgra;
-        (0.3 * r as f32 + 0.59 * g as f32 + 0.11 * b as f32).clamp(0.0, 255.0) as u8 // Luminosity Method
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffusionKernel {
+    FloydSteinberg,
+    Atkinson, // diffuses only 3/4 of the error, higher contrast
+    JarvisJudiceNinke,
+    Stucki,
+    Sierra,
+}
+
+impl DiffusionKernel {
+    // (dx, dy, weight) of the pixels after the current one in scan order, and the divisor of the weights
+    fn weights(&self) -> (&'static [(i32, usize, i32)], i32) {
+        match self {
+            Self::FloydSteinberg => (&[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)], 16),
+            Self::Atkinson => (&[(1, 0, 1), (2, 0, 1), (-1, 1, 1), (0, 1, 1), (1, 1, 1), (0, 2, 1)], 8),
+            Self::JarvisJudiceNinke => (&[(1, 0, 7), (2, 0, 5),
+                                          (-2, 1, 3), (-1, 1, 5), (0, 1, 7), (1, 1, 5), (2, 1, 3),
+                                          (-2, 2, 1), (-1, 2, 3), (0, 2, 5), (1, 2, 3), (2, 2, 1)], 48),
+            Self::Stucki => (&[(1, 0, 8), (2, 0, 4),
+                               (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+                               (-2, 2, 1), (-1, 2, 2), (0, 2, 4), (1, 2, 2), (2, 2, 1)], 42),
+            Self::Sierra => (&[(1, 0, 5), (2, 0, 3),
+                               (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+                               (-1, 2, 2), (0, 2, 3), (1, 2, 2)], 32),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ErrorDiffusion {
+    pub kernel: DiffusionKernel,
+    // alternate the scan direction every row, which avoids the diagonal "worm" artifacts
+    pub serpentine: bool,
+}
+
+impl FromStr for ErrorDiffusion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, serpentine) = match s.strip_suffix("_serpentine") {
+            Some(name) => (name, true),
+            None => (s, false),
+        };
+        let kernel = match name {
+            "fs" => DiffusionKernel::FloydSteinberg,
+            "atkinson" => DiffusionKernel::Atkinson,
+            "jjn" => DiffusionKernel::JarvisJudiceNinke,
+            "stucki" => DiffusionKernel::Stucki,
+            "sierra" => DiffusionKernel::Sierra,
+            _ => anyhow::bail!("Unsupported error diffusion kernel: {}", s),
+        };
+        Ok(ErrorDiffusion { kernel, serpentine })
+    }
+}
+
+impl std::fmt::Display for ErrorDiffusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.kernel {
+            DiffusionKernel::FloydSteinberg => "fs",
+            DiffusionKernel::Atkinson => "atkinson",
+            DiffusionKernel::JarvisJudiceNinke => "jjn",
+            DiffusionKernel::Stucki => "stucki",
+            DiffusionKernel::Sierra => "sierra",
+        };
+        write!(f, "{}{}", name, if self.serpentine { "_serpentine" } else { "" })
     }
 }
 
 pub fn floyd_steinberg(bgra_src: &impl ConstImage, target_color_space: TargetColorSpace) -> ImageBuffer {
+    error_diffusion(bgra_src, target_color_space, ErrorDiffusion {
+        kernel: DiffusionKernel::FloydSteinberg,
+        serpentine: false,
+    })
+}
+
+// max dx (and dy) of the kernels, i.e. how far the error of a pixel is diffused
+pub const DIFFUSION_RADIUS: usize = 2;
+
+pub fn error_diffusion(bgra_src: &impl ConstImage, target_color_space: TargetColorSpace,
+                       error_diffusion: ErrorDiffusion) -> ImageBuffer {
     assert_eq!(bgra_src.format(), ImageFormat::BGRA);
     let mut dst: ImageBuffer = ImageBuffer::new(ImageFormat::Mono8Bpp, bgra_src.width(), bgra_src.height(), None);
 
-    let mut current_row_additions: Vec<i32> = vec![0; (bgra_src.width() as usize) + 1];
-    let mut next_row_additions: Vec<i32> = vec![0; (bgra_src.width() as usize) + 1];
-
-    for row in 0..bgra_src.height() {
-        let src_row_ptr = bgra_src.ptr(row);
-        let dst_row_ptr = dst.mut_ptr(row);
-        for col in 0..(bgra_src.width() as usize) {
-            let src_gray_val = bgra_to_gray(unsafe { src_row_ptr.add(col * 4) });
-            let (val, residual) = target_color_space.find_nearest_and_residual(
-                (src_gray_val as i32 + current_row_additions[col] / 256)
-                    .clamp(0, 255) as u8);
-            unsafe{ *dst_row_ptr.add(col) = val; }
-
-            // row_additions are scaled by 256 to preserve precision
-            let residual_16th = residual as i32 * 16;
-            current_row_additions[col+1] += residual_16th * 7;
-            if col >= 1 {
-                next_row_additions[col-1] += residual_16th * 3;
+    let width = bgra_src.width() as usize;
+    let mut gray: Vec<u8> = vec![0; width * bgra_src.height() as usize];
+    for (row, gray_row) in gray.chunks_exact_mut(width).enumerate() {
+        let src_row = unsafe { std::slice::from_raw_parts(bgra_src.ptr(row as i32), width * 4) };
+        simd::bgra_to_gray(src_row, gray_row);
+    }
+    diffuse(&gray, &mut dst, target_color_space, error_diffusion, None);
+    dst
+}
+
+// Error diffusion of the gray levels (`dst.width()` per row) into the 8bpp `dst`.
+// If `changed` is given, only the changed pixels are written, and no error is diffused from or into the others,
+// so that they can keep their previous values.
+pub fn diffuse(gray: &[u8], dst: &mut (impl Image + ?Sized), target_color_space: TargetColorSpace,
+               error_diffusion: ErrorDiffusion, changed: Option<&[bool]>) {
+    assert_eq!(dst.format(), ImageFormat::Mono8Bpp);
+    let width = dst.width() as usize;
+    assert_eq!(gray.len(), width * dst.height() as usize);
+    let (weights, divisor) = error_diffusion.kernel.weights();
+    // errors of the current row and the next 2 rows, scaled by 256 to preserve precision
+    let mut errors: [Vec<i32>; 3] = std::array::from_fn(|_| vec![0; width + DIFFUSION_RADIUS * 2]);
+
+    for (row, gray_row) in gray.chunks_exact(width).enumerate() {
+        let dst_row = unsafe { std::slice::from_raw_parts_mut(dst.mut_ptr(row as i32), width) };
+        let changed_row = changed.map(|x| &x[row * width..(row + 1) * width]);
+        let reverse = error_diffusion.serpentine && row % 2 == 1;
+        let direction = if reverse { -1 } else { 1 };
+        for i in 0..width {
+            let col = if reverse { width - 1 - i } else { i };
+            if changed_row.is_some_and(|x| !x[col]) {
+                continue;
+            }
+            let pos = col + DIFFUSION_RADIUS;
+            let src_val = (gray_row[col] as i32 * 256 + errors[0][pos] + 128) >> 8;
+            let (val, residual) = target_color_space.find_nearest_and_residual(src_val.clamp(0, 255));
+            dst_row[col] = val;
+
+            let residual_256th = residual * 256;
+            for &(dx, dy, weight) in weights {
+                let target = (pos as i32 + dx * direction) as usize;
+                errors[dy][target] += residual_256th * weight / divisor;
+            }
+        }
+        errors.rotate_left(1);
+        errors[2].fill(0);
+    }
+}
+
+// Ordered dithering of the gray levels (`dst.width()` per row) into the 8bpp `dst`:
+// round up to the next level if the remainder, scaled to 0..256, is greater than the threshold. Same as the GPU
+pub fn ordered(gray: &[u8], dst: &mut (impl Image + ?Sized), target_color_space: TargetColorSpace,
+               thresholds: &ThresholdMap) {
+    assert_eq!(dst.format(), ImageFormat::Mono8Bpp);
+    let width = dst.width() as usize;
+    assert_eq!(gray.len(), width * dst.height() as usize);
+    let (step, n_levels) = (target_color_space.step as u32, target_color_space.n_levels as u32);
+    for (row, gray_row) in gray.chunks_exact(width).enumerate() {
+        let dst_row = unsafe { std::slice::from_raw_parts_mut(dst.mut_ptr(row as i32), width) };
+        for (col, (dst, gray)) in dst_row.iter_mut().zip(gray_row).enumerate() {
+            let mut level = *gray as u32 / step;
+            if (*gray as u32 - level * step) * 256 / step > thresholds.threshold(col, row) as u32 {
+                level += 1;
+            }
+            *dst = (level.min(n_levels - 1) * step) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grey4() {
+        let mut src = ImageBuffer::new(ImageFormat::BGRA, 64, 4, None);
+        for (i, v) in src.mut_data().iter_mut().enumerate() {
+            *v = (i / 4 % 64 * 4) as u8; // horizontal gradient
+        }
+        let dst = floyd_steinberg(&src, GREY4_TARGET_COLOR_SPACE);
+        assert!(dst.data().iter().all(|x| [0x00, 0x50, 0xa0, 0xf0].contains(x)));
+        assert_eq!(dst.data()[0], 0x00);
+        assert_eq!(dst.data()[63], 0xf0);
+    }
+
+    #[test]
+    fn test_ordered() {
+        // flat gray 100 is between the DU4 levels 0x50 and 0xa0, a quarter of the way
+        let gray = vec![100; 64 * 4];
+        let mut dst = ImageBuffer::new(ImageFormat::Mono8Bpp, 64, 4, None);
+        let thresholds = ThresholdMap::bayer(4);
+        ordered(&gray, &mut dst, GREY4_TARGET_COLOR_SPACE, &thresholds);
+        assert!(dst.data().iter().all(|x| [0x50, 0xa0].contains(x)));
+        assert_eq!(dst.data().iter().filter(|x| **x == 0xa0).count(), 64 * 4 / 4);
+
+        let gray = (0..=255).collect::<Vec<u8>>();
+        let mut dst = ImageBuffer::new(ImageFormat::Mono8Bpp, 256, 1, None);
+        ordered(&gray, &mut dst, GREY16_TARGET_COLOR_SPACE, &thresholds);
+        assert_eq!(dst.data()[0], 0);
+        assert_eq!(dst.data()[255], 0xf0);
+        assert!(dst.data().windows(2).all(|x| x[0].abs_diff(x[1]) <= 0x10));
+    }
+
+    #[test]
+    fn test_error_diffusion() {
+        let mut src = ImageBuffer::new(ImageFormat::BGRA, 64, 64, None);
+        src.fill(100);
+        let mut gray = [0u8];
+        simd::bgra_to_gray(&src.data()[..4], &mut gray);
+        for kernel in [DiffusionKernel::FloydSteinberg, DiffusionKernel::Atkinson, DiffusionKernel::JarvisJudiceNinke,
+                       DiffusionKernel::Stucki, DiffusionKernel::Sierra] {
+            for serpentine in [false, true] {
+                let method = ErrorDiffusion { kernel, serpentine };
+                assert_eq!(method.to_string().parse::<ErrorDiffusion>().unwrap(), method);
+                for target_color_space in [BW_TARGET_COLOR_SPACE, GREY4_TARGET_COLOR_SPACE, GREY16_TARGET_COLOR_SPACE] {
+                    let dst = error_diffusion(&src, target_color_space, method);
+                    let step = target_color_space.step();
+                    assert!(dst.data().iter().all(|x| x % step == 0 && x / step < target_color_space.n_levels()));
+                    // the error is diffused both ways, so the average level is preserved
+                    let mean = dst.data().iter().map(|x| *x as f64).sum::<f64>() / dst.data().len() as f64;
+                    let tolerance = if kernel == DiffusionKernel::Atkinson { 8.0 } else { 2.0 };
+                    assert!((mean - gray[0] as f64).abs() < tolerance, "{} {:?} {}", method, target_color_space, mean);
+                }
             }
-            next_row_additions[col] += residual_16th * 5;
-            next_row_additions[col+1] += residual_16th;
         }
-        std::mem::swap(&mut current_row_additions, &mut next_row_additions);
-        next_row_additions.fill(0);
+        assert!("fs_serpentine".parse::<ErrorDiffusion>().unwrap().serpentine);
+        assert!("unknown".parse::<ErrorDiffusion>().is_err());
     }
-    return dst;
 }
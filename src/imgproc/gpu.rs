@@ -1,40 +1,127 @@
+use std::sync::Arc;
+
 use log::debug;
 use wgpu::util::DeviceExt;
 
-use super::{DitheringMethod, MonoImgprocOptions, Rotation};
+use super::dithering::TargetColorSpace;
+use super::hybrid::BLOCK_SIZE;
+use super::stroke::Embolden;
+use super::threshold_map::ThresholdMap;
+use super::{coord_transform, dithering_thresholds, DitheringMethod, MonoImgprocOptions, DEFAULT_NAIVE_THRESHOLD};
 use crate::image::*;
 
-pub struct MonoImgproc {
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GpuOutput {
+    Mono, // packed 1bpp black or white, `main` in the shader
+    Gray { n_levels: u32, level_step: u32 }, // 8bpp, `main_gray` in the shader
+}
+
+impl GpuOutput {
+    fn format(&self) -> ImageFormat {
+        match self {
+            GpuOutput::Mono => ImageFormat::Mono1Bpp,
+            GpuOutput::Gray { .. } => ImageFormat::Mono8Bpp,
+        }
+    }
+
+    fn entry_point(&self) -> &'static str {
+        match self {
+            GpuOutput::Mono => "main",
+            GpuOutput::Gray { .. } => "main_gray",
+        }
+    }
+
+    // bindings used by the entry point, the gray one does not use the block info of the hybrid method
+    fn n_bindings(&self) -> usize {
+        match self {
+            GpuOutput::Mono => 6,
+            GpuOutput::Gray { .. } => 5,
+        }
+    }
+
+    fn pixels_per_invocation(&self) -> i32 {
+        match self {
+            GpuOutput::Mono => 1,
+            GpuOutput::Gray { .. } => 4,
+        }
+    }
+}
+
+struct GpuImgproc {
     opts: MonoImgprocOptions,
+    output: GpuOutput,
 
     device: wgpu::Device,
     queue: wgpu::Queue,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
     pipeline: wgpu::ComputePipeline,
 
+    params_buffer: wgpu::Buffer,
     input_buffer: wgpu::Buffer,
     input_stage_buffer: wgpu::Buffer,
     output_buffer: wgpu::Buffer,
     output_stage_buffer: wgpu::Buffer,
+    coord_transform_buffer: wgpu::Buffer,
 
-    dithering_threshold_buffer: wgpu::Buffer,
+    dithering_threshold_buffer: wgpu::Buffer, // re-created if a larger threshold map is used
+    block_info_buffer: wgpu::Buffer,
+    // `main_blocks` in the shader, classifying blocks for the hybrid method (mono only)
+    blocks_pass: Option<(wgpu::ComputePipeline, wgpu::BindGroup)>,
     current_dithering_method: DitheringMethod,
+    current_thresholds: Arc<ThresholdMap>, // of `current_dithering_method`
+    naive_threshold: u8, // threshold for DitheringMethod::NoDithering
+    font_recovery: bool,
+    embolden: Embolden,
 }
 
 const WORKGROUP_SIZE: (i32, i32) = (64, 1);
+// offset of map_width and map_height in Params of the shader
+const PARAMS_MAP_SIZE_OFFSET: u64 = 32;
+// offset of hybrid and font_recovery in Params
+const PARAMS_HYBRID_OFFSET: u64 = 40;
+const PARAMS_FONT_RECOVERY_OFFSET: u64 = 44;
+// offset of embolden_x and embolden_y in Params
+const PARAMS_EMBOLDEN_OFFSET: u64 = 48;
+
+// one u32 per threshold
+fn dithering_thresholds_buf(map: &ThresholdMap) -> Vec<u8> {
+    map.thresholds.iter().flat_map(|x| (*x as u32).to_le_bytes()).collect()
+}
 
-const BAYERS4_THRESHOLDS: [u32; 16] = [
-    0, 128, 32, 160, 192, 64, 224, 96, 48, 176, 16, 144, 240, 112, 208, 80,
-];
-
-const BAYERS2_THRESHOLDS: [u32; 16] = [
-    0, 128, 0, 128, 192, 64, 192, 64, 0, 128, 0, 128, 192, 64, 192, 64,
-];
+fn create_dithering_threshold_buffer(device: &wgpu::Device, size: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("dithering_thresholds"),
+        size: size as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
 
-const NO_DITHERING_THRESHOLDS: [u32; 16] = [128; 16];
+// (binding, buffer) pairs
+fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout,
+                     buffers: &[(u32, &wgpu::Buffer)]) -> wgpu::BindGroup {
+    let entries: Vec<wgpu::BindGroupEntry> = buffers
+        .iter()
+        .map(|(binding, buffer)| wgpu::BindGroupEntry {
+            binding: *binding,
+            resource: buffer.as_entire_binding(),
+        })
+        .collect();
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &entries,
+    })
+}
 
-fn dithering_thresholds_buf(v: &[u32; 16]) -> &[u8] {
-    unsafe { std::slice::from_raw_parts(v.as_ptr() as *const u8, 16 * 4) }
+// bind group of the main entry point, buffers of binding 0, 1, ...
+fn create_main_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, output: GpuOutput,
+                          buffers: [&wgpu::Buffer; 6]) -> wgpu::BindGroup {
+    let entries: Vec<(u32, &wgpu::Buffer)> = buffers.into_iter().take(output.n_bindings()).enumerate()
+        .map(|(i, buffer)| (i as u32, buffer))
+        .collect();
+    create_bind_group(device, layout, &entries)
 }
 
 fn mat_transpose<const COL: usize, const ROW: usize, const SIZE: usize>(
@@ -54,20 +141,19 @@ fn optimal_pitch(width: i32, bpp: i32) -> i32 {
     (((width * bpp + 7) / 8) + 7) / 8 * 8  // 8 byte aligned
 }
 
-impl MonoImgproc {
-    pub async fn new_async(opts: MonoImgprocOptions) -> Self {
+impl GpuImgproc {
+    async fn new_async(opts: MonoImgprocOptions, output: GpuOutput) -> anyhow::Result<Self> {
         let instance = wgpu::Instance::default();
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions::default())
             .await
-            .unwrap();
+            .ok_or_else(|| anyhow::format_err!("No GPU adapter found"))?;
         debug!("Initializing GPU imgproc: {:?}", adapter.get_info());
 
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor::default(), None)
-            .await
-            .unwrap();
+            .await?;
 
         let input_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("input"),
@@ -82,9 +168,10 @@ impl MonoImgproc {
             mapped_at_creation: false,
         });
 
+        let output_pitch = optimal_pitch(opts.output_size.width, output.format().bpp());
         let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("output"),
-            size: (opts.output_size.height * optimal_pitch(opts.output_size.width, 1)) as u64,
+            size: (opts.output_size.height * output_pitch) as u64,
             usage: wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_SRC
                 | wgpu::BufferUsages::COPY_DST,
@@ -92,35 +179,39 @@ impl MonoImgproc {
         });
         let output_stage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("output_staging"),
-            size: (opts.output_size.height * optimal_pitch(opts.output_size.width, 1)) as u64,
+            size: (opts.output_size.height * output_pitch) as u64,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
 
-        let params_data: Vec<i32> = vec![
-            opts.input_size.width,
-            opts.input_size.height,
-            optimal_pitch(opts.input_size.width, 32),
-            opts.output_size.width,
-            opts.output_size.height,
-            optimal_pitch(opts.output_size.width, 1),
+        let (n_levels, level_step) = match output {
+            GpuOutput::Mono => (2, 256),
+            GpuOutput::Gray { n_levels, level_step } => (n_levels, level_step),
+        };
+        let initial_thresholds = dithering_thresholds(DitheringMethod::Bayers4, DEFAULT_NAIVE_THRESHOLD);
+        let params_data: Vec<u32> = vec![
+            opts.input_size.width as u32,
+            opts.input_size.height as u32,
+            optimal_pitch(opts.input_size.width, 32) as u32,
+            opts.output_size.width as u32,
+            opts.output_size.height as u32,
+            output_pitch as u32,
+            n_levels,
+            level_step,
+            initial_thresholds.width as u32,
+            initial_thresholds.height as u32,
+            0, // hybrid
+            0, // font_recovery
+            0, // embolden_x
+            0, // embolden_y
         ];
         let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("params"),
-            contents: unsafe { std::slice::from_raw_parts(params_data.as_ptr() as *const u8, 24) },
+            contents: unsafe { std::slice::from_raw_parts(params_data.as_ptr() as *const u8, params_data.len() * 4) },
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let coord_transform_data = mat_transpose::<3, 2, 6>(match opts.rotation {
-            Rotation::NoRotation => [1.0, 0.0, 0.0,
-                                     0.0, 1.0, 0.0],
-            Rotation::Rotate90 => [0.0, 1.0, 0.0,
-                                   -1.0, 0.0, opts.output_size.width as f32],
-            Rotation::Rotate180 => [-1.0, 0.0, opts.output_size.width as f32,
-                                    0.0, -1.0, opts.output_size.height as f32],
-            Rotation::Rotate270 => [0.0, -1.0, opts.output_size.height as f32,
-                                    1.0, 0.0, 1.0],
-        });
+        let coord_transform_data = mat_transpose::<3, 2, 6>(coord_transform(&opts));
         let coord_transform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("coord_transform"),
             contents: unsafe { std::slice::from_raw_parts(coord_transform_data.as_ptr() as *const u8, 24) },
@@ -129,11 +220,20 @@ impl MonoImgproc {
 
         let dithering_threshold_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("bayers4"),
-                contents: dithering_thresholds_buf(&BAYERS4_THRESHOLDS),
+                label: Some("dithering_thresholds"),
+                contents: &dithering_thresholds_buf(&initial_thresholds),
                 usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
             });
 
+        let n_blocks = (opts.output_size.width as usize).div_ceil(BLOCK_SIZE)
+            * (opts.output_size.height as usize).div_ceil(BLOCK_SIZE);
+        let block_info_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("block_info"),
+            size: (n_blocks.max(1) * 4) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
         let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
             source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("gpu.wgsl"))),
@@ -143,51 +243,111 @@ impl MonoImgproc {
             label: None,
             layout: None,
             module: &shader_module,
-            entry_point: "main",
+            entry_point: output.entry_point(),
         });
         let bind_group_layout = pipeline.get_bind_group_layout(0);
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: params_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: input_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: output_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: dithering_threshold_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: coord_transform_buffer.as_entire_binding(),
-                },
-            ],
+        let bind_group = create_main_bind_group(
+            &device,
+            &bind_group_layout,
+            output,
+            [&params_buffer, &input_buffer, &output_buffer, &dithering_threshold_buffer, &coord_transform_buffer,
+             &block_info_buffer],
+        );
+        let blocks_pass = (output == GpuOutput::Mono).then(|| {
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: None,
+                module: &shader_module,
+                entry_point: "main_blocks",
+            });
+            let bind_group = create_bind_group(
+                &device,
+                &pipeline.get_bind_group_layout(0),
+                &[(0, &params_buffer), (1, &input_buffer), (4, &coord_transform_buffer), (5, &block_info_buffer)],
+            );
+            (pipeline, bind_group)
         });
 
-        Self {
+        Ok(Self {
             opts,
+            output,
             device,
             queue,
+            bind_group_layout,
             bind_group,
             pipeline,
+            params_buffer,
             input_buffer,
             input_stage_buffer,
             output_buffer,
             output_stage_buffer,
+            coord_transform_buffer,
             dithering_threshold_buffer,
+            block_info_buffer,
+            blocks_pass,
             current_dithering_method: DitheringMethod::Bayers4,
+            current_thresholds: initial_thresholds,
+            naive_threshold: DEFAULT_NAIVE_THRESHOLD,
+            font_recovery: false,
+            embolden: Embolden::default(),
+        })
+    }
+
+    fn set_naive_threshold(&mut self, threshold: u8) {
+        if threshold != self.naive_threshold {
+            self.naive_threshold = threshold;
+            if self.current_dithering_method == DitheringMethod::NoDithering {
+                self.write_dithering_thresholds(DitheringMethod::NoDithering);
+            }
+        }
+    }
+
+    fn set_font_recovery(&mut self, enabled: bool) {
+        if enabled != self.font_recovery {
+            self.font_recovery = enabled;
+            self.queue.write_buffer(&self.params_buffer, PARAMS_FONT_RECOVERY_OFFSET, &(enabled as u32).to_le_bytes());
         }
     }
 
+    fn set_embolden(&mut self, embolden: Embolden) {
+        if embolden != self.embolden {
+            self.embolden = embolden;
+            let (radius_x, radius_y) = embolden.radius_xy();
+            let radius = [radius_x as u32, radius_y as u32];
+            self.queue.write_buffer(&self.params_buffer, PARAMS_EMBOLDEN_OFFSET,
+                                    unsafe { std::slice::from_raw_parts(radius.as_ptr() as *const u8, 8) });
+        }
+    }
+
+    fn write_dithering_thresholds(&mut self, dithering_method: DitheringMethod) {
+        let thresholds = dithering_thresholds(dithering_method, self.naive_threshold);
+        let data = dithering_thresholds_buf(&thresholds);
+        if data.len() as u64 > self.dithering_threshold_buffer.size() {
+            self.dithering_threshold_buffer = create_dithering_threshold_buffer(&self.device, data.len());
+            self.bind_group = create_main_bind_group(
+                &self.device,
+                &self.bind_group_layout,
+                self.output,
+                [
+                    &self.params_buffer,
+                    &self.input_buffer,
+                    &self.output_buffer,
+                    &self.dithering_threshold_buffer,
+                    &self.coord_transform_buffer,
+                    &self.block_info_buffer,
+                ],
+            );
+        }
+        self.queue.write_buffer(&self.dithering_threshold_buffer, 0, &data);
+        let map_size = [thresholds.width as u32, thresholds.height as u32];
+        self.queue.write_buffer(&self.params_buffer, PARAMS_MAP_SIZE_OFFSET,
+                                unsafe { std::slice::from_raw_parts(map_size.as_ptr() as *const u8, 8) });
+        let hybrid = (dithering_method == DitheringMethod::Hybrid) as u32;
+        self.queue.write_buffer(&self.params_buffer, PARAMS_HYBRID_OFFSET, &hybrid.to_le_bytes());
+        self.current_dithering_method = dithering_method;
+        self.current_thresholds = thresholds;
+    }
+
     fn map_buffer_sync(&self, buffer_slice: &wgpu::BufferSlice, mode: wgpu::MapMode) {
         let (sender, receiver) = std::sync::mpsc::channel::<()>();
         buffer_slice.map_async(mode, move |v| {
@@ -217,28 +377,25 @@ impl MonoImgproc {
     }
 
     fn read_output<T: Image + ?Sized>(&self, output_img: &mut T) {
-        assert_eq!(output_img.format(), ImageFormat::Mono1Bpp);
+        let format = self.output.format();
+        assert_eq!(output_img.format(), format);
         assert_eq!(output_img.size(), self.opts.output_size);
         let slice = self.output_stage_buffer.slice(..);
         self.map_buffer_sync(&slice, wgpu::MapMode::Read);
         let output_buf = slice.get_mapped_range();
         let output_buf_img = ConstImageView::new(
-            ImageFormat::Mono1Bpp,
+            format,
             &output_buf,
             self.opts.output_size.width,
             self.opts.output_size.height,
-            Some(optimal_pitch(self.opts.output_size.width, 1)),
+            Some(optimal_pitch(self.opts.output_size.width, format.bpp())),
         );
         output_img.copy_from(&output_buf_img);
         drop(output_buf);
         self.output_stage_buffer.unmap();
     }
 
-    pub fn new(options: MonoImgprocOptions) -> Self {
-        pollster::block_on(Self::new_async(options))
-    }
-
-    pub fn process<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
+    fn process<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
         &mut self,
         input_img: &InputT,
         output_img: &mut OutputT,
@@ -247,17 +404,11 @@ impl MonoImgproc {
         let t_start = std::time::Instant::now();
 
         self.write_input(input_img);
-        if dithering_method != self.current_dithering_method {
-            self.queue.write_buffer(
-                &self.dithering_threshold_buffer,
-                0,
-                dithering_thresholds_buf(match dithering_method {
-                    DitheringMethod::Bayers2 => &BAYERS2_THRESHOLDS,
-                    DitheringMethod::Bayers4 => &BAYERS4_THRESHOLDS,
-                    DitheringMethod::NoDithering => &NO_DITHERING_THRESHOLDS,
-                }),
-            );
-            self.current_dithering_method = dithering_method;
+        // maps loaded from files are reloaded when modified, see `threshold_map::loaded`
+        let reloaded = matches!(dithering_method, DitheringMethod::Map(_))
+            && !Arc::ptr_eq(&dithering_thresholds(dithering_method, self.naive_threshold), &self.current_thresholds);
+        if dithering_method != self.current_dithering_method || reloaded {
+            self.write_dithering_thresholds(dithering_method);
         }
 
         let t_uploaded = std::time::Instant::now();
@@ -280,10 +431,22 @@ impl MonoImgproc {
 
         {
             let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            let blocks_pass = self.blocks_pass.as_ref().filter(|_| dithering_method == DitheringMethod::Hybrid);
+            if let Some((pipeline, bind_group)) = blocks_pass {
+                cpass.set_pipeline(pipeline);
+                cpass.set_bind_group(0, bind_group, &[]);
+                let block_size = BLOCK_SIZE as f32;
+                cpass.dispatch_workgroups(
+                    (self.opts.output_size.width as f32 / block_size / WORKGROUP_SIZE.0 as f32).ceil() as u32,
+                    (self.opts.output_size.height as f32 / block_size / WORKGROUP_SIZE.1 as f32).ceil() as u32,
+                    1,
+                );
+            }
             cpass.set_pipeline(&self.pipeline);
             cpass.set_bind_group(0, &self.bind_group, &[]);
             cpass.dispatch_workgroups(
-                (self.opts.output_size.width as f32 / WORKGROUP_SIZE.0 as f32).ceil() as u32,
+                (self.opts.output_size.width as f32
+                    / (WORKGROUP_SIZE.0 * self.output.pixels_per_invocation()) as f32).ceil() as u32,
                 (self.opts.output_size.height as f32 / WORKGROUP_SIZE.1 as f32).ceil() as u32,
                 1,
             );
@@ -305,7 +468,8 @@ impl MonoImgproc {
         let t_downloaded = std::time::Instant::now();
 
         debug!(
-            "GPU imgproc processed one frame {:?}: upload {:?}, compute {:?}, download {:?}",
+            "GPU imgproc processed one {:?} frame {:?}: upload {:?}, compute {:?}, download {:?}",
+            self.output,
             self.opts.output_size,
             t_uploaded - t_start,
             t_computed - t_uploaded,
@@ -314,9 +478,65 @@ impl MonoImgproc {
     }
 }
 
+pub struct GpuMonoImgproc(GpuImgproc);
+
+impl GpuMonoImgproc {
+    pub async fn new_async(opts: MonoImgprocOptions) -> anyhow::Result<Self> {
+        Ok(Self(GpuImgproc::new_async(opts, GpuOutput::Mono).await?))
+    }
+
+    pub fn new(opts: MonoImgprocOptions) -> anyhow::Result<Self> {
+        pollster::block_on(Self::new_async(opts))
+    }
+
+    pub fn set_naive_threshold(&mut self, threshold: u8) {
+        self.0.set_naive_threshold(threshold);
+    }
+
+    pub fn set_font_recovery(&mut self, enabled: bool) {
+        self.0.set_font_recovery(enabled);
+    }
+
+    pub fn set_embolden(&mut self, embolden: Embolden) {
+        self.0.set_embolden(embolden);
+    }
+
+    pub fn process<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
+        &mut self,
+        input_img: &InputT,
+        output_img: &mut OutputT,
+        dithering_method: DitheringMethod,
+    ) {
+        self.0.process(input_img, output_img, dithering_method);
+    }
+}
+
+// Convert BGRA frames to 8bpp gray of the levels in `target_color_space`, with rotation and ordered dithering
+pub struct GpuGrayImgproc(GpuImgproc);
+
+impl GpuGrayImgproc {
+    pub fn new(opts: MonoImgprocOptions, target_color_space: TargetColorSpace) -> anyhow::Result<Self> {
+        let output = GpuOutput::Gray {
+            n_levels: target_color_space.n_levels() as u32,
+            level_step: target_color_space.step() as u32,
+        };
+        Ok(Self(pollster::block_on(GpuImgproc::new_async(opts, output))?))
+    }
+
+    pub fn process<InputT: ConstImage + ?Sized, OutputT: Image + ?Sized>(
+        &mut self,
+        input_img: &InputT,
+        output_img: &mut OutputT,
+        dithering_method: DitheringMethod,
+    ) {
+        self.0.process(input_img, output_img, dithering_method);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::imgproc::{dithering, GrayImgproc, ImgprocBackend, Rotation};
 
     #[test]
     fn test_basic() {
@@ -333,11 +553,11 @@ mod tests {
             let mut output_img_data: Vec<u8> = vec![0; 4];
             let mut output_img = ImageView::new(ImageFormat::Mono1Bpp, output_img_data.as_mut_slice(), 32, 1, None);
 
-            let mut imgproc = MonoImgproc::new(MonoImgprocOptions {
+            let mut imgproc = GpuMonoImgproc::new(MonoImgprocOptions {
                 input_size: color_img.size(),
                 output_size: output_img.size(),
                 rotation: Rotation::NoRotation,
-            });
+            }).unwrap();
             imgproc.process(&color_img, &mut output_img, DitheringMethod::Bayers4);
 
             drop(output_img);
@@ -352,11 +572,11 @@ mod tests {
             let mut output_img_data: Vec<u8> = vec![0; 4];
             let mut output_img = ImageView::new(ImageFormat::Mono1Bpp, output_img_data.as_mut_slice(), 32, 1, None);
 
-            let mut imgproc = MonoImgproc::new(MonoImgprocOptions {
+            let mut imgproc = GpuMonoImgproc::new(MonoImgprocOptions {
                 input_size: color_img.size(),
                 output_size: output_img.size(),
                 rotation: Rotation::Rotate180,
-            });
+            }).unwrap();
             imgproc.process(&color_img, &mut output_img, DitheringMethod::Bayers4);
 
             drop(output_img);
@@ -380,11 +600,11 @@ mod tests {
         let color_img = ConstImageView::new(ImageFormat::BGRA, input_img_data.as_slice(), 2048, 1024, None);
 
         let mut output_img_data: Vec<u8> = vec![0; 256*1024];
-        let mut imgproc = MonoImgproc::new(MonoImgprocOptions {
+        let mut imgproc = GpuMonoImgproc::new(MonoImgprocOptions {
             input_size: color_img.size(),
             output_size: (2048, 1024).into(),
             rotation: Rotation::NoRotation,
-        });
+        }).unwrap();
         for _ in 0..10 {
             let mut output_img = ImageView::new(ImageFormat::Mono1Bpp, output_img_data.as_mut_slice(), 2048, 1024, None);
             output_img.fill(0);
@@ -413,11 +633,11 @@ mod tests {
         let mut output_img_data: Vec<u8> = vec![0; 16];
         let mut output_img = ImageView::new(ImageFormat::Mono1Bpp, output_img_data.as_mut_slice(), 32, 4, Some(4));
 
-        let mut imgproc = MonoImgproc::new(MonoImgprocOptions {
+        let mut imgproc = GpuMonoImgproc::new(MonoImgprocOptions {
             input_size: color_img.size(),
             output_size: output_img.size(),
             rotation: Rotation::Rotate90,
-        });
+        }).unwrap();
         imgproc.process(&color_img, &mut output_img, DitheringMethod::NoDithering);
         drop(output_img);
 
@@ -446,11 +666,11 @@ mod tests {
         let mut output_img_data: Vec<u8> = vec![0; 16];
         let mut output_img = ImageView::new(ImageFormat::Mono1Bpp, output_img_data.as_mut_slice(), 32, 4, Some(4));
 
-        let mut imgproc = MonoImgproc::new(MonoImgprocOptions {
+        let mut imgproc = GpuMonoImgproc::new(MonoImgprocOptions {
             input_size: color_img.size(),
             output_size: output_img.size(),
             rotation: Rotation::Rotate90,
-        });
+        }).unwrap();
         imgproc.process(&color_img, &mut output_img, DitheringMethod::NoDithering);
         drop(output_img);
 
@@ -459,4 +679,46 @@ mod tests {
         assert_eq!(output_img_data[11], 255);
         assert_eq!(output_img_data[15], 127);
     }
+
+    #[test]
+    fn test_gray() {
+        // gradient from black to white, 70 pixels so that the last u32 is partial
+        let mut color_img = ImageBuffer::new(ImageFormat::BGRA, 70, 1, None);
+        for (i, v) in color_img.mut_data().iter_mut().enumerate() {
+            *v = ((i / 4) * 255 / 69) as u8;
+        }
+        for target_color_space in [dithering::GREY16_TARGET_COLOR_SPACE, dithering::GREY4_TARGET_COLOR_SPACE] {
+            let mut imgproc = GpuGrayImgproc::new(MonoImgprocOptions {
+                input_size: color_img.size(),
+                output_size: color_img.size(),
+                rotation: Rotation::NoRotation,
+            }, target_color_space).unwrap();
+            let mut output_img = ImageBuffer::new(ImageFormat::Mono8Bpp, 70, 1, None);
+            imgproc.process(&color_img, &mut output_img, DitheringMethod::Bayers4);
+
+            let (n_levels, step) = (target_color_space.n_levels() as u32, target_color_space.step() as u32);
+            let thresholds = dithering_thresholds(DitheringMethod::Bayers4, DEFAULT_NAIVE_THRESHOLD);
+            for x in 0..70 {
+                let v = color_img.data()[x * 4] as f32;
+                let gray = (0.3 * v + 0.59 * v + 0.11 * v) as u32;
+                let mut level = gray / step;
+                if (gray - level * step) * 256 / step > thresholds.threshold(x, 0) as u32 {
+                    level += 1;
+                }
+                assert_eq!(output_img.data()[x] as u32, level.min(n_levels - 1) * step, "{}", x);
+            }
+            assert_eq!(output_img.data()[0], 0);
+            assert_eq!(output_img.data()[69], 0xf0);
+
+            // the CPU implementation produces the same result
+            let mut cpu = GrayImgproc::ordered(MonoImgprocOptions {
+                input_size: color_img.size(),
+                output_size: color_img.size(),
+                rotation: Rotation::NoRotation,
+            }, target_color_space, ImgprocBackend::Cpu).unwrap();
+            let mut cpu_output_img = ImageBuffer::new(ImageFormat::Mono8Bpp, 70, 1, None);
+            cpu.process(&color_img, &mut cpu_output_img);
+            assert_eq!(cpu_output_img.data(), output_img.data());
+        }
+    }
 }
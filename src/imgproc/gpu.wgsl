@@ -5,6 +5,14 @@ struct Params {
   output_width: u32,
   output_height: u32,
   output_pitch: u32,
+  n_levels: u32,    // gray only
+  level_step: u32,  // gray only
+  map_width: u32,   // size of the threshold map
+  map_height: u32,
+  hybrid: u32,      // mono only, threshold by the classes of blocks in `block_info`, see hybrid.rs
+  font_recovery: u32,  // mono only, threshold glyph edges at `edge_threshold`, see antialias.rs
+  embolden_x: u32,  // mono only, radius of the stroke dilation in `stroke_gray`, see stroke.rs
+  embolden_y: u32,
 }
 
 @group(0) @binding(0)
@@ -15,23 +23,97 @@ var<uniform> params: Params;
 @group(0) @binding(1)
 var<storage, read> input_img: array<u32>;
 
-// the BW image is packed as 1 BIT per pixel
+// the BW image is packed as 1 BIT per pixel, the gray image is 8 bits per pixel
 
 @group(0) @binding(2)
 var<storage, read_write> output_img: array<u32>;
 
 @group(0) @binding(3)
-var<storage, read> thresholds4x4: array<u32>;
+var<storage, read> thresholds: array<u32>;
 
 @group(0) @binding(4)
 var<uniform> coord_transform: mat3x2<f32>;
 
+// `class | threshold << 8` of each block, written by `main_blocks`
+@group(0) @binding(5)
+var<storage, read_write> block_info: array<u32>;
+
+
+const BLOCK_SIZE: u32 = 16u;
+const FLAT_RANGE: u32 = 32u;
+const FLAT_THRESHOLD: u32 = 128u;
+const CLASS_FLAT: u32 = 0u;
+const CLASS_TEXT: u32 = 1u;
+const CLASS_IMAGE: u32 = 2u;
+
+fn block_cols() -> u32 {
+  return (params.output_width + BLOCK_SIZE - 1u) / BLOCK_SIZE;
+}
+
+fn block_rows() -> u32 {
+  return (params.output_height + BLOCK_SIZE - 1u) / BLOCK_SIZE;
+}
+
+fn block_class(col: u32, row: u32) -> u32 {
+  return block_info[row * block_cols() + col] & 0xffu;
+}
+
+// same as `BlockInfos::threshold` in hybrid.rs
+fn hybrid_threshold(x: u32, y: u32, map_threshold: u32) -> u32 {
+  let col = x / BLOCK_SIZE;
+  let row = y / BLOCK_SIZE;
+  let info = block_info[row * block_cols() + col];
+  let kind = info & 0xffu;
+  if (kind == CLASS_TEXT) {
+    return info >> 8u;
+  }
+  if (kind == CLASS_FLAT) {
+    let next_to_image = (col > 0u && block_class(col - 1u, row) == CLASS_IMAGE)
+      || (col + 1u < block_cols() && block_class(col + 1u, row) == CLASS_IMAGE)
+      || (row > 0u && block_class(col, row - 1u) == CLASS_IMAGE)
+      || (row + 1u < block_rows() && block_class(col, row + 1u) == CLASS_IMAGE);
+    if (!next_to_image) {
+      return FLAT_THRESHOLD;
+    }
+  }
+  return map_threshold;
+}
+
+const EDGE_CONTRAST: u32 = 128u;
+
+// the middle of the 3x3 neighbourhood if on a glyph edge, same as `edge_threshold` in antialias.rs
+fn edge_threshold(x: u32, y: u32, threshold: u32) -> u32 {
+  var lo: u32 = 255u;
+  var hi: u32 = 0u;
+  let x_end = min(x + 2u, params.output_width);
+  let y_end = min(y + 2u, params.output_height);
+  for (var ny: u32 = max(y, 1u) - 1u; ny < y_end; ny++) {
+    for (var nx: u32 = max(x, 1u) - 1u; nx < x_end; nx++) {
+      let gray = stroke_gray(nx, ny);
+      lo = min(lo, gray);
+      hi = max(hi, gray);
+    }
+  }
+  if (hi - lo >= EDGE_CONTRAST) {
+    return (lo + hi) / 2u;
+  }
+  return threshold;
+}
 
 fn rgb_to_gray_with_dithering(rgb: vec3<u32>, x: u32, y: u32) -> u32 {
-  let gray = 0.3 * f32(rgb.x) + 0.59 * f32(rgb.y) + 0.11 * f32(rgb.z); // Luminosity Method
+  var gray = u32(0.3 * f32(rgb.x) + 0.59 * f32(rgb.y) + 0.11 * f32(rgb.z)); // Luminosity Method
   // let gray = (rgb.x + rgb.y + rgb.z) / 3u;
-  let threshold = thresholds4x4[(y % 4u) * 4u + (x % 4u)];
-  if (u32(gray) <= threshold) {
+  if (params.embolden_x != 0u || params.embolden_y != 0u) {
+    gray = stroke_gray(x, y);
+  }
+  var threshold = thresholds[(y % params.map_height) * params.map_width + (x % params.map_width)];
+  if (params.hybrid != 0u) {
+    threshold = hybrid_threshold(x, y, threshold);
+  }
+  if (params.font_recovery != 0u) {
+    threshold = edge_threshold(x, y, threshold);
+  }
+  if (gray <= threshold) {
     return 0u;
   } else {
     return 1u;
@@ -81,4 +163,130 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>,
   if (x % 32u == 0u && y < params.output_height) {
     output_img[y * params.output_pitch / 4u + x / 32u] = *workgroup_output_32bit;
   }
-}
\ No newline at end of file
+}
+
+// gray level of an output pixel, white if out of range
+fn output_gray(x: u32, y: u32) -> u32 {
+  let input_coord: vec2<f32> = coord_transform * vec3(f32(x) + 0.5, f32(y) + 0.5, 1.0);
+  let input_coord_x: u32 = u32(input_coord.x);
+  let input_coord_y: u32 = u32(input_coord.y);
+  if (input_coord_x >= params.input_width || input_coord_y >= params.input_height) {
+    return 255u;
+  }
+  let bgra: u32 = input_img[input_coord_y * params.input_pitch / 4u + input_coord_x];
+  return u32(0.3 * f32((bgra >> 16u) & 0xffu) + 0.59 * f32((bgra >> 8u) & 0xffu) + 0.11 * f32(bgra & 0xffu));
+}
+
+// the darkest of the pixels up to `params.embolden_x` left and `params.embolden_y` above, which dilates dark strokes.
+// Same as `embolden` in stroke.rs
+fn stroke_gray(x: u32, y: u32) -> u32 {
+  var gray: u32 = 255u;
+  for (var ny: u32 = y - min(y, params.embolden_y); ny <= y; ny++) {
+    for (var nx: u32 = x - min(x, params.embolden_x); nx <= x; nx++) {
+      gray = min(gray, output_gray(nx, ny));
+    }
+  }
+  return gray;
+}
+
+// Classify a block of the output for the hybrid method, one block per invocation. Same as `block_info` in hybrid.rs
+@compute
+@workgroup_size(64,1)
+fn main_blocks(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let cols = block_cols();
+  if (global_id.x >= cols || global_id.y >= block_rows()) {
+    return;
+  }
+
+  var hist: array<u32, 256>;
+  var n: u32 = 0u;
+  var sum: u32 = 0u;
+  var min_gray: u32 = 255u;
+  var max_gray: u32 = 0u;
+  let x_end = min((global_id.x + 1u) * BLOCK_SIZE, params.output_width);
+  let y_end = min((global_id.y + 1u) * BLOCK_SIZE, params.output_height);
+  for (var y: u32 = global_id.y * BLOCK_SIZE; y < y_end; y++) {
+    for (var x: u32 = global_id.x * BLOCK_SIZE; x < x_end; x++) {
+      let gray = stroke_gray(x, y);
+      hist[gray] += 1u;
+      n += 1u;
+      sum += gray;
+      min_gray = min(min_gray, gray);
+      max_gray = max(max_gray, gray);
+    }
+  }
+
+  let index = global_id.y * cols + global_id.x;
+  let range = max_gray - min_gray;
+  if (range < FLAT_RANGE) {
+    block_info[index] = CLASS_FLAT;
+    return;
+  }
+  var mid: u32 = 0u;
+  for (var i: u32 = min_gray + range / 4u + 1u; i < max_gray - range / 4u; i++) {
+    mid += hist[i];
+  }
+  if (mid * 4u > n) {
+    block_info[index] = CLASS_IMAGE;
+    return;
+  }
+
+  // Otsu, see hybrid.rs
+  var n0: u32 = 0u;
+  var sum0: u32 = 0u;
+  var best_d: f32 = 0.0;
+  var best_w: f32 = 1.0;
+  var best_threshold: u32 = min_gray;
+  for (var t: u32 = min_gray; t < max_gray; t++) {
+    n0 += hist[t];
+    sum0 += t * hist[t];
+    let d = f32(i32(sum0 * n) - i32(sum * n0));
+    let w = f32(n0 * (n - n0));
+    if (d * d * best_w > best_d * best_d * w) {
+      best_d = d;
+      best_w = w;
+      best_threshold = t;
+    }
+  }
+  block_info[index] = CLASS_TEXT | (best_threshold << 8u);
+}
+
+// Quantize to `params.n_levels` levels (of value level * `params.level_step`) with dithering:
+// round up if the remainder, scaled to 0..256, is greater than the threshold
+fn gray_level(x: u32, y: u32) -> u32 {
+  let input_coord: vec2<f32> = coord_transform * vec3(f32(x) + 0.5, f32(y) + 0.5, 1.0);
+  let input_coord_x: u32 = u32(input_coord.x);
+  let input_coord_y: u32 = u32(input_coord.y);
+  if (input_coord_x >= params.input_width || input_coord_y >= params.input_height) {
+    return params.n_levels - 1u;  // default white
+  }
+
+  let bgra: u32 = input_img[input_coord_y * params.input_pitch / 4u + input_coord_x];
+  let gray = u32(0.3 * f32((bgra >> 16u) & 0xffu) + 0.59 * f32((bgra >> 8u) & 0xffu) + 0.11 * f32(bgra & 0xffu));
+  var level = gray / params.level_step;
+  let remainder = gray - level * params.level_step;
+  if (remainder * 256u / params.level_step > thresholds[(y % params.map_height) * params.map_width + (x % params.map_width)]) {
+    level += 1u;
+  }
+  return min(level, params.n_levels - 1u);
+}
+
+// 4 pixels (one u32) per invocation
+@compute
+@workgroup_size(64,1)
+fn main_gray(@builtin(global_invocation_id) global_id: vec3<u32>) {
+  let x0 = global_id.x * 4u;
+  let y = global_id.y;
+  if (x0 >= params.output_width || y >= params.output_height) {
+    return;
+  }
+
+  var output_word: u32 = 0u;
+  for (var i: u32 = 0u; i < 4u; i++) {
+    if (x0 + i < params.output_width) {
+      // little endian
+      output_word |= (gray_level(x0 + i, y) * params.level_step) << (i * 8u);
+    }
+  }
+  output_img[y * params.output_pitch / 4u + global_id.x] = output_word;
+}
@@ -0,0 +1,132 @@
+use super::threshold_map::ThresholdMap;
+
+// Text-aware hybrid binarization: the output is split into blocks, which are classified by their contrast
+// and by how bimodal their gray levels are. Text and UI blocks are thresholded at their (local) Otsu threshold,
+// so that glyphs are clean even if colored, while image blocks keep the ordered dithering.
+// Must match `main_blocks` and `hybrid_threshold` in gpu.wgsl exactly.
+
+pub const BLOCK_SIZE: usize = 16;
+// blocks of a smaller gray level range are flat
+const FLAT_RANGE: u32 = 32;
+// flat blocks are thresholded at this, unless next to an image block
+const FLAT_THRESHOLD: u8 = 128;
+
+// block info is `class | threshold << 8`
+const CLASS_FLAT: u32 = 0;
+const CLASS_TEXT: u32 = 1;
+const CLASS_IMAGE: u32 = 2;
+
+// Classify a block by the histogram of its gray levels
+fn block_info(hist: &[u32; 256]) -> u32 {
+    let min = hist.iter().position(|x| *x > 0).unwrap_or(0) as u32;
+    let max = hist.iter().rposition(|x| *x > 0).unwrap_or(0) as u32;
+    let range = max - min;
+    if range < FLAT_RANGE {
+        return CLASS_FLAT;
+    }
+    // text is mostly background and foreground, anti-aliased edges included;
+    // images (and gradients) have much more in between
+    let n: u32 = hist.iter().sum();
+    let mid: u32 = hist[(min + range / 4 + 1) as usize..(max - range / 4) as usize].iter().sum();
+    if mid * 4 > n {
+        return CLASS_IMAGE;
+    }
+
+    // Otsu: maximize the between class variance, n0 * n1 * (mean0 - mean1)^2, which is proportional to
+    // d^2 / (n0 * n1) with d = sum0 * n - sum * n0. The comparison avoids division, and products of f32 are
+    // correctly rounded in both WGSL and Rust, so the result is the same
+    let sum: u32 = hist.iter().enumerate().map(|(i, x)| i as u32 * x).sum();
+    let (mut n0, mut sum0) = (0u32, 0u32);
+    let (mut best_d, mut best_w, mut best_threshold) = (0.0f32, 1.0f32, min);
+    for t in min..max {
+        n0 += hist[t as usize];
+        sum0 += t * hist[t as usize];
+        let d = (sum0 * n) as i32 - (sum * n0) as i32;
+        let d = d as f32;
+        let w = (n0 * (n - n0)) as f32;
+        if d * d * best_w > best_d * best_d * w {
+            (best_d, best_w, best_threshold) = (d, w, t);
+        }
+    }
+    CLASS_TEXT | best_threshold << 8
+}
+
+pub struct BlockInfos {
+    cols: usize,
+    rows: usize,
+    infos: Vec<u32>,
+}
+
+impl BlockInfos {
+    // `gray` is the whole output, out of range pixels are white (255)
+    pub fn new(gray: &[u8], width: usize, height: usize) -> Self {
+        let (cols, rows) = (width.div_ceil(BLOCK_SIZE), height.div_ceil(BLOCK_SIZE));
+        let mut hists = vec![[0u32; 256]; cols];
+        let mut infos = Vec::with_capacity(cols * rows);
+        for (y, row) in gray.chunks_exact(width).enumerate() {
+            for (x, v) in row.iter().enumerate() {
+                hists[x / BLOCK_SIZE][*v as usize] += 1;
+            }
+            if y % BLOCK_SIZE == BLOCK_SIZE - 1 || y == height - 1 {
+                for hist in hists.iter_mut() {
+                    infos.push(block_info(hist));
+                    hist.fill(0);
+                }
+            }
+        }
+        BlockInfos { cols, rows, infos }
+    }
+
+    // a pixel is black if its gray level <= threshold, see `dithering_thresholds`
+    pub fn threshold(&self, x: usize, y: usize, map: &ThresholdMap) -> u8 {
+        let (col, row) = (x / BLOCK_SIZE, y / BLOCK_SIZE);
+        let info = self.infos[row * self.cols + col];
+        match info & 0xff {
+            CLASS_TEXT => (info >> 8) as u8,
+            CLASS_FLAT if !self.next_to_image(col, row) => FLAT_THRESHOLD,
+            // flat areas in images (e.g. the sky) are dithered like the rest of the image
+            _ => map.threshold(x, y),
+        }
+    }
+
+    fn next_to_image(&self, col: usize, row: usize) -> bool {
+        let is_image = |col: usize, row: usize| self.infos[row * self.cols + col] & 0xff == CLASS_IMAGE;
+        (col > 0 && is_image(col - 1, row))
+            || (col + 1 < self.cols && is_image(col + 1, row))
+            || (row > 0 && is_image(col, row - 1))
+            || (row + 1 < self.rows && is_image(col, row + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        // anti-aliased dark red text on white, a photo-like gradient, and flat gray
+        let (width, height) = (48, 16);
+        let mut gray = vec![0u8; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                gray[y * width + x] = match x / BLOCK_SIZE {
+                    0 if x % 8 == 1 => 70,
+                    0 if x % 8 == 2 => 160,
+                    0 => 250,
+                    1 => ((x - 16) * 12 + y * 2) as u8,
+                    _ => 200,
+                };
+            }
+        }
+        let infos = BlockInfos::new(&gray, width, height);
+        let map = ThresholdMap::bayer(4);
+        let threshold = infos.threshold(0, 0, &map);
+        assert!((70..250).contains(&threshold));
+        assert!((0..4).all(|y| infos.threshold(16, y, &map) == map.threshold(16, y)));
+        // flat, but next to the image
+        assert!((0..4).all(|y| infos.threshold(32, y, &map) == map.threshold(32, y)));
+
+        let infos = BlockInfos::new(&vec![200; width * height], width, height);
+        assert!((0..width).all(|x| infos.threshold(x, 0, &map) == FLAT_THRESHOLD));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::image::*;
 
-#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Rotation {
     NoRotation,
     Rotate90,
@@ -15,8 +16,32 @@ impl Rotation {
             Rotation::Rotate90 | Rotation::Rotate270 => (size.height, size.width).into(),
         }
     }
+
+    // map a rect in input (source) coordinate to output (screen) coordinate,
+    // consistent with the pixel mapping used by `rotate`
+    pub fn map_rect(&self, rect: Rect, output_size: Size) -> Rect {
+        let (x, y, w, h) = (rect.pt.x, rect.pt.y, rect.size.width, rect.size.height);
+        let (ow, oh) = (output_size.width, output_size.height);
+        let (pt, size): (Point, Size) = match self {
+            Rotation::NoRotation => ((x, y).into(), (w, h).into()),
+            Rotation::Rotate90 => ((ow - y - h, x).into(), (h, w).into()),
+            Rotation::Rotate180 => ((ow - x - w, oh - y - h).into(), (w, h).into()),
+            Rotation::Rotate270 => ((y, oh - x - w).into(), (h, w).into()),
+        };
+        Rect::new(pt, size).intersect(&Rect::new((0, 0).into(), output_size))
+    }
 }
 
+impl std::fmt::Display for Rotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use clap::ValueEnum;
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+// output pixels per side of the tiles processed at once, so that the rows of the input stay in the cache
+const ROTATE_TILE_SIZE: i32 = 64;
+
 pub fn rotate<T: ConstImage + ?Sized>(
     input_img: &T,
     rotation: Rotation,
@@ -34,23 +59,72 @@ pub fn rotate<T: ConstImage + ?Sized>(
             Rotation::Rotate270 => (output_size.height - 1 - y, x),
         }
     };
+    let in_range = |(input_x, input_y): (i32, i32)| {
+        input_x >= 0 && input_x < input_img.width() && input_y >= 0 && input_y < input_img.height()
+    };
+    let copy_pixel = |output_img: &mut ImageBuffer, x: i32, y: i32| {
+        let (input_x, input_y) = transform(x, y);
+        if in_range((input_x, input_y)) {
+            unsafe {
+                std::ptr::copy(
+                    input_img.ptr(input_y).add((input_x * bpp / 8) as usize),
+                    output_img.mut_ptr(y).add((x * bpp / 8) as usize),
+                    (bpp / 8) as usize,
+                );
+            }
+        }
+    };
 
-    for y in 0..output_size.height {
-        let row_ptr = output_img.mut_ptr(y);
-        for x in 0..output_size.width {
-            let (input_x, input_y) = transform(x, y);
-            if input_x >= 0
-                && input_x < input_img.width()
-                && input_y >= 0
-                && input_y < input_img.height()
-            {
-                let input_row_ptr = input_img.ptr(input_y);
-                unsafe {
-                    std::ptr::copy(
-                        input_row_ptr.add((input_x * bpp / 8) as usize),
-                        row_ptr.add((x * bpp / 8) as usize),
-                        (bpp / 8) as usize,
-                    );
+    match rotation {
+        Rotation::NoRotation => {
+            let row_bytes = (i32::min(input_img.width(), output_size.width) * bpp / 8) as usize;
+            for y in 0..i32::min(input_img.height(), output_size.height) {
+                unsafe { std::ptr::copy_nonoverlapping(input_img.ptr(y), output_img.mut_ptr(y), row_bytes) };
+            }
+        }
+        // rows become columns, transpose 4x4 blocks in tiles
+        Rotation::Rotate90 | Rotation::Rotate270 if bpp == 32 => {
+            let (input_pitch, output_pitch) = (input_img.pitch() as isize, output_img.pitch() as isize);
+            for tile_y in (0..output_size.height).step_by(ROTATE_TILE_SIZE as usize) {
+                for tile_x in (0..output_size.width).step_by(ROTATE_TILE_SIZE as usize) {
+                    let tile_bottom = i32::min(tile_y + ROTATE_TILE_SIZE, output_size.height);
+                    let tile_right = i32::min(tile_x + ROTATE_TILE_SIZE, output_size.width);
+                    for y in (tile_y..tile_bottom).step_by(4) {
+                        for x in (tile_x..tile_right).step_by(4) {
+                            let full_block = x + 4 <= tile_right && y + 4 <= tile_bottom
+                                && in_range(transform(x, y)) && in_range(transform(x + 3, y + 3));
+                            if !full_block {
+                                for (bx, by) in (x..i32::min(x + 4, tile_right))
+                                    .flat_map(|bx| (y..i32::min(y + 4, tile_bottom)).map(move |by| (bx, by)))
+                                {
+                                    copy_pixel(&mut output_img, bx, by);
+                                }
+                                continue;
+                            }
+                            unsafe {
+                                if rotation == Rotation::Rotate90 {
+                                    // input rows from bottom to top become the output rows
+                                    let (input_x, input_y) = transform(x, y);
+                                    crate::imgproc::simd::transpose4x4_u32(
+                                        input_img.ptr(input_y).add(input_x as usize * 4), -input_pitch,
+                                        output_img.mut_ptr(y).add(x as usize * 4), output_pitch);
+                                } else {
+                                    // input columns from right to left become the output rows
+                                    let (input_x, input_y) = transform(x, y + 3);
+                                    crate::imgproc::simd::transpose4x4_u32(
+                                        input_img.ptr(input_y).add(input_x as usize * 4), input_pitch,
+                                        output_img.mut_ptr(y + 3).add(x as usize * 4), -output_pitch);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        _ => {
+            for y in 0..output_size.height {
+                for x in 0..output_size.width {
+                    copy_pixel(&mut output_img, x, y);
                 }
             }
         }
@@ -77,4 +151,61 @@ mod tests {
                                   0, 0, 10, 11, 6, 7, 2, 3,
                                   0, 0, 0, 0, 0, 0, 0, 0]);
     }
+
+    // the 4x4 transposed blocks of 32bpp images must match the per-pixel copy, including the partial blocks
+    // at the tile and image edges, and the parts out of a smaller (or larger) input
+    #[test]
+    fn test_rotate_32bpp() {
+        let pixel = |img: &dyn ConstImage, x: i32, y: i32| -> u32 {
+            let offset = (y * img.pitch() + x * 4) as usize;
+            u32::from_le_bytes(img.data()[offset..offset + 4].try_into().unwrap())
+        };
+        let input_size: Size = (70, 131).into();
+        let mut input_img = ImageBuffer::new(ImageFormat::BGRA, input_size.width, input_size.height, Some(70 * 4 + 12));
+        for y in 0..input_size.height {
+            for x in 0..input_size.width {
+                let offset = (y * input_img.pitch() + x * 4) as usize;
+                let value = ((y + 1) << 16 | (x + 1)) as u32;
+                input_img.mut_data()[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        for rotation in [Rotation::NoRotation, Rotation::Rotate90, Rotation::Rotate180, Rotation::Rotate270] {
+            let rotated_size = rotation.rotated_size(input_size);
+            for output_size in [rotated_size, (rotated_size.width - 7, rotated_size.height + 5).into(),
+                                (rotated_size.width + 3, rotated_size.height - 10).into()] {
+                let output_img = rotate(&input_img, rotation, output_size);
+                for y in 0..output_size.height {
+                    for x in 0..output_size.width {
+                        let (input_x, input_y) = match rotation {
+                            Rotation::NoRotation => (x, y),
+                            Rotation::Rotate90 => (y, output_size.width - 1 - x),
+                            Rotation::Rotate180 => (output_size.width - 1 - x, output_size.height - 1 - y),
+                            Rotation::Rotate270 => (output_size.height - 1 - y, x),
+                        };
+                        let expected = if input_x >= 0 && input_x < input_size.width
+                            && input_y >= 0 && input_y < input_size.height {
+                            pixel(&input_img, input_x, input_y)
+                        } else {
+                            0
+                        };
+                        assert_eq!(pixel(&output_img, x, y), expected, "{:?} {:?} at ({}, {})",
+                                   rotation, output_size, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_map_rect() {
+        let rect = Rect::new((1, 2).into(), (3, 4).into());
+        let output_size: Size = (20, 10).into();
+        assert_eq!(Rotation::NoRotation.map_rect(rect, output_size), rect);
+        assert_eq!(Rotation::Rotate90.map_rect(rect, output_size),
+                   Rect::new((14, 1).into(), (4, 3).into()));
+        assert_eq!(Rotation::Rotate180.map_rect(rect, output_size),
+                   Rect::new((16, 4).into(), (3, 4).into()));
+        assert_eq!(Rotation::Rotate270.map_rect(rect, output_size),
+                   Rect::new((2, 6).into(), (4, 3).into()));
+    }
 }
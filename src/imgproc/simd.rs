@@ -0,0 +1,376 @@
+use std::sync::OnceLock;
+
+// Vectorized kernels of the CPU image pipeline.
+// SSE2 (x86_64) or NEON (aarch64) is selected at runtime, other CPUs use the scalar implementations.
+// Every vectorized kernel produces exactly the same output as the scalar one: the SIMD part handles
+// whole chunks and returns how many pixels it has done, and the scalar part finishes the rest.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimdLevel {
+    Scalar,
+    Sse2,
+    Neon,
+}
+
+// detected on first use
+pub fn simd_level() -> SimdLevel {
+    static LEVEL: OnceLock<SimdLevel> = OnceLock::new();
+    *LEVEL.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("sse2") {
+            return SimdLevel::Sse2;
+        }
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return SimdLevel::Neon;
+        }
+        SimdLevel::Scalar
+    })
+}
+
+// gray[i] = luminosity of the BGRA pixel bgra[i * 4..i * 4 + 4]
+pub fn bgra_to_gray(bgra: &[u8], gray: &mut [u8]) {
+    bgra_to_gray_with(simd_level(), bgra, gray)
+}
+
+fn bgra_to_gray_with(level: SimdLevel, bgra: &[u8], gray: &mut [u8]) {
+    assert!(bgra.len() >= gray.len() * 4);
+    let done = match level {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 => unsafe { sse2::bgra_to_gray(bgra, gray) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => unsafe { neon::bgra_to_gray(bgra, gray) },
+        _ => 0,
+    };
+    scalar::bgra_to_gray(&bgra[done * 4..], &mut gray[done..]);
+}
+
+// Pack 8bpp pixels to 1bpp (from the least significant bit), a bit is set if the pixel is greater than
+// `thresholds[x]`. The unused bits of the last byte are cleared.
+pub fn pack_above_thresholds(src: &[u8], thresholds: &[u8], dst: &mut [u8]) {
+    pack_above_thresholds_with(simd_level(), src, thresholds, dst)
+}
+
+fn pack_above_thresholds_with(level: SimdLevel, src: &[u8], thresholds: &[u8], dst: &mut [u8]) {
+    assert!(dst.len() >= src.len().div_ceil(8));
+    assert!(thresholds.len() >= src.len());
+    let done = match level {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 => unsafe { sse2::pack_above_thresholds(src, thresholds, dst) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => unsafe { neon::pack_above_thresholds(src, thresholds, dst) },
+        _ => 0,
+    };
+    scalar::pack_above_thresholds(&src[done..], &thresholds[done..], &mut dst[done / 8..]);
+}
+
+const fn unpack_lut() -> [[u8; 8]; 256] {
+    let mut lut = [[0u8; 8]; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut j = 0;
+        while j < 8 {
+            if (i >> j) & 1 == 1 {
+                lut[i][j] = 0xff;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    lut
+}
+
+static UNPACK_LUT: [[u8; 8]; 256] = unpack_lut();
+
+// Unpack 1bpp pixels (from the least significant bit) to 8bpp 0x00 or 0xff, 8 pixels per lookup
+pub fn unpack_bits(src: &[u8], dst: &mut [u8]) {
+    assert!(src.len() >= dst.len().div_ceil(8));
+    let n = dst.len();
+    let mut chunks = dst.chunks_exact_mut(8);
+    for (d, s) in (&mut chunks).zip(src) {
+        d.copy_from_slice(&UNPACK_LUT[*s as usize]);
+    }
+    let remainder = chunks.into_remainder();
+    if !remainder.is_empty() {
+        let s = src[n / 8];
+        remainder.copy_from_slice(&UNPACK_LUT[s as usize][..remainder.len()]);
+    }
+}
+
+// Transpose a block of 4x4 32bpp pixels, i.e. pixel (x, y) of src goes to (y, x) of dst.
+// The pitches may be negative to flip the block.
+// Safety: the 4 rows of 16 bytes from `src` and `dst` must be valid and not overlap
+pub(crate) unsafe fn transpose4x4_u32(src: *const u8, src_pitch: isize, dst: *mut u8, dst_pitch: isize) {
+    match simd_level() {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 => sse2::transpose4x4_u32(src, src_pitch, dst, dst_pitch),
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => neon::transpose4x4_u32(src, src_pitch, dst, dst_pitch),
+        _ => scalar::transpose4x4_u32(src, src_pitch, dst, dst_pitch),
+    }
+}
+
+// Fast non-cryptographic hash, for detecting modified rows.
+// 4 independent lanes for instruction level parallelism; a change within a single lane always changes
+// the hash, since every step is bijective.
+pub fn row_hash(data: &[u8]) -> u64 {
+    const K0: u64 = 0x9e37_79b9_7f4a_7c15;
+    const K1: u64 = 0xff51_afd7_ed55_8ccd;
+    let mix = |h: u64, w: u64| (h ^ w).wrapping_mul(K0).rotate_left(29);
+
+    let mut lanes = [0u64, 1, 2, 3];
+    let mut chunks = data.chunks_exact(32);
+    for chunk in &mut chunks {
+        for (i, lane) in lanes.iter_mut().enumerate() {
+            *lane = mix(*lane, u64::from_le_bytes(chunk[i * 8..i * 8 + 8].try_into().unwrap()));
+        }
+    }
+    let mut tail = [0u8; 32];
+    tail[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    for (i, lane) in lanes.iter_mut().enumerate() {
+        *lane = mix(*lane, u64::from_le_bytes(tail[i * 8..i * 8 + 8].try_into().unwrap()));
+    }
+
+    let h = lanes.iter().fold(data.len() as u64, |h, lane| mix(h, *lane));
+    (h ^ (h >> 33)).wrapping_mul(K1)
+}
+
+mod scalar {
+    pub fn bgra_to_gray(bgra: &[u8], gray: &mut [u8]) {
+        for (g, p) in gray.iter_mut().zip(bgra.chunks_exact(4)) {
+            *g = (0.3 * p[2] as f32 + 0.59 * p[1] as f32 + 0.11 * p[0] as f32).clamp(0.0, 255.0) as u8; // Luminosity Method
+        }
+    }
+
+    pub fn pack_above_thresholds(src: &[u8], thresholds: &[u8], dst: &mut [u8]) {
+        for ((byte, pixels), thresholds) in dst.iter_mut().zip(src.chunks(8)).zip(thresholds.chunks(8)) {
+            *byte = pixels
+                .iter()
+                .zip(thresholds)
+                .enumerate()
+                .map(|(i, (v, t))| ((*v > *t) as u8) << i)
+                .fold(0, |a, b| a | b);
+        }
+    }
+
+    pub unsafe fn transpose4x4_u32(src: *const u8, src_pitch: isize, dst: *mut u8, dst_pitch: isize) {
+        for y in 0..4 {
+            for x in 0..4 {
+                std::ptr::copy_nonoverlapping(src.offset(y * src_pitch + x * 4), dst.offset(x * dst_pitch + y * 4), 4);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    // luminosity of 4 BGRA pixels, as i32
+    #[target_feature(enable = "sse2")]
+    unsafe fn gray4(px: __m128i) -> __m128i {
+        let mask = _mm_set1_epi32(0xff);
+        let b = _mm_cvtepi32_ps(_mm_and_si128(px, mask));
+        let g = _mm_cvtepi32_ps(_mm_and_si128(_mm_srli_epi32::<8>(px), mask));
+        let r = _mm_cvtepi32_ps(_mm_and_si128(_mm_srli_epi32::<16>(px), mask));
+        // same order of operations as the scalar code, for the same rounding
+        let gray = _mm_add_ps(
+            _mm_add_ps(_mm_mul_ps(_mm_set1_ps(0.3), r), _mm_mul_ps(_mm_set1_ps(0.59), g)),
+            _mm_mul_ps(_mm_set1_ps(0.11), b),
+        );
+        _mm_cvttps_epi32(_mm_min_ps(gray, _mm_set1_ps(255.0)))
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn bgra_to_gray(bgra: &[u8], gray: &mut [u8]) -> usize {
+        let n = gray.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let src = bgra.as_ptr().add(i * 4) as *const __m128i;
+            let g0 = gray4(_mm_loadu_si128(src));
+            let g1 = gray4(_mm_loadu_si128(src.add(1)));
+            let g2 = gray4(_mm_loadu_si128(src.add(2)));
+            let g3 = gray4(_mm_loadu_si128(src.add(3)));
+            let packed = _mm_packus_epi16(_mm_packs_epi32(g0, g1), _mm_packs_epi32(g2, g3));
+            _mm_storeu_si128(gray.as_mut_ptr().add(i) as *mut __m128i, packed);
+        }
+        n
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn pack_above_thresholds(src: &[u8], thresholds: &[u8], dst: &mut [u8]) -> usize {
+        let n = src.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let v = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            let t = _mm_loadu_si128(thresholds.as_ptr().add(i) as *const __m128i);
+            // v <= t, there is no unsigned comparison in SSE2
+            let not_above = _mm_cmpeq_epi8(_mm_max_epu8(v, t), t);
+            let bits = !(_mm_movemask_epi8(not_above) as u16);
+            dst[i / 8..i / 8 + 2].copy_from_slice(&bits.to_le_bytes());
+        }
+        n
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn transpose4x4_u32(src: *const u8, src_pitch: isize, dst: *mut u8, dst_pitch: isize) {
+        let r0 = _mm_loadu_si128(src as *const __m128i);
+        let r1 = _mm_loadu_si128(src.offset(src_pitch) as *const __m128i);
+        let r2 = _mm_loadu_si128(src.offset(src_pitch * 2) as *const __m128i);
+        let r3 = _mm_loadu_si128(src.offset(src_pitch * 3) as *const __m128i);
+        let t0 = _mm_unpacklo_epi32(r0, r1); // a0 b0 a1 b1
+        let t1 = _mm_unpacklo_epi32(r2, r3); // c0 d0 c1 d1
+        let t2 = _mm_unpackhi_epi32(r0, r1); // a2 b2 a3 b3
+        let t3 = _mm_unpackhi_epi32(r2, r3); // c2 d2 c3 d3
+        _mm_storeu_si128(dst as *mut __m128i, _mm_unpacklo_epi64(t0, t1));
+        _mm_storeu_si128(dst.offset(dst_pitch) as *mut __m128i, _mm_unpackhi_epi64(t0, t1));
+        _mm_storeu_si128(dst.offset(dst_pitch * 2) as *mut __m128i, _mm_unpacklo_epi64(t2, t3));
+        _mm_storeu_si128(dst.offset(dst_pitch * 3) as *mut __m128i, _mm_unpackhi_epi64(t2, t3));
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    // luminosity of 4 BGRA pixels
+    #[target_feature(enable = "neon")]
+    unsafe fn gray4(px: uint32x4_t) -> uint32x4_t {
+        let mask = vdupq_n_u32(0xff);
+        let b = vcvtq_f32_u32(vandq_u32(px, mask));
+        let g = vcvtq_f32_u32(vandq_u32(vshrq_n_u32::<8>(px), mask));
+        let r = vcvtq_f32_u32(vandq_u32(vshrq_n_u32::<16>(px), mask));
+        // separate multiplications and additions (not fused), for the same rounding as the scalar code
+        let gray = vaddq_f32(
+            vaddq_f32(vmulq_f32(vdupq_n_f32(0.3), r), vmulq_f32(vdupq_n_f32(0.59), g)),
+            vmulq_f32(vdupq_n_f32(0.11), b),
+        );
+        vcvtq_u32_f32(vminq_f32(gray, vdupq_n_f32(255.0)))
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn bgra_to_gray(bgra: &[u8], gray: &mut [u8]) -> usize {
+        let n = gray.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let src = bgra.as_ptr().add(i * 4);
+            let g0 = gray4(vreinterpretq_u32_u8(vld1q_u8(src)));
+            let g1 = gray4(vreinterpretq_u32_u8(vld1q_u8(src.add(16))));
+            let g2 = gray4(vreinterpretq_u32_u8(vld1q_u8(src.add(32))));
+            let g3 = gray4(vreinterpretq_u32_u8(vld1q_u8(src.add(48))));
+            let lo = vcombine_u16(vmovn_u32(g0), vmovn_u32(g1));
+            let hi = vcombine_u16(vmovn_u32(g2), vmovn_u32(g3));
+            vst1q_u8(gray.as_mut_ptr().add(i), vcombine_u8(vmovn_u16(lo), vmovn_u16(hi)));
+        }
+        n
+    }
+
+    // one bit per byte of a comparison result (0x00 or 0xff), as _mm_movemask_epi8 of SSE2
+    #[target_feature(enable = "neon")]
+    unsafe fn movemask(mask: uint8x16_t) -> u16 {
+        const WEIGHTS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+        let bits = vandq_u8(mask, vld1q_u8(WEIGHTS.as_ptr()));
+        vaddv_u8(vget_low_u8(bits)) as u16 | (vaddv_u8(vget_high_u8(bits)) as u16) << 8
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn pack_above_thresholds(src: &[u8], thresholds: &[u8], dst: &mut [u8]) -> usize {
+        let n = src.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let bits = movemask(vcgtq_u8(vld1q_u8(src.as_ptr().add(i)), vld1q_u8(thresholds.as_ptr().add(i))));
+            dst[i / 8..i / 8 + 2].copy_from_slice(&bits.to_le_bytes());
+        }
+        n
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn transpose4x4_u32(src: *const u8, src_pitch: isize, dst: *mut u8, dst_pitch: isize) {
+        let r0 = vreinterpretq_u32_u8(vld1q_u8(src));
+        let r1 = vreinterpretq_u32_u8(vld1q_u8(src.offset(src_pitch)));
+        let r2 = vreinterpretq_u32_u8(vld1q_u8(src.offset(src_pitch * 2)));
+        let r3 = vreinterpretq_u32_u8(vld1q_u8(src.offset(src_pitch * 3)));
+        let t01 = vtrnq_u32(r0, r1); // (a0 b0 a2 b2), (a1 b1 a3 b3)
+        let t23 = vtrnq_u32(r2, r3); // (c0 d0 c2 d2), (c1 d1 c3 d3)
+        let c0 = vcombine_u32(vget_low_u32(t01.0), vget_low_u32(t23.0));
+        let c1 = vcombine_u32(vget_low_u32(t01.1), vget_low_u32(t23.1));
+        let c2 = vcombine_u32(vget_high_u32(t01.0), vget_high_u32(t23.0));
+        let c3 = vcombine_u32(vget_high_u32(t01.1), vget_high_u32(t23.1));
+        vst1q_u8(dst, vreinterpretq_u8_u32(c0));
+        vst1q_u8(dst.offset(dst_pitch), vreinterpretq_u8_u32(c1));
+        vst1q_u8(dst.offset(dst_pitch * 2), vreinterpretq_u8_u32(c2));
+        vst1q_u8(dst.offset(dst_pitch * 3), vreinterpretq_u8_u32(c3));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_bytes(n: usize, seed: u32) -> Vec<u8> {
+        let mut seed = seed;
+        (0..n)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_as_scalar() {
+        let level = simd_level();
+        for n in [0, 1, 15, 16, 33, 1600] {
+            let bgra = random_bytes(n * 4, n as u32);
+            let mut expected = vec![0; n];
+            let mut actual = vec![0; n];
+            bgra_to_gray_with(SimdLevel::Scalar, &bgra, &mut expected);
+            bgra_to_gray_with(level, &bgra, &mut actual);
+            assert_eq!(expected, actual, "bgra_to_gray {}", n);
+
+            let thresholds = &bgra[n * 2..n * 3];
+            let mut expected = vec![0; n.div_ceil(8)];
+            let mut actual = vec![0xaa; n.div_ceil(8)];
+            pack_above_thresholds_with(SimdLevel::Scalar, &bgra[..n], thresholds, &mut expected);
+            pack_above_thresholds_with(level, &bgra[..n], thresholds, &mut actual);
+            assert_eq!(expected, actual, "pack_above_thresholds {}", n);
+        }
+        // white and black, including the rounding at 255
+        let mut gray = [0; 2];
+        bgra_to_gray_with(level, &[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0xff], &mut gray);
+        assert_eq!(gray, [0xff, 0]);
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let src = [0x00, 0x80, 0x7f, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0x01];
+        let mut packed = [0; 2];
+        pack_above_thresholds(&src, &[0x7f; 10], &mut packed);
+        assert_eq!(packed, [0b1010, 0b01]);
+        let mut unpacked = [0; 10];
+        unpack_bits(&packed, &mut unpacked);
+        assert_eq!(unpacked, [0, 0xff, 0, 0xff, 0, 0, 0, 0, 0xff, 0]);
+    }
+
+    #[test]
+    fn test_transpose() {
+        let src: Vec<u8> = (0..64).collect();
+        let mut dst = [0u8; 64];
+        unsafe { transpose4x4_u32(src.as_ptr(), 16, dst.as_mut_ptr(), 16) };
+        let mut expected = [0u8; 64];
+        unsafe { scalar::transpose4x4_u32(src.as_ptr(), 16, expected.as_mut_ptr(), 16) };
+        assert_eq!(dst, expected);
+        assert_eq!(dst[4..8], [16, 17, 18, 19]);
+        // flipped vertically
+        unsafe { transpose4x4_u32(src.as_ptr().add(48), -16, dst.as_mut_ptr(), 16) };
+        assert_eq!(dst[0..8], [48, 49, 50, 51, 32, 33, 34, 35]);
+    }
+
+    #[test]
+    fn test_row_hash() {
+        let data = random_bytes(1000, 1);
+        for i in [0, 31, 32, 999] {
+            let mut modified = data.clone();
+            modified[i] ^= 1;
+            assert_ne!(row_hash(&data), row_hash(&modified));
+        }
+        assert_eq!(row_hash(&data), row_hash(&data.clone()));
+        assert_ne!(row_hash(&data[..999]), row_hash(&data));
+    }
+}
@@ -0,0 +1,251 @@
+use super::{rotate, simd, MonoImgprocOptions};
+use crate::image::*;
+
+// Keeps the output pixels of the previous frame while their input stays within a hysteresis band,
+// so that small changes (e.g. shifting gradients, or one changed pixel in an error diffused area)
+// do not toggle pixels all over the screen, which flickers on e-ink and makes rows dirty.
+// All images are in output (screen) coordinate.
+pub struct Stabilizer {
+    options: MonoImgprocOptions,
+    hysteresis: u8,
+    gray: Vec<u8>, // gray levels of the current frame
+    reference: Vec<u8>, // gray levels that the previous output pixels were made from
+    changed: Vec<bool>, // pixels to be taken from the new output
+    previous: Option<ImageBuffer>,
+}
+
+impl Stabilizer {
+    pub fn new(options: MonoImgprocOptions, hysteresis: u8) -> Self {
+        Stabilizer {
+            options,
+            hysteresis,
+            gray: Vec::new(),
+            reference: Vec::new(),
+            changed: Vec::new(),
+            previous: None,
+        }
+    }
+
+    pub fn gray(&self) -> &[u8] {
+        &self.gray
+    }
+
+    pub fn changed(&self) -> &[bool] {
+        &self.changed
+    }
+
+    // Find the pixels whose gray level moved out of the band, extended by `radius` pixels
+    // (e.g. the reach of error diffusion), or all pixels if there is no previous output.
+    pub fn update<InputT: ConstImage + ?Sized>(&mut self, input_img: &InputT, radius: usize) {
+        let size = self.options.output_size;
+        let (width, height) = (size.width as usize, size.height as usize);
+        let rotated = rotate::rotate(input_img, self.options.rotation, size);
+        self.gray.resize(width * height, 0);
+        for (y, gray_row) in self.gray.chunks_exact_mut(width).enumerate() {
+            let bgra_row = unsafe { std::slice::from_raw_parts(rotated.ptr(y as i32), width * 4) };
+            simd::bgra_to_gray(bgra_row, gray_row);
+        }
+        self.find_changed(radius);
+    }
+
+    // Like `update`, with the gray levels (in output coordinate) that the imgproc already computed
+    pub fn update_gray(&mut self, gray: &[u8], radius: usize) {
+        assert_eq!(gray.len(), (self.options.output_size.width * self.options.output_size.height) as usize);
+        self.gray.clear();
+        self.gray.extend_from_slice(gray);
+        self.find_changed(radius);
+    }
+
+    fn find_changed(&mut self, radius: usize) {
+        let width = self.options.output_size.width as usize;
+        self.changed.resize(self.gray.len(), true);
+        if self.previous.is_none() {
+            self.changed.fill(true);
+            return;
+        }
+        for ((changed, gray), reference) in self.changed.iter_mut().zip(&self.gray).zip(&self.reference) {
+            *changed = gray.abs_diff(*reference) > self.hysteresis;
+        }
+        dilate(&mut self.changed, width, radius);
+    }
+
+    // Restore the unchanged pixels of `output_img` from the previous output, and remember the result.
+    // Must follow `update` of the same frame.
+    pub fn apply<OutputT: Image + ?Sized>(&mut self, output_img: &mut OutputT) {
+        assert_eq!(output_img.size(), self.options.output_size);
+        let width = output_img.width() as usize;
+        match self.previous.as_ref() {
+            Some(previous) if previous.format() == output_img.format() => {
+                for y in 0..output_img.height() {
+                    let changed_row = &self.changed[y as usize * width..(y as usize + 1) * width];
+                    let row_len = (width * output_img.bpp() as usize).div_ceil(8);
+                    let src = unsafe { std::slice::from_raw_parts(previous.ptr(y), row_len) };
+                    let dst = unsafe { std::slice::from_raw_parts_mut(output_img.mut_ptr(y), row_len) };
+                    restore_row(output_img.format(), changed_row, src, dst);
+                }
+            }
+            _ => self.changed.fill(true),
+        }
+
+        self.reference.resize(self.gray.len(), 0);
+        for ((reference, gray), changed) in self.reference.iter_mut().zip(&self.gray).zip(&self.changed) {
+            if *changed {
+                *reference = *gray;
+            }
+        }
+        let previous = self.previous.get_or_insert_with(|| {
+            ImageBuffer::new(output_img.format(), output_img.width(), output_img.height(), None)
+        });
+        if previous.format() != output_img.format() {
+            *previous = ImageBuffer::new(output_img.format(), output_img.width(), output_img.height(), None);
+        }
+        previous.copy_from(output_img);
+    }
+}
+
+fn restore_row(format: ImageFormat, changed: &[bool], src: &[u8], dst: &mut [u8]) {
+    match format {
+        ImageFormat::Mono8Bpp => {
+            for ((dst, src), changed) in dst.iter_mut().zip(src).zip(changed) {
+                if !changed {
+                    *dst = *src;
+                }
+            }
+        }
+        ImageFormat::Mono1Bpp => {
+            // packed from the least significant bit
+            for (x, changed) in changed.iter().enumerate() {
+                if !changed {
+                    let mask = 1 << (x % 8);
+                    dst[x / 8] = (dst[x / 8] & !mask) | (src[x / 8] & mask);
+                }
+            }
+        }
+        _ => panic!("unsupported format {:?}", format),
+    }
+}
+
+// extend the true pixels by `radius` in both directions
+fn dilate(mask: &mut [bool], width: usize, radius: usize) {
+    if radius == 0 {
+        return;
+    }
+    let original = mask.to_vec();
+    let height = mask.len() / width;
+    for y in 0..height {
+        for x in 0..width {
+            if !original[y * width + x] {
+                continue;
+            }
+            for ny in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                mask[ny * width + x.saturating_sub(radius)..ny * width + (x + radius + 1).min(width)].fill(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imgproc::dithering::{self, DiffusionKernel, ErrorDiffusion};
+    use crate::imgproc::cpu::CpuMonoImgproc;
+    use crate::imgproc::{DitheringMethod, Rotation};
+
+    fn gradient(size: Size, offset: u8) -> ImageBuffer {
+        let mut img = ImageBuffer::new(ImageFormat::BGRA, size.width, size.height, None);
+        for y in 0..size.height {
+            let row = unsafe { std::slice::from_raw_parts_mut(img.mut_ptr(y), size.width as usize * 4) };
+            for (x, pixel) in row.chunks_mut(4).enumerate() {
+                let v = (x as u8).saturating_add(offset);
+                pixel.copy_from_slice(&[v, v, v, 0xff]);
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let size: Size = (200, 16).into();
+        let options = MonoImgprocOptions { input_size: size, output_size: size, rotation: Rotation::NoRotation };
+        let mut stabilizer = Stabilizer::new(options, 4);
+        let error_diffusion = ErrorDiffusion { kernel: DiffusionKernel::FloydSteinberg, serpentine: true };
+        let mut process = |input: &ImageBuffer| {
+            let mut output = ImageBuffer::new(ImageFormat::Mono8Bpp, size.width, size.height, None);
+            stabilizer.update(input, dithering::DIFFUSION_RADIUS);
+            dithering::diffuse(stabilizer.gray(), &mut output, dithering::GREY4_TARGET_COLOR_SPACE, error_diffusion,
+                               Some(stabilizer.changed()));
+            stabilizer.apply(&mut output);
+            output
+        };
+
+        let first = process(&gradient(size, 0));
+        // a slightly shifted gradient keeps the output
+        let second = process(&gradient(size, 2));
+        assert!(first.data() == second.data());
+
+        // one changed pixel only affects the pixels around it
+        let mut input = gradient(size, 2);
+        input.mut_data()[(8 * 200 + 100) * 4..(8 * 200 + 101) * 4].copy_from_slice(&[0xff; 4]);
+        let third = process(&input);
+        for y in 0..16usize {
+            for x in 0..200usize {
+                let i = y * 200 + x;
+                if y.abs_diff(8) > dithering::DIFFUSION_RADIUS || x.abs_diff(100) > dithering::DIFFUSION_RADIUS {
+                    assert_eq!(third.data()[i], second.data()[i], "{} {}", x, y);
+                }
+            }
+        }
+        assert_eq!(third.data()[8 * 200 + 100], 0xf0);
+
+        // the band is relative to the gray levels the output was made from, so slow drifts are followed
+        let fourth = process(&gradient(size, 7));
+        assert!(fourth.data() != second.data());
+    }
+
+    // the gray levels of the CPU mono imgproc can be used instead of computing them again
+    #[test]
+    fn test_update_gray() {
+        let size: Size = (20, 6).into();
+        let options = MonoImgprocOptions {
+            input_size: (6, 20).into(),
+            output_size: size,
+            rotation: Rotation::Rotate90,
+        };
+        let mut imgproc = CpuMonoImgproc::new(options);
+        let (mut from_input, mut from_gray) = (Stabilizer::new(options, 8), Stabilizer::new(options, 8));
+        let mut output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+        for offset in [100, 104, 120] {
+            let input = gradient((6, 20).into(), offset);
+            imgproc.process(&input, &mut output, DitheringMethod::Bayers4);
+            from_input.update(&input, 1);
+            from_gray.update_gray(imgproc.output_gray(), 1);
+            assert_eq!(from_gray.gray(), from_input.gray());
+            assert_eq!(from_gray.changed(), from_input.changed());
+            from_input.apply(&mut output);
+            from_gray.apply(&mut output);
+        }
+    }
+
+    #[test]
+    fn test_mono() {
+        let size: Size = (20, 2).into();
+        let options = MonoImgprocOptions { input_size: size, output_size: size, rotation: Rotation::NoRotation };
+        let mut stabilizer = Stabilizer::new(options, 8);
+        let mut output = ImageBuffer::new(ImageFormat::Mono1Bpp, size.width, size.height, None);
+        stabilizer.update(&gradient(size, 100), 0);
+        output.fill(0);
+        stabilizer.apply(&mut output);
+
+        // pixels 0..8 moved within the band, 8.. moved out of it
+        let mut input = gradient(size, 100);
+        for (i, v) in input.mut_data().iter_mut().enumerate() {
+            if i / 4 % 20 >= 8 {
+                *v = 0xff;
+            }
+        }
+        stabilizer.update(&input, 0);
+        output.fill(0xff);
+        stabilizer.apply(&mut output);
+        assert_eq!(output.data(), &[0x00, 0xff, 0xff, 0x00, 0xff, 0xff]);
+    }
+}
@@ -0,0 +1,92 @@
+// Stroke emboldening: dark strokes are dilated before binarization (a minimum filter of the gray levels),
+// so that thin fonts, 1px underlines and hairline borders survive thresholding and dithering.
+// The dilation is one-sided (towards right and bottom), each pixel of radius adds one pixel to the stroke width.
+// Must match `stroke_gray` in gpu.wgsl exactly.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StrokeDirection {
+    #[default]
+    None,
+    Horizontal, // thicker vertical strokes
+    Vertical,   // thicker horizontal strokes, e.g. underlines
+    Both,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Embolden {
+    pub direction: StrokeDirection,
+    pub radius: u8,
+}
+
+impl Embolden {
+    // (horizontal, vertical) radius
+    pub fn radius_xy(&self) -> (usize, usize) {
+        let radius = self.radius as usize;
+        match self.direction {
+            StrokeDirection::None => (0, 0),
+            StrokeDirection::Horizontal => (radius, 0),
+            StrokeDirection::Vertical => (0, radius),
+            StrokeDirection::Both => (radius, radius),
+        }
+    }
+}
+
+// `gray` is the whole output, `width` per row
+pub fn embolden(gray: &mut [u8], width: usize, embolden: Embolden, buf: &mut Vec<u8>) {
+    let (radius_x, radius_y) = embolden.radius_xy();
+    if radius_x > 0 {
+        for row in gray.chunks_exact_mut(width) {
+            buf.clear();
+            buf.extend_from_slice(row);
+            for (x, v) in row.iter_mut().enumerate() {
+                *v = *buf[x.saturating_sub(radius_x)..=x].iter().min().unwrap();
+            }
+        }
+    }
+    if radius_y > 0 {
+        buf.clear();
+        buf.extend_from_slice(gray);
+        for (i, v) in gray.iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+            *v = (y.saturating_sub(radius_y)..=y).map(|ny| buf[ny * width + x]).min().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embolden() {
+        // a 1px vertical line at x = 2, and a 1px horizontal line at y = 2
+        let (width, height) = (6, 5);
+        let mut gray = vec![255u8; width * height];
+        gray[2 * width..3 * width].fill(100);
+        for y in 0..height {
+            gray[y * width + 2] = 0;
+        }
+        let original = gray.clone();
+        let mut buf = Vec::new();
+
+        let mut horizontal = original.clone();
+        embolden(&mut horizontal, width, Embolden { direction: StrokeDirection::Horizontal, radius: 1 }, &mut buf);
+        assert_eq!(&horizontal[..width], &[255, 255, 0, 0, 255, 255]);
+        assert_eq!(&horizontal[2 * width..3 * width], &[100, 100, 0, 0, 100, 100]);
+        assert_eq!(&horizontal[3 * width..4 * width], &[255, 255, 0, 0, 255, 255]);
+
+        let mut vertical = original.clone();
+        embolden(&mut vertical, width, Embolden { direction: StrokeDirection::Vertical, radius: 2 }, &mut buf);
+        assert_eq!(&vertical[..width], &original[..width]);
+        assert!((2..5).all(|y| vertical[y * width..(y + 1) * width] == [100, 100, 0, 100, 100, 100]));
+
+        let mut both = original.clone();
+        embolden(&mut both, width, Embolden { direction: StrokeDirection::Both, radius: 1 }, &mut buf);
+        assert_eq!(&both[3 * width..4 * width], &[100, 100, 0, 0, 100, 100]);
+
+        let mut none = original.clone();
+        embolden(&mut none, width, Embolden { direction: StrokeDirection::None, radius: 3 }, &mut buf);
+        assert_eq!(none, original);
+    }
+}
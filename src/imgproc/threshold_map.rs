@@ -0,0 +1,365 @@
+mod blue_noise;
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use log::{info, warn};
+
+// Threshold map of ordered dithering, tiled over the image.
+// A pixel at (x, y) is black if its gray level <= threshold(x, y).
+// Thresholds are at most 254, so that white stays white.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThresholdMap {
+    pub width: usize,
+    pub height: usize,
+    pub thresholds: Vec<u8>, // row major
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+impl ThresholdMap {
+    pub fn flat(threshold: u8) -> Self {
+        ThresholdMap {
+            width: 1,
+            height: 1,
+            thresholds: vec![threshold],
+        }
+    }
+
+    // from ranks 0..n (n = number of entries or the maximum value + 1), scaled to 0..256 (at most 254)
+    fn from_ranks(width: usize, height: usize, ranks: &[u32], n: u32) -> Self {
+        assert_eq!(ranks.len(), width * height);
+        ThresholdMap {
+            width,
+            height,
+            thresholds: ranks.iter().map(|r| (*r as u64 * 256 / n as u64).min(254) as u8).collect(),
+        }
+    }
+
+    // bayer matrix of size x size, size must be a power of 2
+    pub fn bayer(size: usize) -> Self {
+        assert!(size.is_power_of_two());
+        // M(2n) = [[4M, 4M + 2], [4M + 3, 4M + 1]]
+        let mut ranks = vec![0u32];
+        let mut n = 1;
+        while n < size {
+            let mut next = vec![0u32; n * n * 4];
+            for y in 0..n {
+                for x in 0..n {
+                    let v = ranks[y * n + x] * 4;
+                    next[y * n * 2 + x] = v;
+                    next[y * n * 2 + x + n] = v + 2;
+                    next[(y + n) * n * 2 + x] = v + 3;
+                    next[(y + n) * n * 2 + x + n] = v + 1;
+                }
+            }
+            ranks = next;
+            n *= 2;
+        }
+        Self::from_ranks(size, size, &ranks, (size * size) as u32)
+    }
+
+    // 64x64 blue noise, precomputed by `void_and_cluster`
+    pub fn blue_noise() -> Arc<Self> {
+        static BLUE_NOISE: OnceLock<Arc<ThresholdMap>> = OnceLock::new();
+        BLUE_NOISE
+            .get_or_init(|| {
+                let ranks = blue_noise::BLUE_NOISE_RANKS.map(u32::from);
+                Arc::new(Self::from_ranks(BLUE_NOISE_SIZE, BLUE_NOISE_SIZE, &ranks, ranks.len() as u32))
+            })
+            .clone()
+    }
+
+    pub fn threshold(&self, x: usize, y: usize) -> u8 {
+        self.thresholds[(y % self.height) * self.width + (x % self.width)]
+    }
+
+    // thresholds of row `y` for pixels 0..width
+    pub fn tiled_row(&self, y: usize, width: usize) -> Vec<u8> {
+        let row = &self.thresholds[(y % self.height) * self.width..][..self.width];
+        row.iter().cycle().take(width).copied().collect()
+    }
+
+    // Load from a PGM file (P2 or P5) or a text file of whitespace or comma separated numbers, one row per line.
+    // Values are ranks: they are scaled from 0..=maxval (PGM), or 0..=the maximum value (text), to 0..255.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read(path).with_context(|| format!("Failed to read threshold map {:?}", path))?;
+        let map = if content.starts_with(b"P2") || content.starts_with(b"P5") {
+            parse_pgm(&content)
+        } else {
+            parse_text(&String::from_utf8_lossy(&content))
+        }
+        .with_context(|| format!("Invalid threshold map {:?}", path))?;
+        info!("Loaded {}x{} threshold map from {:?}", map.width, map.height, path);
+        Ok(map)
+    }
+}
+
+fn parse_pgm(content: &[u8]) -> anyhow::Result<ThresholdMap> {
+    // header: magic, width, height, maxval, separated by whitespaces and comments
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        match content.get(pos) {
+            None => anyhow::bail!("Truncated header"),
+            Some(b'#') => {
+                while pos < content.len() && content[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => pos += 1,
+            Some(_) => {
+                let start = pos;
+                while pos < content.len() && !content[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                fields.push(std::str::from_utf8(&content[start..pos])?.to_string());
+            }
+        }
+    }
+    let width: usize = fields[1].parse()?;
+    let height: usize = fields[2].parse()?;
+    let maxval: u32 = fields[3].parse()?;
+    anyhow::ensure!(width > 0 && height > 0 && maxval > 0 && maxval < 65536, "Invalid header");
+
+    let values: Vec<u32> = if fields[0] == "P2" {
+        std::str::from_utf8(&content[pos..])?
+            .split_ascii_whitespace()
+            .map(|x| x.parse::<u32>())
+            .collect::<Result<_, _>>()?
+    } else {
+        // a single whitespace after maxval
+        let data = content.get(pos + 1..).unwrap_or_default();
+        if maxval < 256 {
+            data.iter().map(|x| *x as u32).collect()
+        } else {
+            data.chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]]) as u32).collect()
+        }
+    };
+    anyhow::ensure!(values.len() >= width * height, "Expected {} values, got {}", width * height, values.len());
+    anyhow::ensure!(values.iter().all(|x| *x <= maxval), "Value greater than maxval");
+    Ok(ThresholdMap::from_ranks(width, height, &values[..width * height], maxval + 1))
+}
+
+fn parse_text(content: &str) -> anyhow::Result<ThresholdMap> {
+    let rows: Vec<Vec<u32>> = content
+        .lines()
+        .map(|line| line.split(|c: char| c.is_whitespace() || c == ',').filter(|x| !x.is_empty()).collect::<Vec<_>>())
+        .filter(|row| !row.is_empty())
+        .map(|row| row.iter().map(|x| x.parse::<u32>()).collect::<Result<_, _>>())
+        .collect::<Result<_, _>>()?;
+    anyhow::ensure!(!rows.is_empty(), "Empty threshold map");
+    let width = rows[0].len();
+    anyhow::ensure!(rows.iter().all(|x| x.len() == width), "Rows are of different lengths");
+    let values: Vec<u32> = rows.concat();
+    let max = *values.iter().max().unwrap();
+    Ok(ThresholdMap::from_ranks(width, rows.len(), &values, max + 1))
+}
+
+// Blue noise ranks of a size x size (tiled) area by the void-and-cluster method (Ulichney 1993):
+// start from a random pattern of few pixels, move the pixel of the tightest cluster to the largest void until stable,
+// then rank by removing the tightest cluster and by filling the largest void repeatedly.
+#[cfg(test)]
+fn void_and_cluster(size: usize) -> Vec<u32> {
+    const SIGMA: f32 = 1.5;
+    let n = size * size;
+    // gaussian of the (wrapped) distance
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let (dx, dy) = (i % size, i / size);
+            let (dx, dy) = (dx.min(size - dx), dy.min(size - dy));
+            (-((dx * dx + dy * dy) as f32) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+
+    struct Pattern<'a> {
+        size: usize,
+        kernel: &'a [f32],
+        ones: Vec<bool>,
+        energy: Vec<f32>,
+    }
+    impl Pattern<'_> {
+        fn set(&mut self, p: usize, one: bool) {
+            self.ones[p] = one;
+            let sign = if one { 1.0 } else { -1.0 };
+            let (px, py) = (p % self.size, p / self.size);
+            for (i, e) in self.energy.iter_mut().enumerate() {
+                let (dx, dy) = ((i % self.size + self.size - px) % self.size, (i / self.size + self.size - py) % self.size);
+                *e += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+        fn tightest_cluster(&self) -> usize {
+            (0..self.ones.len()).filter(|i| self.ones[*i]).max_by(|a, b| self.energy[*a].total_cmp(&self.energy[*b])).unwrap()
+        }
+        fn largest_void(&self) -> usize {
+            (0..self.ones.len()).filter(|i| !self.ones[*i]).min_by(|a, b| self.energy[*a].total_cmp(&self.energy[*b])).unwrap()
+        }
+    }
+
+    let mut pattern = Pattern {
+        size,
+        kernel: &kernel,
+        ones: vec![false; n],
+        energy: vec![0.0; n],
+    };
+    let n_initial = n / 10;
+    let mut seed = 1u32;
+    let mut placed = 0;
+    while placed < n_initial {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let p = (seed >> 8) as usize % n;
+        if !pattern.ones[p] {
+            pattern.set(p, true);
+            placed += 1;
+        }
+    }
+    for _ in 0..n {
+        let cluster = pattern.tightest_cluster();
+        pattern.set(cluster, false);
+        let void = pattern.largest_void();
+        pattern.set(void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0u32; n];
+    let mut removing = Pattern {
+        size,
+        kernel: &kernel,
+        ones: pattern.ones.clone(),
+        energy: pattern.energy.clone(),
+    };
+    for rank in (0..n_initial).rev() {
+        let cluster = removing.tightest_cluster();
+        removing.set(cluster, false);
+        ranks[cluster] = rank as u32;
+    }
+    for rank in n_initial..n {
+        let void = pattern.largest_void();
+        pattern.set(void, true);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+// Maps loaded from files are kept for the whole process and referred by index,
+// so that `DitheringMethod` (and the run mode) stays Copy. A file is reloaded when it's modified.
+struct LoadedMap {
+    path: PathBuf,
+    version: Option<(SystemTime, u64)>, // mtime and length of the loaded file
+    map: Arc<ThresholdMap>,
+}
+
+static LOADED_MAPS: Mutex<Vec<LoadedMap>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadedMapId(usize);
+
+fn file_version(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+pub fn load(path: &Path) -> anyhow::Result<LoadedMapId> {
+    let mut maps = LOADED_MAPS.lock().unwrap();
+    if let Some(i) = maps.iter().position(|x| x.path == path) {
+        return Ok(LoadedMapId(i));
+    }
+    let version = file_version(path);
+    let map = ThresholdMap::load(path)?;
+    maps.push(LoadedMap { path: path.to_path_buf(), version, map: Arc::new(map) });
+    Ok(LoadedMapId(maps.len() - 1))
+}
+
+// the path and the current map, reloaded if the file is modified since last load.
+// the previous map is kept if the file cannot be loaded (e.g. it's being written)
+pub fn loaded(id: LoadedMapId) -> (PathBuf, Arc<ThresholdMap>) {
+    let mut maps = LOADED_MAPS.lock().unwrap();
+    let loaded = &mut maps[id.0];
+    let version = file_version(&loaded.path);
+    if version.is_some() && version != loaded.version {
+        loaded.version = version;
+        match ThresholdMap::load(&loaded.path) {
+            Ok(map) => loaded.map = Arc::new(map),
+            Err(e) => warn!("{:#}, keeping the previous map", e),
+        }
+    }
+    (loaded.path.clone(), loaded.map.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bayer() {
+        assert_eq!(ThresholdMap::bayer(2).thresholds, vec![0, 128, 192, 64]);
+        let bayer4 = ThresholdMap::bayer(4);
+        assert_eq!(bayer4.thresholds, vec![0, 128, 32, 160, 192, 64, 224, 96, 48, 176, 16, 144, 240, 112, 208, 80]);
+        assert_eq!(bayer4.tiled_row(5, 6), vec![192, 64, 224, 96, 192, 64]);
+        let mut sorted = ThresholdMap::bayer(8).thresholds;
+        sorted.sort();
+        assert_eq!(sorted, (0..64).map(|x| x * 4).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_blue_noise() {
+        let map = ThresholdMap::blue_noise();
+        assert_eq!((map.width, map.height), (64, 64));
+        assert_eq!(*map.thresholds.iter().max().unwrap(), 254);
+        // 50% gray: half of the pixels are black, evenly spread without large clusters
+        for by in 0..8 {
+            for bx in 0..8 {
+                let black = (0..64).filter(|i| map.threshold(bx * 8 + i % 8, by * 8 + i / 8) >= 127).count();
+                assert!((24..=40).contains(&black), "{} {} {}", bx, by, black);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blue_noise_table() {
+        let ranks = void_and_cluster(BLUE_NOISE_SIZE);
+        assert!(ranks.iter().zip(blue_noise::BLUE_NOISE_RANKS).all(|(a, b)| *a == b as u32));
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("rabbitink-test-reload-map-{}.txt", std::process::id()));
+        std::fs::write(&path, "0 2\n3 1\n").unwrap();
+        let id = load(&path).unwrap();
+        assert_eq!(load(&path).unwrap(), id);
+        assert_eq!(*loaded(id).1, ThresholdMap::bayer(2));
+
+        std::fs::write(&path, "0 1\n").unwrap();
+        assert_eq!(loaded(id).1.thresholds, vec![0, 128]);
+        // invalid content keeps the previous map
+        std::fs::write(&path, "0 1\n2\n").unwrap();
+        assert_eq!(loaded(id).1.thresholds, vec![0, 128]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded(id).1.thresholds, vec![0, 128]);
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir();
+        let text_path = dir.join(format!("rabbitink-test-map-{}.txt", std::process::id()));
+        std::fs::write(&text_path, "0, 2\n3 1\n\n").unwrap();
+        let map = ThresholdMap::load(&text_path).unwrap();
+        assert_eq!(map, ThresholdMap::bayer(2));
+
+        let pgm_path = dir.join(format!("rabbitink-test-map-{}.pgm", std::process::id()));
+        std::fs::write(&pgm_path, b"P5\n# comment\n2 1\n255\n\x00\xff").unwrap();
+        let map = ThresholdMap::load(&pgm_path).unwrap();
+        assert_eq!(map.thresholds, vec![0, 254]);
+        std::fs::write(&pgm_path, "P2 2 1 3\n1 3\n").unwrap();
+        assert_eq!(ThresholdMap::load(&pgm_path).unwrap().thresholds, vec![64, 192]);
+
+        std::fs::write(&text_path, "0 1\n2\n").unwrap();
+        assert!(ThresholdMap::load(&text_path).is_err());
+        std::fs::remove_file(&text_path).unwrap();
+        std::fs::remove_file(&pgm_path).unwrap();
+    }
+}
@@ -0,0 +1,260 @@
+// Ranks (0..4096) of the 64x64 blue noise threshold map, row major.
+// Generated by `void_and_cluster(64)`, which is too slow to run on every start, see `test_blue_noise_table`.
+pub(super) const BLUE_NOISE_RANKS: [u16; 64 * 64] = [
+     450,  788, 1379, 3397,  605, 2558,   97, 1098, 3260, 1463,  612, 2077, 1200, 3215,  302, 4033,
+    1496, 3483,  457, 3961, 1901, 2607, 3804, 3470, 1666, 2373, 3695, 1803, 3302, 2040,  128, 2236,
+    1172, 1911,  497, 1409,  176, 3859, 1028, 2708, 3300,  791, 2971, 2044,  382, 2267, 3261, 1321,
+    3118,  827, 2570, 1267, 1773, 4038, 2772, 2068,  309, 1889, 2455, 3173, 1314, 3888,  896, 1495,
+    3606, 3123, 2372,  265, 2908, 1205, 1784, 3762, 2310, 1856, 3948,  187, 3602,  737, 2860, 1779,
+    2723,  931, 3005, 1611,  147,  999,  678, 2062,  469, 2936,  821, 2714,  392,  938, 3759, 2841,
+    3213,  813, 4048, 2761, 1780, 3401, 1514,  279, 4028, 1244, 2586, 3510,  944, 3779,  656,  284,
+    2759, 2055, 3878,  603, 3249,  926, 2462,  658, 3853, 1518,  881, 3614,  327, 2389, 3407, 2737,
+    1986, 1634, 1044, 3940, 1896, 3533, 3105,  761,  262, 2996,  920, 2619, 1570, 2387, 1289, 3408,
+     600, 2473, 1298, 3582, 2747, 3373, 3018, 1477, 4089, 1160, 2175, 1545, 3938, 2526, 1751, 1345,
+     326, 2514, 2067,  966, 3138,  621, 2105, 2943, 1892,  562, 1636,   67, 1931, 2659, 1164, 1812,
+    3629, 1434,  321, 3505, 1979,   31, 1329, 3474, 2979, 2613,  520, 2027, 1584, 3037, 1148,  144,
+     576, 2664, 3234,  741, 2235,  451, 1471, 2677, 3398, 1313, 2234, 3481, 3056,  397, 3904, 2056,
+     114, 3797, 2228,  348, 1983, 1194, 2316,   49, 2560, 3574,  239, 3378, 1216, 3140,  594, 3628,
+    3360, 1579,  131, 3536, 1261, 2343, 3761,  925, 3534, 2265, 3220, 3894, 1399, 2999, 4043, 2478,
+     952, 2977, 2357, 1086, 2622, 3126, 2271, 1731,  231, 1161, 3229, 3909, 2683,  617, 2183, 4081,
+    1265, 3751,   64, 1531, 2793, 3691,  946, 2066, 4090,  633, 1733,   81, 1092, 1835,  803, 3235,
+    1163, 3041, 1762,  871, 3992,  529, 3682, 1790,  921, 3100, 1943,  683, 2374,   27, 2141,  982,
+    2719, 2221, 3864, 2904,  445, 2647,    7, 1427, 2726,  312, 1079, 2396,  773,  426, 2172,   98,
+    3365,  627, 1755, 3939, 1513,  483, 3737,  986, 3974, 2201, 1418,   56,  996, 3354, 1710, 2950,
+    2541, 1806, 2305, 3387, 1185,  235, 2453, 1689,  377, 2517, 2874, 3850, 2153, 3673, 2706, 2341,
+    1633,  667, 3480, 2590, 3161, 1567, 2701, 3324,  593, 1435, 2837, 3826, 1662, 2998, 4015, 1807,
+     389, 1182,  682, 1484, 1969, 3985, 1711, 3329,  695, 3722, 1796, 3092, 3670, 1706, 3203, 1467,
+    2060, 3724,  237, 2852,  799, 3400, 2713, 2048,  692, 2840, 3542, 2413, 1934, 3785,  225,  853,
+    3323,  335,  933, 4003, 1916, 2991, 3807, 3273, 1218, 3597,  819, 1413, 3285,  507, 1372,  286,
+    4091, 2807,  165, 1352, 2122,  248, 1068, 2052, 3950, 2439,  350, 1055, 3523,  847, 1424, 2858,
+    3698, 3158, 2427, 3451,  876, 3178, 1114, 2491, 2091, 2870, 1334,  161, 2672, 1046, 3563,  746,
+    2602, 1209, 3163, 2239, 1923, 1276,  156, 1542, 3250,  434, 1677,  764, 3120, 1292, 2375, 3651,
+    2086, 1431, 3070, 2584,  407,  832, 1441,   22, 2198, 3147, 1956,  269, 2431, 1008, 3112, 3544,
+    2092, 1087, 2436, 3763,  800, 3886, 3027,  418, 1264, 3180, 1858, 2724, 2223,  200, 2532,  616,
+    2049,   77, 1661, 2743,  203, 2272,  508, 3844,  355,  898, 4014, 2001,  551, 2273, 2828,  304,
+    3995, 1685,  873, 3571,  429, 4072, 3065, 2382, 3658, 1240, 2544, 4032,  323, 2876, 1590,  542,
+    2669, 3732,  645, 2173, 1624, 3476, 2325, 2885, 1026,  512, 2655, 4009, 1709, 2849, 1894,  744,
+    1560, 3289,  540, 1839, 2893, 2378, 1640, 3545, 2246,   82, 3766,  655, 1593, 3301, 3812, 1220,
+    3485,  939, 4075, 1256, 3727, 1802, 2917, 1301, 1679, 3127, 2324, 3423, 1557, 3834, 1362, 1954,
+    3011, 2386,   73, 2742, 1470, 2555,  615,  964, 1975,   96, 3295, 1855, 1124, 2218, 3233,  981,
+    1767,  219, 3294, 1090, 3791, 2732,  584, 3908, 1851, 3666, 1480,  890, 3443,   47, 3706, 2614,
+     218, 2932, 1396, 3393,    8, 1251,  556, 2654,  849, 1533, 3377, 1151, 3010, 1912,  430, 2366,
+    2989, 1840, 2243,  569, 3099,  836, 3581, 2657, 3457,  210, 1174,  716, 2938,   23,  935, 3411,
+     522, 1126, 3877, 3207, 1022, 2127, 3765, 1655, 2859, 3884,  839, 2777,  558, 3506,   16, 3947,
+    2850, 2042, 1381, 2929,  129, 1977, 1239, 1600, 3319,  151, 2935, 2116,  579, 1339, 2215, 1058,
+    3933, 2295, 3604,  943, 2169, 3708, 3227, 1952, 4065, 2901, 2349,  226, 3960,  833, 2798, 1561,
+     713, 3322,  291, 2513, 1491, 2082,  270,  660, 1993, 2542, 3801, 1817, 2438, 3271, 1715, 2293,
+    3734, 1489, 2065,  685, 1764, 3475,  319, 3214,  515, 2304, 1280, 2133, 3798, 1707, 2454, 1181,
+    3446,  733, 4047, 2336,  878, 3379,  360, 2598,  781, 2380, 1149, 3269, 2494, 3890, 3046, 1663,
+     654, 1905,  415, 2577, 1668, 2802, 1084,  175, 1336,  587, 1838, 2639, 1373, 2270, 3572,   95,
+    3937, 1279, 2815, 3818, 3238, 1102, 2411, 4035, 1437, 1002, 3044,  417, 1290, 3998,  599, 2682,
+     206, 2913, 3509, 2597,  168, 2902, 1333, 2470, 1515, 3530,  198, 3013, 1452,  786, 3110,  410,
+    1591, 2566,  303, 1529, 3612, 3001, 2152, 4007, 3094, 1935, 3806,  295, 1785,  910,  345, 3436,
+    2824, 1238, 3074, 4029,  274,  730, 3825, 2531, 3160, 3527,  983, 3730,  329, 3276, 1088, 2081,
+    2557, 1693,  984, 1873,  116, 3632, 2875, 1748, 3274,  153, 3644, 2130, 2778, 1042, 2030, 3167,
+     883, 1881,  431, 1303, 3811, 2249,  809, 4011, 1016, 1893, 2580, 3350,  324, 1989, 3750, 2260,
+    3058, 3577, 1115, 2765, 1809,  608, 1423, 1027,  207, 1543,  649, 2694, 3631, 1493, 2569, 2154,
+     100, 3713,  782, 1475, 2124, 3462, 1816, 2257, 1565,  366, 2191, 3072, 1553, 1887,  550, 3085,
+    3675,  363, 3422,  642, 2166, 1382,  792,  466, 2278, 2738,  675, 1511, 3441,  124, 3634, 1571,
+    1213, 4058, 2410, 3267, 1041, 1834, 3082,   30, 2805, 3662,  732, 1132, 4076, 2736, 1317,  927,
+    1900,  535, 2136, 3824,   66, 2481, 3764, 2729, 3551, 2300, 3174, 1207, 2087, 3299,  734, 3999,
+    1588, 1982, 2405, 3356, 2909, 1253,  468,  886, 2855, 3932, 1262,  738, 2727, 4046, 2408, 1365,
+     816, 1926, 2966, 2471, 3988, 2652, 3139, 3546, 1271, 3868, 1867, 2486,  854, 2937, 2315,  400,
+    3364, 2083,  679, 1626, 2698,  539, 3472, 2147, 1608,  459, 2190, 1740, 2388,  614, 3532,  167,
+    3990, 1469, 3281,  891, 3103, 1230, 1999,  449, 1753,  852, 3980,   63, 2877,  408, 1361, 2725,
+     942, 3204,  480, 1059,   53, 2618, 3664, 3246,  135, 1913, 2573, 3384,   11,  958, 3500,  229,
+    2771, 3866, 1176, 1555,  341, 1015, 1671,   15, 2097,  947, 3315,  276, 3957, 1341, 1800, 3820,
+    2505,   52, 3030, 3615,  256, 3951, 1383,  762, 3247, 3869, 2656, 3157,   83, 1573, 2973, 2224,
+    1192, 2839,  332, 2294, 1635, 3467,  804, 2981, 3399, 1368, 2480, 1868, 1010, 3856, 2385, 3528,
+     224, 2842, 3590, 1758, 3858, 1960, 1454, 2313, 1065, 3552,  549, 2160, 1681, 3004, 2005, 1494,
+    3312, 2244,   86, 3616, 3280, 2039, 3767, 2419, 3057,  548, 1660, 2881, 2111,  503, 3096,  729,
+    1081, 2762, 1354,  834, 2322, 1918, 2915, 2535, 1188,  246,  868, 1309, 3800, 3328,  789, 2633,
+    3741,  710, 1825, 3895, 2699,  247, 4077, 2225,  137, 2783,  586, 3498, 3125, 1646,  565, 1886,
+    1159, 2194, 1353, 2452,  768, 3051,  533, 4052, 1742, 2945, 1442, 3903, 1135, 3754,  671, 2642,
+     475, 1724,  916, 2370,  698, 2879,  446, 1405, 4054, 2606, 1231, 3712,  988, 2700, 3541, 1616,
+    3298, 1942, 3900, 1744, 3185, 1035,  328, 3613, 2047, 1699, 3429, 2848, 2085, 1057, 1757,  223,
+    3122, 2426, 3524, 1107,  636, 1466, 2497, 1069, 1658, 3831, 2113, 1255,  234, 2277, 3386, 2921,
+    3755,  672, 4020,  316, 3455, 1157, 2562,  278, 3200,  817,  191, 2443, 2834,  294, 2321, 3565,
+    1064, 4002, 3191, 2691, 1891, 1212, 3463,  846, 1848,  213, 3452, 2279,   79, 1864, 1193,  253,
+    2262,  494, 3448,  142, 2665, 3815, 1598,  606, 3017, 3926, 2344,  629,  292, 2525, 3493, 2034,
+     970, 1540,   25, 2158, 3211, 2865, 1910, 3351,  493, 3090,  828, 3637, 2681, 1410,  879,    9,
+    1612, 2637, 1922, 2903, 1654, 2180, 3728, 1389, 2036, 2716, 3661, 1847,  885, 3176, 1287, 1879,
+    2986, 2121,  315, 1390, 3780,  178, 2472, 3183, 2204, 2987,  670, 1548, 3237, 4026, 2561, 3647,
+    2930,  949, 2464, 1243,  662, 2096, 3344, 2390,  965,   48, 1448, 1870, 4039, 2990, 1394,  470,
+    3910, 2889, 3372, 1340, 3784,  300,  856, 3671, 1324, 1988, 2394,  401, 2995, 1946, 3970, 2457,
+    3145,  433,  994, 3309,  111,  872, 2822,  657, 3339, 1077, 2269,  391, 3405, 1602, 3944,   60,
+     767, 1523, 3499,  630, 3073, 1645, 3930,  554, 1583, 1096, 3891, 2751,  860,  424, 2119,  699,
+    1436, 3986, 1664, 2864, 3643, 1439,  196, 2796, 1286, 3703, 2615, 3292, 1166,  758, 3636, 2200,
+    2605,  795, 1959,  580, 2383, 1768, 3012, 2572,  157, 4016, 1043, 1732, 3775,  648, 1162, 3501,
+    1440, 2110, 3630, 1302, 2369, 3556, 1861, 3837,   38, 1644, 3994, 1323, 2475,  582, 2758, 2230,
+    3738, 2502, 2821, 1105, 2337, 2058,  959, 2785, 3689,  331, 2445, 1947, 1346, 3050, 1702, 3283,
+       6, 2074, 3168,  395, 2241,  902, 4066, 1880, 3144, 2155,  798,  409, 2819, 2400,   90, 1684,
+    1234,  283, 3709, 2767, 1094, 3984,  547, 2151, 1508, 2833, 3305, 2554,  103, 3259, 2197,  272,
+     818, 3922, 2730,  628, 3014, 1524,  437, 2539, 2157, 3047,  700, 2890, 3576, 2054, 1013, 3111,
+    1249,  258, 1859, 4078,   99, 3600,  412, 1342, 3254, 1763, 3410,  158, 3773, 2291, 1104, 3829,
+    2424,  779, 1125, 3418, 1722, 2983, 2547,  702,  266, 3507, 1728, 3861, 1476, 1951, 3038, 3326,
+    4019, 2330, 1458, 3248,  139, 1615, 3149,  907, 3471,  462,  763, 1578, 1236, 2710, 1761, 3025,
+    2417, 1676,  227, 1804, 3883, 1129, 3228,  797, 1414, 3520,  273, 1783, 1154,  338, 3862,  492,
+    1647, 3352,  844, 3008, 1534, 3293, 2649, 1968, 2329,  772, 1232, 2861,  647, 3465,  271, 2818,
+    1554, 3693, 2624,  179, 3881,  504, 1158, 3335, 1558, 1012, 2911, 2308,  240, 3683, 1040,  536,
+    2037, 2957,  951, 1907, 3593, 2686, 1199, 3743, 2425, 1973, 3912, 2258, 3610,  906, 4082,  570,
+    3438, 1005, 3245, 2210, 2623,  132, 2041, 4063, 2680, 1039, 2376, 3808, 2608, 3257, 1826, 2402,
+    3621, 2678, 2163,  357, 2437,  750, 1146, 3838,   28, 3043, 4060, 2149, 1474, 2582,  937, 1950,
+     545, 2962, 1296, 1903, 2379, 1421, 3769, 2070, 2466, 3993,  543, 1260, 3409,  747, 2548, 1604,
+     120, 3508,  454, 2506,  681, 2114,  384, 1788,    0, 1315, 3101,  245, 2958, 1996,  307, 1501,
+    2790, 1283, 3774,  530,  903, 3569, 2952, 1696,  186, 3266, 2010,  754, 1500,  148, 2924, 1387,
+     676, 1067, 3885, 1297, 3444, 1842, 3098,  538, 1675, 2564,  977,  447, 3646, 1769, 3225, 4013,
+    2254,  317, 3477,  861, 3268, 2731,   39, 3049,  386, 1830, 2609, 3114, 1775, 2205, 3795, 3242,
+     857, 2693, 1718, 3971, 1371, 3313, 3842, 2922, 2599, 3402, 1047, 1713,  618, 3349, 2578, 3799,
+    2245,   35, 1957, 2897, 1614, 2391, 1208,  644, 3699, 1337,  488, 2863, 3543,  893, 4045, 2109,
+    3130,   36, 1941, 2786,  439, 3989, 2211, 2809, 3726, 1400, 3258, 1985, 2810,   72, 1291,  727,
+    3066, 1456, 3918, 2103,  637, 1678,  995, 3624, 1355,  859, 3707,  102, 1118, 2836,  365, 1386,
+    2276, 3676, 1137, 3064,   94, 2368, 1023,  717, 1532,  482, 3704, 2792, 2377, 1375,  980, 1860,
+     631, 3562, 3162, 1344, 3945,  362, 3433, 1862, 2447, 3045, 3969, 1648, 2186, 2530, 1190,  455,
+    3494, 2340, 3760, 1652,  932, 1490,  221, 1099,  711, 2397,  204, 3841,  851, 2287, 3450, 2529,
+    1841,  987, 2589,  138, 2886, 3458, 2290, 1945, 2843, 3388, 2084, 1460, 3901,  624, 2000, 3053,
+    1505,  261, 1953,  787, 2775, 1627, 2013, 3137, 4057, 2231, 1897,  855, 3966,  113, 3521, 3075,
+    1203, 2404,  835,  254, 2720, 2072, 1000, 2813,  333, 2125, 1093,   46, 3327,  375, 1837, 2704,
+    1550, 1144,  575, 3026, 3578, 2527, 3177, 3435, 2032, 3587, 1717, 1229, 3117, 1594,  505, 3740,
+     242, 3359, 1606, 3723, 1250,  419, 3927,  715,  193, 2448,  473, 3244, 2296, 3442, 1054, 4067,
+     663, 3202, 2488, 3404, 3796,  467, 3522,  182, 2500, 1223,  356, 3036, 1576, 2095, 2768,  322,
+    1642, 4010, 2601, 1836, 3710,  707, 3121, 3905, 1516,  765, 3642, 2721, 1285, 3871, 3068, 3665,
+     774, 3325, 2449,  171, 2138,  664, 1774, 1360, 2728,  436, 2939,  650, 2628, 3953, 1083, 2016,
+    2814,  646, 2192, 3083, 1890, 2508, 1535, 3039, 1288, 4044, 1682,  805, 2754, 1810,   12, 2576,
+    1688, 3833,  372, 1349, 2181, 1150, 2707, 1457,  880, 3627, 2630, 3419,  564, 3684,  823, 2311,
+    3253,  471, 1034, 3468, 1504, 2299, 1304,  107, 2565, 3169, 1745, 2274,  659, 2023,  989,  134,
+    1734, 2867, 1920, 4062, 1247, 2832, 3827,   62,  928, 4037, 2168, 3392, 1895,  313, 2446, 3256,
+    1358, 4064, 1142,  280,  829, 3314, 1021, 3645, 1898, 2653, 1136, 3059,  359, 1338, 3579, 2960,
+    1191, 2342,  889, 1813, 3019,  677, 3931, 1786, 3240, 2069,   44, 1348, 2399, 1110, 1885, 3810,
+    1397, 2050, 2926,  152, 3052,  452, 3346, 1821, 3777, 1007,  441, 2845, 3265, 1509, 2600, 2259,
+    3899,  367, 1406,  841, 3236,  405, 1980, 3489, 2309, 1597, 1091,  133, 1378, 3557,  802, 1656,
+       3, 2545, 3496, 2362, 3803, 2744,   59, 2266,  393,  651, 3690, 2026, 3887, 2444,  869, 2117,
+     163, 3466, 2884, 3733,   70, 3297, 2371,  406, 2912,  752, 3902, 1820, 3230, 2898,  141, 2702,
+     668, 3585, 2354, 1726, 1145, 4083, 2510,  596, 2203, 3514, 1417, 4017,  180, 3490,  518, 3007,
+    1072, 3414, 2685, 3623, 1670, 2553, 1121, 2928,  598, 2695, 3190, 3781, 2522, 2944, 2176, 3809,
+    3104,  901, 1843, 1486,  597, 2020, 1637, 3454, 2804, 3232, 2339,  174, 1559, 3296,  456, 3963,
+    2640,  632, 2008, 1438, 2568,  968, 2028, 1293, 3776, 1592, 2794,  945,  440, 4053, 1695, 3412,
+    1219,  285, 3897,  742, 2658, 1932,  892, 1536, 2961,  241, 1966, 2461,  930, 1794, 3747, 1350,
+    2011,  626, 2346,  130, 2118,  735, 3317, 1462, 3921,  349, 2012,  724, 1719,  383, 1178,  574,
+    2063, 2784,  443, 3282, 2914, 1133, 4006,  810, 1320, 1714,  904, 3518, 1097, 1915, 3033, 1464,
+    1737, 3290, 1120,  399, 4084, 1697, 3430,  197, 2490,  513, 2283, 3548, 2139, 1385,  793, 2463,
+    1924, 2835, 1510, 3182, 3558,   17, 3717, 2610, 1183, 3366,  785, 3091, 1235, 2212, 2703,   24,
+    3262, 1694, 3925, 1197, 3081, 3846,  209, 1906, 1011, 2435, 3570, 1312, 3116, 4030, 2398, 3445,
+    1528, 3620, 1222, 3860,  140, 2487, 3077,  347, 2165, 3875, 2900,  527, 2516, 2795,  749, 3652,
+     290, 2441, 3611, 2156, 2756,  708, 2941, 3667, 1024, 3198, 1252,  296, 3086, 2627, 3650,  496,
+    3226,  976, 2112,  477, 1257, 2232, 3106,  420, 3946, 1667, 2661, 3849,  402, 3189,  725, 4042,
+    2509,  918, 2923,  510, 1546, 2662, 2314, 3605, 2994, 1672,   37, 2749,  948, 1936,  121, 2675,
+     866,  243, 2423, 1908,  743, 2106, 1538, 3716, 2617,  126, 1211, 2059, 4061,   91, 1284, 2268,
+    1030, 3089,  811, 1569, 3231,  281, 1404, 2134, 1760, 2638, 3983, 1972, 1621,   80, 1089, 2222,
+    3972,  122, 3502, 2469, 2883, 1623, 1029, 1909,  731, 2178,   85, 1407, 1995, 3566, 1563, 1140,
+    2104,  293, 3608, 1882, 3461,  924, 1327,  438,  790, 3440, 2135, 3893,  567, 3537, 1357, 3306,
+    1716, 3991, 3080, 1429, 3592, 3223, 1060,  610, 1752, 3539, 3124, 1472, 3368, 1766, 3156, 3852,
+    2829, 1690,   13, 3748, 1177, 2479, 3855,  808,   54, 3396,  568,  909, 3786, 3277, 2764, 1789,
+    1402, 2978, 1723,  780, 3997,  208, 3341, 3672, 2450, 3032, 3603,  639, 2830, 2401,  250, 2882,
+    3332, 1447, 2711, 2233,   57, 3172, 2076, 4050, 2770, 1485, 1061, 2523, 1631, 2967, 2217,  680,
+    2927, 2038,  478,  941, 2644,  201, 2806, 3318, 2381,  993,  665, 2421,  390,  888, 2128,  506,
+    1981, 3949, 2651, 2199,  546, 1930, 3288, 2823, 2253, 1506, 2968, 2420, 1331, 2031,  684, 3753,
+     404, 2365, 1153, 3656, 2002,  652, 2705, 1204,  339, 1502, 1063, 3287, 1777,  877, 3821, 1852,
+     521, 3752,  753, 1173, 3865,  602, 2593, 1771,  172, 3197,  499, 3340,  220, 1156, 3836,  306,
+    2551, 1141, 3729, 2338, 1701, 4073, 1254, 1984,  288, 3955, 1928, 2974, 3745, 2692, 3554, 1443,
+    3382,  689, 1319, 3437, 2934,  978, 1619,  476, 3936, 1050, 3531,  222, 2847,  432, 3479, 2512,
+     914, 3357,  230, 2587, 1370, 3055, 2206, 1793, 4036, 2739, 2014, 3898,  155, 1332, 3453, 2604,
+    1071, 3087, 2358, 1759, 2916, 1526, 3618, 1127, 2361, 3771, 1808, 2275, 3625, 2717, 1974,  962,
+    3447, 1487,   29, 3385,  673, 2126,  472, 3484, 1507, 2745, 1138,   42, 1669, 1215, 2351,  162,
+    1106, 2485,  310, 1756, 4040,  146, 3511, 1322, 2559, 1866,  674, 2164, 4092, 1705, 1155, 3128,
+    1547, 3823, 1863, 3255,  463, 3564,  929,   45, 3201,  830,  474, 2575, 2208, 2972,  379, 2145,
+    1657,  104, 3965,  371, 3421,  905,  263, 3108,  701, 1274, 2953,  820, 1428,  601, 3205, 1673,
+    3934, 2187, 3107, 2774, 1374, 3758, 3024, 2482,  812, 3790, 2214, 3395,  541, 4023,  771, 3115,
+    3830, 2078, 3195,  882, 2331, 2666, 2009, 3109,  289, 3649, 3218, 1522,  954, 2976, 2261,   18,
+    2079,  590, 2894,  972, 1680, 2415, 3793, 1450, 2353, 3515, 1686, 3133, 1009, 1581, 4079,  796,
+    3212, 2538, 1369, 2043, 2750, 2430, 1815, 2143, 3962, 2688,  136, 2089, 4085, 2432,   89, 2871,
+     739,  428, 1819,  843, 2440,  166, 1095, 1720, 3179,  257, 1420, 3084, 2540, 2019, 2880, 1562,
+     486, 2712, 3688, 1519,  464, 1171, 3828,  622, 2288, 1241, 2757,  119, 2537, 3680,  721, 3935,
+    2755, 1189, 2303, 4059,  159, 2801,  609, 1925, 2899,  346, 1299, 3668,  607, 3342, 2741, 1210,
+    3677,  552, 3495, 1045,  640, 3832, 1356, 3376,  361, 1580, 3547, 3136, 1020, 1833, 3756, 1179,
+    2333, 3307, 1263, 3979, 1937, 3549, 2838,  519, 2021, 3573,  696, 1778,  992, 3449,  238, 1874,
+     961, 1300,  118, 2988, 3420, 1822, 2887,  971, 1712, 3914,  826, 1849, 3338,  413, 1432, 1818,
+    3278,  264, 3538, 1415, 2064, 3188, 1225, 3389, 1003, 3819, 1899, 2263,   61, 2407, 1971,  287,
+    1770, 2188, 2954, 1638, 3222,   69, 2826,  842, 2546, 1927, 1147,  560, 2636,  318, 3380, 1564,
+    2690, 3669,  127, 2970,  566, 1525, 2226, 4031, 1311, 2332, 2696, 3768,  358, 1388, 2468, 3560,
+    2237, 4012, 1940, 2459,  712, 3663,   32, 2528, 3425,  259, 3003, 2317, 1259, 2090, 3488, 2611,
+     840, 1639, 3040,  694, 2621,  396, 3920, 2238,  145, 2549,  687, 2956, 3923, 1430,  755, 3867,
+    2803,  900,  205, 4027, 2284, 1902, 1123, 3735,  516, 3186, 2301, 3687, 1451, 3009, 2142,  524,
+     913, 1727, 2102, 2501, 1004, 3142,  297,  863, 3270,  105, 1082, 3029, 2170, 3954,  822, 3153,
+    2896,  619, 3345, 1076, 1605, 2242, 1277, 3150, 2061, 1530, 3588,  555, 4005, 2799, 1073,  351,
+    3788, 2484, 1970, 1100, 3674, 1596,  784, 1743, 2781, 1248, 3308, 1625, 1038, 3478, 2520, 3175,
+    1272, 3559, 2581, 1425,  442, 3567, 3048, 2213, 1478, 3924,   10, 1750,  783, 3987, 1122, 2827,
+    3889,  388, 3194, 1426, 3873, 2625, 3517, 1687, 2808, 3882, 1871,  572, 1527, 2769,    4, 1674,
+     353, 1479, 2667,  305, 3843, 2816,  444, 4093,  691, 1108, 2612, 1729,  897,   71, 3141, 2281,
+    1318,  523, 3917,   74, 3286, 2359, 2947, 3591,  490, 4055, 2088,  244, 2697,  501, 1704,  170,
+    2046,  634, 3129, 1032, 2465,  714, 1692,  217, 2645,  950, 2856, 3456, 2556, 1964,  110, 3432,
+    2251, 1206, 3492,  214,  703, 1963, 1201, 2395,  532, 1363, 2496, 3251, 3655, 1170, 2024, 3742,
+     998, 2167, 3619, 1869, 3239,  922, 1929, 1465, 2467, 3310,  340, 2906, 3694, 1992, 1517, 3638,
+    1857, 3343, 2779, 2161, 1347,  299, 1048, 2018, 1455, 2997,  884, 3580, 2207, 3020, 1113, 3711,
+    2360, 1549, 3792, 1792, 3403, 2800, 3982, 1278, 3595, 2094,  620, 1294,  370, 3217, 1628, 2504,
+     751, 1829, 2878, 2345, 1641, 3702,   26, 3079, 2131, 3428,  251,  911, 2282,  448, 3374, 2503,
+    3913, 2982,  686, 1269,   88, 2571, 3076, 3622,  160, 2144, 3919, 1376, 2355, 3361,  726, 2674,
+     211, 1169,  669, 1629, 3015, 4000, 2629, 3369,   87, 2422, 1791,  591, 1367, 3977, 1921, 3363,
+     465, 2787,   75, 2179,  344,  912, 2053,  526, 3210, 1797, 3067, 2409, 3720,  985, 2892, 1343,
+    3648,  485, 4068,  957, 2760, 3275, 1066, 4004,  825, 1781, 3822, 2920, 1585, 3078,  759, 1380,
+     143, 1618, 3334, 2334, 3973, 1609,  581, 1152, 2872,  850, 1888,  531, 1139,  301, 3023,  991,
+    4069, 3165, 2524, 3749,  919,  502, 1824,  728, 3731, 1186, 3880, 3155, 2752,    2,  760, 2498,
+     997, 4080, 1328, 3097, 3757, 1586, 3303, 2543, 1109,  173, 4070, 1556, 2182,  561, 3942,  194,
+    2033, 3132, 1468,  268, 2071,  544, 2511, 1539,  342, 2671, 1224, 2045,  112, 4094, 1850, 2748,
+    3525, 2025,  416, 2797,  801, 2107, 3459, 2297, 3813, 1498, 3148, 3497, 2596, 3879, 1938, 2392,
+    1572, 2098,  352, 1884, 3320, 2326, 1411, 3088, 2123, 2603,  376, 1001, 2307, 1665, 3263, 1446,
+    2940, 1823,  709, 2595, 1080, 2393,   93, 3814, 1488, 2817,  894,  311, 3504, 1237, 1754, 3337,
+    2663, 1070, 2428, 3789, 3034, 1295, 3599, 2256, 2949, 3697,  553, 2518, 3439, 1006, 2347,  557,
+    1198, 2534,  990, 3701, 1384, 3143,  184, 1721,  495, 2746,   19, 2174, 1582,  635, 1316,  115,
+    3390,  719, 2942, 1242,    1, 2812, 3516,  975,  236, 1630, 3473, 1978, 3660,  325, 3851, 2150,
+     185, 3594, 2099, 3431,  489, 1933, 2931,  777, 2220, 3686, 2483, 1987, 3135, 2722, 2364,  875,
+     414, 3586, 1904,  756, 1736, 3333,  195, 1877,  967, 3241, 1683,  837, 2869, 1408, 3640, 3113,
+     723, 3896, 2963, 1811,  381, 2594, 1078, 3654, 2476, 1221, 4021,  899, 2857, 3607, 3206, 2632,
+    3847, 1444, 3596, 2202, 3943, 1599,  422, 3872, 2734, 3216, 1377,  778, 2905, 1281, 2646,  870,
+    3134, 1245,  374, 2773, 1483, 3976, 1195, 3417, 1831,  411, 3331, 1103,  693,   34, 3705, 2100,
+    1607, 3071,   76, 2782, 1116, 2563,  666, 3975, 1392,   55, 2146, 3857,  403, 1955,  188, 1691,
+    2148, 1325,  108, 2292, 3358, 4051, 1919, 3002,  736, 2093, 3219, 1846,  275, 2248, 1031,  514,
+    1994,  953, 2687,  559, 1018, 2591, 2017, 1228, 2285,  623, 4049, 2492,  453, 3413, 1878,  517,
+    3744, 2521, 1725, 3692,  858, 3146,  252, 2620,  641, 1359, 2955, 1730, 3874, 1481, 2918,  571,
+    4025, 1273, 2177, 3892,  435, 3635, 2132, 3054, 2429, 3482, 2641, 1180, 3284, 2289, 3967, 2715,
+     354, 3159, 3609, 1587,  588,  862, 1459,  249, 3513, 1622,  380, 3469, 1391, 3956, 1738, 3095,
+    2456,  199, 3272, 1772, 3061, 3718,  718, 3330, 1799,   78, 3021, 1741, 2193, 1053, 3964, 1473,
+    2219, 1014, 2980,   33, 2318, 2015, 1552, 3659, 2162, 4022,  330, 2323, 3434, 1965, 1168, 3291,
+    2433,  923, 3353, 1416, 2969, 1698, 1266,  865,  368, 1844,  722, 2946, 1574,  595, 1019, 3416,
+    1798, 2474,  956, 2776, 2057, 2919, 2327, 3782, 2670,  963, 2965, 2418,  706, 2735,   65, 1258,
+    3486, 1601, 4018,  336, 2350, 1395,  189, 2925, 3817,  874, 1196, 3700,  267, 3170, 2574,  123,
+    3394,  625, 4041, 1351, 3321,  537, 2888,  831, 3221, 1659,  960, 2763,  183,  848, 2635,  343,
+    1814,  192, 2676,  697, 2356,  106, 3424, 2709, 3721, 1503, 3941,  215, 3583, 2515, 3016, 1419,
+     705, 3916,  421, 1268, 3835,   43, 3196, 1282,  573, 2195, 3911, 1165, 2029, 3164, 3626, 2286,
+     740, 2844, 1134, 2101,  824, 3519, 2507, 1537, 2075, 2718, 2352, 3362, 1603,  807, 2004, 3028,
+    1651, 2416, 1949, 2673, 1036, 3906, 2403, 1233,  101, 2567, 3568, 1330, 3151, 3981, 2159, 3617,
+    2985, 3487, 1617, 3736, 1990, 4056,  604, 1939, 3171, 1051, 2348, 2035,  895, 1883,   14, 3685,
+    2868, 1997, 3264, 2406, 3460, 1795, 1025, 2007, 3336, 1568,  125, 3503,  479, 1512,  908, 1787,
+    3854,  498, 2550, 3746, 3193, 1872,  491, 1075, 3598,  314, 1422,  613, 2811, 3870, 1184,  373,
+    3794,  917,  282, 3540, 1632,  202, 1827, 3062, 3848, 2003,  511, 2252, 1776,  460, 1521,  653,
+    1310, 2120,  481, 1130, 3131,  955, 2592, 1398,  149, 2854,  528, 3209, 2648, 4008, 1167, 2328,
+    1492, 1062,  190, 1620,  814,  500, 2626, 3996,  337, 2533, 2907, 1805, 2458, 3783, 2679,  298,
+    3035, 1967, 1520,   41, 1246, 2660, 4086, 3152,  745, 2984, 3952, 1958,   20, 2250, 3464, 2616,
+    1401, 2820, 3199,  611, 2240, 2780, 3383,  398, 1497,  815, 2993, 3725, 1017, 2825, 3772, 2552,
+     940, 3968, 2788, 2412,  228, 1765, 3371, 2229, 3907, 1746, 3681, 1326,  308, 1653, 3348,  484,
+    3589, 2631, 4087, 3042, 2115, 3770, 2933, 1445,  838, 3657, 1085,  661, 3252,  177, 1128, 2185,
+    3355,  969, 3535, 2951,  688, 1613,  164, 1944, 2384, 1649, 1111, 2499, 3102,  934, 1747,  487,
+    2171, 3639, 1845, 1305, 3714,  757, 1117, 2137, 3529, 2650, 1227,   50, 3311, 2320,  277, 3181,
+    1700,    5, 3304, 1461, 3641, 2948,  427, 1143,  704, 2493,  936, 3406, 2910,  748, 1961, 3060,
+     864, 2216,  592, 1366, 2495, 1187,  154, 2306, 3184, 1703, 2129, 4074, 1308, 1917, 2862, 3958,
+    1403,  387, 2363, 3929, 2080, 3427, 2851, 1270, 3553,  255, 3381,  775, 3678, 1499, 3192, 4034,
+     769, 1131,   51, 2585, 2992, 1575, 4071, 2442,  534, 1801, 3959, 2073, 1541,  766, 1275, 1991,
+    3526, 2280,  794, 1948,  578, 1307, 3839, 2733, 3491, 2006,  232, 1551, 2209, 3863, 2460,  150,
+    1643, 3316, 1854, 3633,  394, 3375, 1749, 3584,  563, 2740,   21, 3022, 2367, 3555,  509, 1650,
+     720, 2753, 1739,  233, 1112, 2477,  845, 3876,  585, 2108, 2831, 1876,  425, 2684,  212, 1962,
+    2959, 3347, 2319, 3915,  458, 1976,  109, 2853,  915, 3208,  364, 2891, 3601, 2588, 4088, 2964,
+     525, 1202, 2895, 3928, 2643, 2335, 1875,   68, 1449, 3031, 4095, 2634,  461, 1074, 1393, 3719,
+    3000, 1119,   40, 2766,  973, 3063,  776, 2022, 1335, 3816,  979, 1589,  369,  887, 3119, 2536,
+    3715, 3279, 1217, 2873, 3653,  423, 1782, 3093, 2579, 1453, 4001, 1226, 2312, 3845, 1052, 2489,
+    1482,  320, 1708,  867, 1214, 3512, 3166, 1306, 3696, 1610, 2414, 1056,  583, 1853,  181,  974,
+    2451, 3778, 1595,  385, 1049, 3575,  806, 3154, 2302,  589, 1037, 1735, 3550, 3224, 2789,  334,
+    3978, 2583, 2051, 3805, 1566, 2184, 4024, 2846,  378, 2434, 3367, 2689, 3787, 1832, 2247, 1101,
+      58, 1998, 2298,  770, 3187, 1364, 2255,  216, 1033, 3243,   84,  690, 2975, 1433, 3426,  638,
+    3802, 2668, 3561, 3069, 2196, 2519,  577, 1828, 2227,  169, 3840, 1412, 3391, 2791, 1577, 3679,
+    1914,  117, 3370, 2189, 3006,  260, 1544, 3415, 1175, 3739, 2866,   92, 2140,  643, 1865, 2264,
+];
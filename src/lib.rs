@@ -4,3 +4,5 @@ pub mod source;
 pub mod image;
 pub mod run_mode;
 pub mod app;
+pub mod control;
+pub mod config;
@@ -1,45 +1,63 @@
-use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use rabbitink::app::{App, AppOptions};
+use rabbitink::config::{self, ConfigWatcher, Settings};
+use rabbitink::control;
 use rabbitink::driver::it8915::IT8915;
-use rabbitink::imgproc::Rotation;
+use rabbitink::run_mode::RunMode;
 use rabbitink::source;
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    #[arg(long, short, default_value = "")]
-    device: String,
-
-    #[arg(long, short)]
-    source: Option<String>,
-
-    #[arg(long, default_value_t = 0)]
-    source_offx: i32,
-
-    #[arg(long, default_value_t = 0)]
-    source_offy: i32,
+    #[command(subcommand)]
+    command: Option<Command>,
 
+    // default: $XDG_CONFIG_HOME/rabbitink/config.toml, if exists
     #[arg(long)]
-    vcom: f32,
-
-    #[arg(long, short, default_value = "no-rotation")]
-    rotation: Rotation,
+    config: Option<std::path::PathBuf>,
 
-    #[arg(long, default_value_t = 1)]
-    driver_poll_ready_interval: u64,
+    #[arg(long)]
+    profile: Option<String>,
 
-    #[arg(long, default_value_t = 10)]
-    source_poll_interval: u64,
+    #[command(flatten)]
+    settings: Settings,
+}
 
-    #[arg(long, default_value = "mono_bayers4")]
-    run_mode: String,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a command to the running rabbitink via the control socket.
+    /// Commands: set-run-mode <mode>, refresh [x y w h], pause, resume,
+    /// set-rotation <rotation>, status, subscribe
+    Ctl {
+        #[arg(long)]
+        socket: Option<std::path::PathBuf>,
+
+        #[arg(required = true)]
+        command: Vec<String>,
+    },
+}
 
-    #[arg(long, default_value = "/tmp/rabbitink_run_mode.config")]
-    run_mode_config: std::path::PathBuf,
+fn run_ctl(socket: Option<std::path::PathBuf>, command: Vec<String>) -> anyhow::Result<()> {
+    let socket = socket.unwrap_or_else(control::default_socket_path);
+    let mut responses = control::send_command(&socket, &command.join(" "))?;
+    let response = responses
+        .next()
+        .ok_or_else(|| anyhow::format_err!("No response from {:?}", socket))??;
+    if response["ok"] != serde_json::Value::Bool(true) {
+        anyhow::bail!("{}", response["error"]);
+    }
+    println!("{}", response);
+    // the connection is kept open for subscriptions, print all events
+    if command.first().map(String::as_str) == Some("subscribe") {
+        for event in responses {
+            println!("{}", event?);
+        }
+    }
+    Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
@@ -48,49 +66,82 @@ fn main() -> anyhow::Result<()> {
         .init();
 
     let args = Args::parse();
+    if let Some(Command::Ctl { socket, command }) = args.command {
+        return run_ctl(socket, command);
+    }
 
-    let initial_run_mode = rabbitink::run_mode::RunMode::from_str(&args.run_mode)?;
-    let run_mode_config_path = args.run_mode_config;
-    std::fs::write(&run_mode_config_path, args.run_mode)?;
+    let config_path = args
+        .config
+        .or_else(|| config::default_config_path().filter(|x| x.exists()));
+    let settings = config::load_settings(config_path.as_deref(), args.profile.as_deref(), &args.settings)?;
+    let vcom = settings
+        .vcom
+        .ok_or_else(|| anyhow::format_err!("vcom is required (via --vcom or config file)"))?;
+    let rotation = settings.rotation();
+
+    let initial_run_mode = settings.run_mode()?;
+    let run_mode_config_path = settings.run_mode_config();
+    if let Some(path) = run_mode_config_path.as_ref() {
+        std::fs::write(path, initial_run_mode.to_string())?;
+    }
 
-    let mut dev = IT8915::open(&args.device)?;
-    dev.pmic_control(Some(args.vcom), Some(true))?;
+    let mut dev = IT8915::open(settings.device())?;
+    dev.pmic_control(Some(vcom), Some(true))?;
     dev.reset_display()?;
 
-    let source = source::create_source(
-        args.source.as_deref(),
-        (args.source_offx, args.source_offy).into(),
-        Some(args.rotation.rotated_size(dev.get_screen_size())),
-    )?;
+    let source_display = settings.source.clone();
+    let source_offset = settings.source_offset().into();
+    let source_factory: source::SourceFactory = Box::new(move |max_size| {
+        source::create_source(source_display.as_deref(), source_offset, Some(max_size))
+    });
 
     let reload_flag = Arc::new(AtomicBool::default());
     for s in [signal_hook::consts::SIGUSR1, signal_hook::consts::SIGHUP] {
         signal_hook::flag::register(s, reload_flag.clone())?;
     }
 
+    let control_server = match settings.control_socket() {
+        Some(path) => Some(control::ControlServer::bind(&path)?),
+        None => None,
+    };
+
+    let config_watcher = match config_path {
+        Some(path) => Some(ConfigWatcher::new(&path, args.profile.as_deref(), &args.settings, &settings)?),
+        None => None,
+    };
+
     let terminate_flag = Arc::new(AtomicBool::default());
     for s in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
         signal_hook::flag::register(s, terminate_flag.clone())?;
     }
 
+    let mut last_file_run_mode = Some(initial_run_mode);
     let mut app = App::new(
         dev,
-        source,
+        source_factory,
         AppOptions {
             reload_flag,
             terminate_flag,
+            run_mode: initial_run_mode,
+            // only report the run mode in file when it's changed,
+            // so that the run mode set by other ways (control socket, config file) is kept
             get_run_mode_callback: Box::new(move || {
-                rabbitink::run_mode::RunMode::read_from_file(&run_mode_config_path)
-                    .unwrap_or(initial_run_mode)
+                let run_mode = RunMode::read_from_file(run_mode_config_path.as_ref()?).ok()?;
+                if last_file_run_mode == Some(run_mode) {
+                    return None;
+                }
+                last_file_run_mode = Some(run_mode);
+                Some(run_mode)
             }),
-            driver_poll_ready_interval: std::time::Duration::from_millis(
-                args.driver_poll_ready_interval,
-            ),
-            source_poll_interval: std::time::Duration::from_millis(
-                args.source_poll_interval,
-            ),
-            rotation: args.rotation,
+            driver_poll_ready_interval: settings.driver_poll_ready_interval(),
+            source_poll_interval: settings.source_poll_interval(),
+            rotation,
+            control_server,
+            config_watcher,
+            policy: settings.policy.clone(),
+            imgproc_backend: settings.imgproc(),
+            latency_model_path: settings.latency_model(),
         },
-    );
+    )?;
     app.run()
 }
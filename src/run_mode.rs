@@ -1,13 +1,23 @@
 use std::str::FromStr;
 
 use super::imgproc::DitheringMethod;
+use super::imgproc::dithering::ErrorDiffusion;
 use super::driver::it8915::{DisplayMode, MemMode};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RunMode {
     Mono(DitheringMethod),
     MonoForce8bpp(DitheringMethod),
+    Mono4, // 4 level gray (DU4), error diffusion
     Gray,
+    // bayers 4x4 ordered dithering, to 4 and 16 levels respectively. much faster (in the GPU if available),
+    // but looks worse than error diffusion
+    Mono4Ordered,
+    GrayOrdered,
+    // error diffusion in the CPU, to 2, 4 and 16 levels respectively
+    MonoDiffusion(ErrorDiffusion),
+    Mono4Diffusion(ErrorDiffusion),
+    GrayDiffusion(ErrorDiffusion),
 }
 
 impl Default for RunMode {
@@ -21,47 +31,116 @@ impl FromStr for RunMode {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
-            "mono_bayers4" => RunMode::Mono(DitheringMethod::Bayers4),
-            "mono_bayers2" => RunMode::Mono(DitheringMethod::Bayers2),
-            "mono_naive" => RunMode::Mono(DitheringMethod::NoDithering),
-            "mono_8bpp_bayers4" => RunMode::MonoForce8bpp(DitheringMethod::Bayers4),
-            "mono_8bpp_bayers2" => RunMode::MonoForce8bpp(DitheringMethod::Bayers2),
-            "mono_8bpp_naive" => RunMode::MonoForce8bpp(DitheringMethod::NoDithering),
+            "mono4" => RunMode::Mono4,
             "gray" => RunMode::Gray,
-            _ => anyhow::bail!("Unsupported request: {}", s),
+            "mono4_bayers4" => RunMode::Mono4Ordered,
+            "gray_bayers4" => RunMode::GrayOrdered,
+            _ => {
+                if let Some(dithering) = s.strip_prefix("mono_8bpp_") {
+                    RunMode::MonoForce8bpp(dithering.parse()?)
+                } else if let Some(dithering) = s.strip_prefix("mono_") {
+                    match dithering.parse() {
+                        Ok(error_diffusion) => RunMode::MonoDiffusion(error_diffusion),
+                        Err(_) => RunMode::Mono(dithering.parse()?),
+                    }
+                } else if let Some(error_diffusion) = s.strip_prefix("mono4_") {
+                    RunMode::Mono4Diffusion(error_diffusion.parse()?)
+                } else if let Some(error_diffusion) = s.strip_prefix("gray_") {
+                    RunMode::GrayDiffusion(error_diffusion.parse()?)
+                } else {
+                    anyhow::bail!("Unsupported request: {}", s)
+                }
+            }
         })
     }
 }
 
+impl std::fmt::Display for RunMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mono(v) => write!(f, "mono_{}", v),
+            Self::MonoForce8bpp(v) => write!(f, "mono_8bpp_{}", v),
+            Self::Mono4 => write!(f, "mono4"),
+            Self::Gray => write!(f, "gray"),
+            Self::Mono4Ordered => write!(f, "mono4_bayers4"),
+            Self::GrayOrdered => write!(f, "gray_bayers4"),
+            Self::MonoDiffusion(v) => write!(f, "mono_{}", v),
+            Self::Mono4Diffusion(v) => write!(f, "mono4_{}", v),
+            Self::GrayDiffusion(v) => write!(f, "gray_{}", v),
+        }
+    }
+}
+
 impl RunMode {
     pub fn display_mode_fast(&self) -> DisplayMode {
         match self {
-            &Self::Mono(_) | &Self::MonoForce8bpp(_) => DisplayMode::A2,
-            &Self::Gray => DisplayMode::GL16,
+            &Self::Mono(_) | &Self::MonoForce8bpp(_) | &Self::MonoDiffusion(_) => DisplayMode::A2,
+            &Self::Mono4 | &Self::Mono4Ordered | &Self::Mono4Diffusion(_) => DisplayMode::DU4,
+            &Self::Gray | &Self::GrayOrdered | &Self::GrayDiffusion(_) => DisplayMode::GL16,
         }
     }
     pub fn display_mode_slow(&self) -> DisplayMode {
         match self {
-            &Self::Mono(_) | &Self::MonoForce8bpp(_) => DisplayMode::DU,
-            &Self::Gray => DisplayMode::GL16,
+            &Self::Mono(_) | &Self::MonoForce8bpp(_) | &Self::MonoDiffusion(_) => DisplayMode::DU,
+            &Self::Mono4 | &Self::Mono4Ordered | &Self::Mono4Diffusion(_) => DisplayMode::DU4,
+            &Self::Gray | &Self::GrayOrdered | &Self::GrayDiffusion(_) => DisplayMode::GL16,
+        }
+    }
+    // the first update after switching to this run mode, from whatever on the screen
+    pub fn display_mode_transition(&self) -> DisplayMode {
+        match self {
+            &Self::Mono(_) | &Self::MonoForce8bpp(_) | &Self::MonoDiffusion(_) => DisplayMode::DU,
+            &Self::Mono4 | &Self::Mono4Ordered | &Self::Mono4Diffusion(_) => DisplayMode::DU4,
+            &Self::Gray | &Self::GrayOrdered | &Self::GrayDiffusion(_) => DisplayMode::GL16,
         }
     }
     pub fn mem_mode(&self) -> MemMode {
         match self {
-            &Self::Mono(_) => MemMode::Mem1bpp,
+            &Self::Mono(_) | &Self::MonoDiffusion(_) => MemMode::Mem1bpp,
             &Self::MonoForce8bpp(_) => MemMode::Mem8bpp,
-            &Self::Gray => MemMode::Mem8bpp,
+            // the controller does not have a 2bpp memory mode
+            &Self::Mono4 | &Self::Mono4Ordered | &Self::Mono4Diffusion(_) => MemMode::Mem8bpp,
+            &Self::Gray | &Self::GrayOrdered | &Self::GrayDiffusion(_) => MemMode::Mem8bpp,
         }
     }
     pub fn dithering_method(&self) -> Option<DitheringMethod> {
         match self {
             &Self::Mono(v) | &Self::MonoForce8bpp(v) => Some(v),
-            &Self::Gray => None,
+            _ => None,
         }
     }
+    // 16 level gray modes, which get the gray specific display policies
+    pub fn is_gray(&self) -> bool {
+        matches!(self, Self::Gray | Self::GrayOrdered | Self::GrayDiffusion(_))
+    }
 
     pub fn read_from_file(path: &std::path::Path) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)?;
         Self::from_str(content.trim_end())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for s in ["mono_bayers4", "mono_8bpp_naive", "mono_bayers8", "mono_bluenoise", "mono_hybrid", "mono4", "gray",
+                  "mono4_bayers4", "gray_bayers4", "mono_fs", "mono_atkinson_serpentine", "mono4_jjn", "gray_stucki",
+                  "gray_sierra_serpentine"] {
+            assert_eq!(RunMode::from_str(s).unwrap().to_string(), s);
+        }
+        assert!(RunMode::from_str("mono_unknown").is_err());
+        assert!(RunMode::from_str("gray_bayers8").is_err());
+        assert!(RunMode::from_str("mono_map:/nonexistent").is_err());
+
+        let path = std::env::temp_dir().join(format!("rabbitink-test-run-mode-map-{}.txt", std::process::id()));
+        std::fs::write(&path, "0 2\n3 1\n").unwrap();
+        let s = format!("mono_map:{}", path.display());
+        let run_mode = RunMode::from_str(&s).unwrap();
+        assert_eq!(run_mode.to_string(), s);
+        assert_eq!(RunMode::from_str(&s).unwrap(), run_mode);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -12,8 +12,15 @@ pub trait Source {
     // return BGRA
     fn get_frame(&mut self) -> anyhow::Result<Box<dyn ConstImage + '_>>;
     fn frame_size(&self) -> Size;
+    // pointer position (relative to the frame) when the last frame is captured, if known and inside the frame
+    fn pointer_position(&self) -> Option<Point> {
+        None
+    }
 }
 
+// creates the source in the capture thread, see `app::capture`, with the max frame size in source coordinate.
+// Called again when the rotation swaps the width and height of the screen.
+pub type SourceFactory = Box<dyn FnMut(Size) -> anyhow::Result<Box<dyn Source>> + Send>;
 
 #[cfg(target_os = "linux")]
 pub fn create_source(
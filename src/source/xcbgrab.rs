@@ -57,6 +57,7 @@ pub struct XcbGrabSource {
 
     top_left: Point,
     size: Size,
+    pointer: Option<Point>,
 
     screensave_img: ImageBuffer,
 }
@@ -139,6 +140,7 @@ impl XcbGrabSource {
             shmem,
             top_left,
             size,
+            pointer: None,
             screensave_img: make_screensave_img(size),
         })
     }
@@ -198,7 +200,12 @@ impl Source for XcbGrabSource {
         self.size
     }
 
+    fn pointer_position(&self) -> Option<Point> {
+        self.pointer
+    }
+
     fn get_frame(&mut self) -> anyhow::Result<Box<dyn ConstImage + '_>> {
+        self.pointer = None;
         let screensaver_query_cookie = self.conn.send_request(&xcb::screensaver::QueryInfo {
             drawable: xcb::x::Drawable::Window(self.window),
         });
@@ -229,6 +236,11 @@ impl Source for XcbGrabSource {
         let cursor = self.conn.wait_for_reply(cursor_cookie)?;
         trace!("got cursor: {:?}", cursor);
         if cursor.same_screen() {
+            let x = cursor.root_x() as i32 - self.top_left.x;
+            let y = cursor.root_y() as i32 - self.top_left.y;
+            if x >= 0 && x < self.size.width && y >= 0 && y < self.size.height {
+                self.pointer = Some((x, y).into());
+            }
             let cursor_image_cookie = self.conn.send_request(&xcb::xfixes::GetCursorImage {});
             let cursor_image = self.conn.wait_for_reply(cursor_image_cookie)?;
             let mut image = ImageView::new(